@@ -0,0 +1,40 @@
+//! Compares starting each event's attribute list empty with starting it at a fixed capacity, as
+//! [`Options::set_attr_vec_capacity`](tracing_modality::Options::set_attr_vec_capacity) does, on a
+//! mix of event sizes like the ingest thread packs.
+//!
+//! Unlike the layer benchmarks these don't need a connection to modality.
+
+use auxon_sdk::api::AttrVal;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+/// How many attributes each event in the mix is packed with: the common attributes (kind, name,
+/// severity, source, tick, timestamp, ...) plus zero, a few, or many fields.
+const EVENT_MIX: &[usize] = &[6, 6, 8, 8, 8, 10, 12, 20];
+
+fn pack(capacity: usize) -> usize {
+    EVENT_MIX
+        .iter()
+        .map(|&attrs| {
+            let mut packed_attrs = Vec::with_capacity(capacity);
+            for key in 0..attrs as u32 {
+                packed_attrs.push((key, AttrVal::from(i64::from(key))));
+            }
+            packed_attrs.len()
+        })
+        .sum()
+}
+
+fn attr_vec_benches(c: &mut Criterion) {
+    let mut group = c.benchmark_group("attr_vec");
+    for capacity in [0, 8, 16] {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(capacity),
+            &capacity,
+            |b, &capacity| b.iter(|| pack(capacity)),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, attr_vec_benches);
+criterion_main!(benches);
@@ -0,0 +1,58 @@
+//! Compares the default hasher with the one the `fast-hash` feature uses, on the lookups the
+//! ingest thread does for every event: interned attribute keys by name, and span names by span ID.
+//!
+//! Unlike the layer benchmarks these don't need a connection to modality.
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use std::{collections::HashMap, hash::BuildHasher};
+
+/// The attribute keys a typical event with a few fields is packed with.
+const EVENT_KEYS: &[&str] = &[
+    "event.internal.rs.kind",
+    "event.name",
+    "event.severity",
+    "event.source.module",
+    "event.source.file",
+    "event.source.line",
+    "event.internal.rs.tick",
+    "event.timestamp",
+    "event.internal.rs.timeline_seq",
+    "event.message",
+    "event.request_id",
+    "event.status",
+];
+
+fn lookups<S: BuildHasher + Default>(c: &mut Criterion, hasher: &str) {
+    let event_keys: HashMap<String, u32, S> = EVENT_KEYS
+        .iter()
+        .enumerate()
+        .map(|(i, k)| (k.to_string(), i as u32))
+        .collect();
+    let span_names: HashMap<u64, String, S> =
+        (1..=256u64).map(|id| (id, format!("span {id}"))).collect();
+
+    let mut group = c.benchmark_group(hasher);
+
+    // keys arrive as owned strings, like the ones built while packing an event
+    group.bench_function("event_keys", |b| {
+        b.iter_batched(
+            || EVENT_KEYS.iter().map(|k| k.to_string()).collect::<Vec<_>>(),
+            |keys| keys.iter().filter_map(|k| event_keys.get(k)).sum::<u32>(),
+            BatchSize::SmallInput,
+        )
+    });
+
+    group.bench_function("span_names", |b| {
+        b.iter(|| (1..=256u64).filter_map(|id| span_names.get(&id)).count())
+    });
+
+    group.finish();
+}
+
+fn hash_benches(c: &mut Criterion) {
+    lookups::<std::collections::hash_map::RandomState>(c, "siphash");
+    lookups::<rustc_hash::FxBuildHasher>(c, "fxhash");
+}
+
+criterion_group!(benches, hash_benches);
+criterion_main!(benches);
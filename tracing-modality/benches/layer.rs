@@ -0,0 +1,55 @@
+//! Benchmarks for the producing side of the layer: `on_event`/`on_enter`/`on_exit` through to the
+//! send onto the ingest channel.
+//!
+//! Like the examples, these need a running modalityd (or reflector) to connect to. If a
+//! connection can't be made the benchmarks are skipped.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use tracing::{event, span, Level};
+use tracing_modality::blocking::ModalityLayer;
+
+fn layer_benches(c: &mut Criterion) {
+    let (layer, ingest_handle) = match ModalityLayer::init() {
+        Ok(init) => init,
+        Err(e) => {
+            eprintln!("skipping layer benchmarks, could not connect to modality: {e}");
+            return;
+        }
+    };
+
+    tracing::subscriber::with_default(layer.into_subscriber(), || {
+        let mut group = c.benchmark_group("layer");
+
+        group.bench_function("event_no_fields", |b| {
+            b.iter(|| event!(target: "bench", Level::INFO, {}))
+        });
+
+        group.bench_function("event_5_fields", |b| {
+            b.iter(|| {
+                event!(
+                    Level::INFO,
+                    a = 1u64,
+                    b = -2i64,
+                    c = 3.0f64,
+                    d = true,
+                    e = "five",
+                    "an event with some fields"
+                )
+            })
+        });
+
+        let span = span!(Level::INFO, "bench span");
+        group.bench_function("span_enter_exit", |b| {
+            b.iter(|| {
+                let _entered = span.enter();
+            })
+        });
+
+        group.finish();
+    });
+
+    ingest_handle.finish();
+}
+
+criterion_group!(benches, layer_benches);
+criterion_main!(benches);
@@ -440,7 +440,7 @@ fn send_heartbeat(
     let nonce: i64 = rng.gen();
     tracing::info!(
         destination = Component::Monitor.name(),
-        nonce,
+        interaction.nonce = nonce,
         "Sending heartbeat message"
     );
     if let Err(_e) = monitor_tx.send(HeartbeatMessage {
@@ -58,7 +58,7 @@ fn main() {
                                 });
                                 //let nonce = job.nonce + THREADS as u32;
                                 let nonce = job.nonce;
-                                info!(nonce = nonce, source = ?timeline_id.get_raw(), result, "sending");
+                                info!(interaction.nonce = nonce, source = ?timeline_id.get_raw(), result, "sending");
                                 term_tx
                                     .send(Message::Data(Job {
                                         nonce,
@@ -82,7 +82,7 @@ fn main() {
         let start = rng.gen_range(0..=100) + 2;
         let target = (start as usize) as usize % THREADS;
         info!(
-            nonce = i,
+            interaction.nonce = i,
             worker = target,
             input = start,
             source = ?timeline_id.get_raw(),
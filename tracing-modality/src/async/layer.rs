@@ -1,24 +1,33 @@
-use crate::common::options::Options;
+use crate::common::options::{Options, OptionsSnapshot};
 use crate::InitError;
 
-use crate::common::layer::{LayerHandler, LocalMetadata};
+use crate::common::layer::{LayerHandler, LocalMetadata, MaxLevelFilter};
 use crate::ingest;
-use crate::ingest::{ModalityIngest, ModalityIngestTaskHandle, WrappedMessage};
+use crate::ingest::{IngestSender, ModalityIngest, ModalityIngestTaskHandle, WrappedMessage};
 
 use anyhow::Context as _;
 use once_cell::sync::Lazy;
-use std::{cell::Cell, thread::LocalKey, thread_local};
-use tokio::sync::mpsc::{self, UnboundedSender};
+use std::{
+    cell::Cell,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::LocalKey,
+    thread_local,
+};
+use tokio::sync::mpsc::error::TrySendError;
 use tracing_core::Subscriber;
-use tracing_subscriber::{layer::SubscriberExt, Registry};
-use uuid::Uuid;
+use tracing_subscriber::{layer::SubscriberExt, Layer, Registry};
 
 /// A `tracing` `Layer` that can be used to record trace events and stream them to modality in real
 /// time.
 ///
 /// Can be transformed into a `Subscriber` with [`ModalityLayer::into_subscriber()`].
 pub struct ModalityLayer {
-    sender: UnboundedSender<WrappedMessage>,
+    sender: IngestSender,
+    options: Options,
+    shutting_down: Arc<AtomicBool>,
 }
 
 impl ModalityLayer {
@@ -40,30 +49,79 @@ impl ModalityLayer {
     pub async fn init_with_options(
         mut opts: Options,
     ) -> Result<(Self, ModalityIngestTaskHandle), InitError> {
-        let run_id = Uuid::new_v4();
-        opts.add_metadata("run_id", run_id.to_string());
+        opts.add_init_metadata();
+        let options = opts.clone();
+
+        let mut mirrors = Vec::new();
+        for addr in &opts.additional_server_addrs {
+            let mirror = ModalityIngest::async_connect(opts.for_mirror(*addr))
+                .await
+                .with_context(|| format!("connect to modality at {addr}"))?;
+            mirrors.push(mirror.spawn_task(Vec::new()).await);
+        }
 
         let ingest = ModalityIngest::async_connect(opts)
             .await
             .context("connect to modality")?;
-        let ingest_handle = ingest.spawn_task().await;
+        let ingest_handle = ingest.spawn_task(mirrors).await;
         let sender = ingest_handle.ingest_sender.clone();
+        let shutting_down = ingest_handle.shutting_down.clone();
+
+        Ok((
+            ModalityLayer {
+                sender,
+                options,
+                shutting_down,
+            },
+            ingest_handle,
+        ))
+    }
 
-        Ok((ModalityLayer { sender }, ingest_handle))
+    /// A read-only view of the options this layer was initialized with.
+    pub fn effective_options(&self) -> OptionsSnapshot {
+        self.options.snapshot()
     }
 
     /// Convert this `Layer` into a `Subscriber`by by layering it on a new instace of `tracing`'s
     /// `Registry`.
     pub fn into_subscriber(self) -> impl Subscriber {
-        Registry::default().with(self)
+        let filter = self.max_level_filter();
+        Registry::default().with(self.with_filter(filter))
+    }
+
+    /// A filter for the [max level](Options::set_max_level), to apply with
+    /// [`with_filter`](Layer::with_filter) when adding this layer to a subscriber alongside other
+    /// layers. The max level only takes effect through this filter, which
+    /// [`into_subscriber`](Self::into_subscriber) applies.
+    ///
+    /// ```rust,ignore
+    /// let filter = layer.max_level_filter();
+    /// let subscriber = Registry::default()
+    ///     .with(layer.with_filter(filter))
+    ///     .with(tracing_subscriber::fmt::layer());
+    /// ```
+    pub fn max_level_filter(&self) -> MaxLevelFilter {
+        MaxLevelFilter(self.options.max_level.clone())
     }
 }
 
 impl LayerHandler for ModalityLayer {
-    fn send(&self, msg: WrappedMessage) -> Result<(), mpsc::error::SendError<WrappedMessage>> {
+    fn send(&self, msg: WrappedMessage) -> Result<(), TrySendError<()>> {
         self.sender.send(msg)
     }
 
+    fn sender(&self) -> &IngestSender {
+        &self.sender
+    }
+
+    fn options(&self) -> &Options {
+        &self.options
+    }
+
+    fn shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::Relaxed)
+    }
+
     fn local_metadata(&self) -> &'static LocalKey<Lazy<LocalMetadata>> {
         &Self::LOCAL_METADATA
     }
@@ -1,11 +1,22 @@
 mod layer;
 
-pub use crate::ingest::ModalityIngestThreadHandle;
-pub use crate::{timeline_id, InitError, Options, TimelineId};
+pub use crate::ingest::{IngestExitReason, ModalityIngestThreadHandle, ShutdownReport};
+#[cfg(feature = "config")]
+pub use crate::ConfigError;
+pub use crate::{
+    current_span_context, import_span, last_event_id, record_panic, set_timeline_id, timed,
+    timeline_id, timeline_scope, InitError, Options, OptionsSnapshot, SpanContext, TimelineId,
+    TimelineScope, UserTimelineInfo,
+};
 pub use layer::ModalityLayer;
 
+use crate::common::layer::LayerHandler;
 use anyhow::Context as _;
-use tracing::Dispatch;
+use auxon_sdk::api::AttrVal;
+use std::sync::atomic::Ordering;
+use tracing::{dispatcher::SetGlobalDefaultError, Dispatch};
+use tracing_core::LevelFilter;
+use uuid::Uuid;
 
 /// A global tracer instance for [tracing.rs](https://tracing.rs/) that sends traces via a network
 /// socket to [Modality](https://auxon.io/).
@@ -15,7 +26,9 @@ use tracing::Dispatch;
 /// initialized inside a tokio runtime. Both versions support tracing from within and outside of a
 /// tokio runtime.
 pub struct TracingModality {
-    ingest_handle: ModalityIngestThreadHandle,
+    /// `None` when tracing to the console because modality couldn't be reached.
+    ingest_handle: Option<ModalityIngestThreadHandle>,
+    options: Options,
 }
 
 impl TracingModality {
@@ -26,17 +39,177 @@ impl TracingModality {
 
     /// Initialize with the provided options and set as the global default tracer.
     pub fn init_with_options(opts: Options) -> Result<Self, InitError> {
-        let (layer, ingest_handle) =
-            ModalityLayer::init_with_options(opts).context("initialize ModalityLayer")?;
+        let fallback_options = opts.console_fallback.then(|| opts.clone());
+        let initialized =
+            ModalityLayer::init_with_options(opts).context("initialize ModalityLayer");
+        let (layer, ingest_handle) = match (initialized, fallback_options) {
+            (Ok(initialized), _) => initialized,
+            (Err(e), Some(options)) => return Ok(Self::console_fallback(options, &e).unwrap()),
+            (Err(e), None) => return Err(e.into()),
+        };
 
+        let options = layer.options().clone();
         let disp = Dispatch::new(layer.into_subscriber());
-        tracing::dispatcher::set_global_default(disp).unwrap();
+        crate::install_global_default(disp, &options, || {
+            crate::signal::install_signal_thread(
+                ingest_handle.flush_requests(),
+                ingest_handle.shutting_down.clone(),
+            )
+        })
+        .unwrap();
 
-        Ok(Self { ingest_handle })
+        Ok(Self {
+            ingest_handle: Some(ingest_handle),
+            options,
+        })
+    }
+
+    /// Initialize with the provided options and set as the global default tracer, unless a global
+    /// default tracer has already been set, in which case `Ok(None)` is returned.
+    ///
+    /// This is intended for libraries that want to ensure tracing is set up without fighting the
+    /// application over the global default tracer.
+    pub fn init_if_not_already(opts: Options) -> Result<Option<Self>, InitError> {
+        if tracing_core::dispatcher::has_been_set() {
+            return Ok(None);
+        }
+        let fallback_options = opts.console_fallback.then(|| opts.clone());
+
+        let initialized =
+            ModalityLayer::init_with_options(opts).context("initialize ModalityLayer");
+        let (layer, ingest_handle) = match (initialized, fallback_options) {
+            (Ok(initialized), _) => initialized,
+            (Err(e), Some(options)) => return Ok(Self::console_fallback(options, &e).ok()),
+            (Err(e), None) => return Err(e.into()),
+        };
+
+        let options = layer.options().clone();
+        let disp = Dispatch::new(layer.into_subscriber());
+        let installed = crate::install_global_default(disp, &options, || {
+            crate::signal::install_signal_thread(
+                ingest_handle.flush_requests(),
+                ingest_handle.shutting_down.clone(),
+            )
+        });
+        if installed.is_err() {
+            // lost a race with another initializer
+            ingest_handle.finish();
+            return Ok(None);
+        }
+
+        Ok(Some(Self {
+            ingest_handle: Some(ingest_handle),
+            options,
+        }))
+    }
+
+    /// Trace to stderr instead of modality, after initializing modality failed with `error`.
+    fn console_fallback(
+        options: Options,
+        error: &anyhow::Error,
+    ) -> Result<Self, SetGlobalDefaultError> {
+        crate::fallback::install_console_fallback(&options, error)?;
+        Ok(Self {
+            ingest_handle: None,
+            options,
+        })
+    }
+
+    /// A read-only view of the options this tracer was initialized with, e.g. for a diagnostics
+    /// endpoint.
+    pub fn effective_options(&self) -> OptionsSnapshot {
+        self.options.snapshot()
+    }
+
+    /// Returns `true` if a `TracingModality` has been installed as the global default tracer.
+    pub fn is_initialized() -> bool {
+        crate::INITIALIZED.load(Ordering::SeqCst)
+    }
+
+    /// Add metadata to the root timeline after initialization, e.g. for facts that are only
+    /// learned once the program is running.
+    pub fn add_root_metadata<K: AsRef<str>, V: Into<AttrVal>>(&self, key: K, value: V) {
+        if let Some(ingest_handle) = &self.ingest_handle {
+            ingest_handle.add_root_metadata(key, value);
+        }
+    }
+
+    /// Start a new run: flush what has been recorded so far and switch to a fresh `run_id`,
+    /// returning it. See [`ModalityIngestThreadHandle::rotate_run_id`] for the effect on timelines.
+    pub fn rotate_run_id(&self) -> Uuid {
+        match &self.ingest_handle {
+            Some(ingest_handle) => ingest_handle.rotate_run_id(),
+            None => self.options.run_id.rotate(),
+        }
+    }
+
+    /// How many distinct timelines have been recorded so far. See
+    /// [`ModalityIngestThreadHandle::timeline_count`].
+    pub fn timeline_count(&self) -> usize {
+        self.ingest_handle
+            .as_ref()
+            .map_or(0, |ingest_handle| ingest_handle.timeline_count())
+    }
+
+    /// How many messages have been dropped so far because the ingest's queue was full. See
+    /// [`ModalityIngestThreadHandle::dropped_messages`].
+    pub fn dropped_messages(&self) -> u64 {
+        self.ingest_handle
+            .as_ref()
+            .map_or(0, |ingest_handle| ingest_handle.dropped_messages())
+    }
+
+    /// Change the [max level](Options::set_max_level) events and spans are recorded at, e.g. to
+    /// record `debug` events for a while during an incident.
+    ///
+    /// Callsites `tracing` has already checked are re-checked, so this takes effect for every
+    /// callsite, not only new ones.
+    pub fn set_level_filter<L: Into<LevelFilter>>(&self, max_level: L) {
+        self.options.max_level.set(max_level.into());
+    }
+
+    /// Every attribute key declared to modality so far, with its interned ID. See
+    /// [`ModalityIngestThreadHandle::export_key_dictionary`].
+    pub fn export_key_dictionary(&self) -> Vec<(String, u64)> {
+        self.ingest_handle
+            .as_ref()
+            .map(|ingest_handle| ingest_handle.export_key_dictionary())
+            .unwrap_or_default()
+    }
+
+    /// The ID of the timeline `user_id` maps to in the current run. See
+    /// [`ModalityIngestThreadHandle::timeline_id_for`].
+    pub fn timeline_id_for(&self, user_id: u64) -> TimelineId {
+        match &self.ingest_handle {
+            Some(ingest_handle) => ingest_handle.timeline_id_for(user_id),
+            None => crate::timeline::derive_timeline_id(&self.options.run_id.get(), user_id),
+        }
+    }
+
+    /// Close the timeline with `user_id` in the current run. See
+    /// [`ModalityIngestThreadHandle::close_timeline`].
+    pub fn close_timeline(&self, user_id: u64) {
+        if let Some(ingest_handle) = &self.ingest_handle {
+            ingest_handle.close_timeline(user_id)
+        }
+    }
+
+    /// Send the events held by the [flight recorder](Options::set_flight_recorder) to modality
+    /// now. See [`ModalityIngestThreadHandle::dump_flight_recorder`].
+    pub fn dump_flight_recorder(&self) {
+        if let Some(ingest_handle) = &self.ingest_handle {
+            ingest_handle.dump_flight_recorder()
+        }
     }
 
     /// Stop accepting new trace events, flush all existing events, and stop ingest thread.
-    pub fn finish(self) {
-        self.ingest_handle.finish();
+    ///
+    /// Returns a [`ShutdownReport`] describing how backed up the queue was and whether the final
+    /// flush succeeded.
+    pub fn finish(self) -> ShutdownReport {
+        match self.ingest_handle {
+            Some(ingest_handle) => ingest_handle.finish(),
+            None => ShutdownReport::default(),
+        }
     }
 }
@@ -0,0 +1,339 @@
+//! Attribute handlers control how the value of a specific field is turned into modality event
+//! attributes.
+//!
+//! Fields without a handler are sent as a single `event.<field>` attribute. Handlers are
+//! registered with [`Options::add_attr_handler`](crate::Options::add_attr_handler), on top of the
+//! [`default_handlers`].
+//!
+//! A handler can be limited to some timelines with [`AttributeHandler::for_timelines`], e.g. when
+//! a `code` field means an HTTP status on the `network` timeline but a SQL error on the `db`
+//! timeline. For each field a matching timeline-scoped handler takes precedence over a handler for
+//! all timelines, which is the fallback.
+//!
+//! Each field goes through these steps in order, and a field dropped by one never reaches the
+//! next:
+//!
+//! 1. The [field allowlist](crate::Options::set_field_allowlist) drops fields that aren't listed.
+//! 2. A [redacted](crate::Options::add_redaction) field is sent as `"<redacted>"` under its own
+//!    key, and its handler isn't run.
+//! 3. The field's handler turns its value into attributes, or without one the value is sent as
+//!    `event.<field>`.
+//! 4. Each resulting key is checked against the
+//!    [approved keys](crate::Options::set_approved_keys), as are the `<field>.__type` and
+//!    `<field>.unit` keys added alongside a field.
+//!
+//! Modality attributes have no array type, so a collection logged with `?` is recorded as its
+//! `Debug` string. Use [`as_indexed`] to record the elements of such a field as indexed attributes
+//! instead, `event.<field>.0` through `event.<field>.N`.
+
+use crate::ingest::{coerce_timestamp, tracing_value_to_attr_val};
+use crate::layer::TracingValue;
+use crate::UserTimelineInfo;
+use auxon_sdk::api::{AttrVal, BigInt, Uuid};
+use std::{str::FromStr, sync::Arc};
+
+pub(crate) type HandlerFn = dyn Fn(TracingValue) -> Vec<(String, AttrVal)> + Send + Sync;
+pub(crate) type TimelinePredicate = dyn Fn(&UserTimelineInfo) -> bool + Send + Sync;
+
+/// Converts the value of the field named `key` into zero or more attributes.
+///
+/// The handler returns attribute keys without the `event.` prefix, e.g. `"foo"` is sent as
+/// `event.foo`. Keys that already start with `event.` are sent as is, they aren't prefixed again.
+#[derive(Clone)]
+pub struct AttributeHandler {
+    pub(crate) key: String,
+    pub(crate) handler: Arc<HandlerFn>,
+    pub(crate) timelines: Option<Arc<TimelinePredicate>>,
+}
+
+impl AttributeHandler {
+    /// Handle the field named `key` with `handler`.
+    pub fn new<K, F>(key: K, handler: F) -> Self
+    where
+        K: Into<String>,
+        F: Fn(TracingValue) -> Vec<(String, AttrVal)> + Send + Sync + 'static,
+    {
+        AttributeHandler {
+            key: key.into(),
+            handler: Arc::new(handler),
+            timelines: None,
+        }
+    }
+
+    /// Only use this handler for events on timelines `predicate` accepts, see the
+    /// [module docs](self) for how it combines with other handlers for the same field.
+    ///
+    /// Timelines are only known by their [`UserTimelineInfo`] when they come from a
+    /// [timeline identifier](crate::Options::set_timeline_identifier) or a
+    /// [`timeline_scope`](crate::timeline_scope), so scoped handlers never apply to the default
+    /// per-thread timelines.
+    pub fn for_timelines<F>(mut self, predicate: F) -> Self
+    where
+        F: Fn(&UserTimelineInfo) -> bool + Send + Sync + 'static,
+    {
+        self.timelines = Some(Arc::new(predicate));
+        self
+    }
+
+    /// The name of the field this handler is for.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+}
+
+/// The handlers every [`Options`](crate::Options) starts with.
+pub fn default_handlers() -> Vec<AttributeHandler> {
+    vec![remote_timeline_id(), remote_timestamp()]
+}
+
+/// Send `interaction.remote_timeline_id` as a timeline ID when it's a UUID string.
+pub fn remote_timeline_id() -> AttributeHandler {
+    let key = "interaction.remote_timeline_id";
+    AttributeHandler::new(key, move |value| {
+        let attrval = match tracing_value_to_attr_val(value) {
+            AttrVal::String(string) => match Uuid::from_str(&string) {
+                Ok(uuid) => AttrVal::TimelineId(Box::new(uuid.into())),
+                Err(_) => AttrVal::String(string),
+            },
+            attrval => attrval,
+        };
+        vec![(key.to_string(), attrval)]
+    })
+}
+
+/// Send `interaction.nonce`, the nonce a sending event tags an interaction with, as an integer.
+///
+/// Nonces only match if both sides record them with the same type, so integers are recorded as
+/// integers whatever their width or signedness, and strings holding an integer are parsed. Other
+/// values are recorded as usual.
+///
+/// This isn't one of the [`default_handlers`], since it changes the recorded type of existing
+/// nonces. Register it, and [`remote_nonce`], in every process taking part in the interactions.
+pub fn nonce() -> AttributeHandler {
+    let key = "interaction.nonce";
+    AttributeHandler::new(key, move |value| {
+        vec![(key.to_string(), nonce_attr_val(value))]
+    })
+}
+
+/// Send `interaction.remote_nonce`, the nonce of the sending event a receiving event interacts
+/// with, as an integer, coerced like [`nonce`].
+pub fn remote_nonce() -> AttributeHandler {
+    let key = "interaction.remote_nonce";
+    AttributeHandler::new(key, move |value| {
+        vec![(key.to_string(), nonce_attr_val(value))]
+    })
+}
+
+fn nonce_attr_val(value: TracingValue) -> AttrVal {
+    match value {
+        TracingValue::U64(n) => BigInt::new_attr_val(n.into()),
+        TracingValue::String(s) => match s.parse::<i128>() {
+            Ok(n) => BigInt::new_attr_val(n),
+            Err(_) => s.into(),
+        },
+        value => tracing_value_to_attr_val(value),
+    }
+}
+
+/// Record the boolean field `key` as the integer `0` or `1`, e.g. for workspaces built around a
+/// system that logged booleans as integers. Values of other types are recorded as usual.
+///
+/// This isn't one of the [`default_handlers`], register it for each field that needs it.
+pub fn bool_as_int<K: Into<String>>(key: K) -> AttributeHandler {
+    let key = key.into();
+    AttributeHandler::new(key.clone(), move |value| {
+        let attrval = match value {
+            TracingValue::Bool(b) => AttrVal::Integer(b as i64),
+            value => tracing_value_to_attr_val(value),
+        };
+        vec![(key.clone(), attrval)]
+    })
+}
+
+/// Record a field holding a `Debug` formatted enum, e.g. `state = ?State::Failed(3)`, as both the
+/// full `Debug` string under `<key>` and the variant name, `Failed`, under `<key>.variant`, so the
+/// variant can be filtered on.
+///
+/// The variant is the leading identifier of the string. Values that don't start with one, or
+/// aren't strings, are only recorded under `<key>` as usual.
+pub fn enum_variant<K: Into<String>>(key: K) -> AttributeHandler {
+    let key = key.into();
+    AttributeHandler::new(key.clone(), move |value| {
+        let variant = match &value {
+            TracingValue::String(s) => leading_identifier(s).map(str::to_string),
+            _ => None,
+        };
+
+        let mut attrs = vec![(key.clone(), tracing_value_to_attr_val(value))];
+        if let Some(variant) = variant {
+            attrs.push((format!("{key}.variant"), variant.into()));
+        }
+        attrs
+    })
+}
+
+fn leading_identifier(s: &str) -> Option<&str> {
+    let end = s
+        .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .unwrap_or(s.len());
+    let ident = &s[..end];
+    ident
+        .starts_with(|c: char| c.is_alphabetic() || c == '_')
+        .then_some(ident)
+}
+
+/// Record a field holding a `Debug` formatted list, e.g. `ids = ?vec![1, 2, 3]`, as one attribute
+/// per element, `<key>.0` through `<key>.N`, plus the number of elements in `<key>.len`.
+///
+/// Elements that parse as integers, floats, or bools get that type, others are recorded as strings
+/// with any surrounding quotes removed. This is meant for flat lists of simple values, elements
+/// that themselves contain `, ` are split apart. Values that aren't a `[...]` list are recorded
+/// under `<key>` as usual.
+pub fn as_indexed<K: Into<String>>(key: K) -> AttributeHandler {
+    let key = key.into();
+    AttributeHandler::new(key.clone(), move |value| match value {
+        TracingValue::String(s) if s.starts_with('[') && s.ends_with(']') => {
+            indexed_attrs(&key, &s[1..s.len() - 1])
+        }
+        value => vec![(key.clone(), tracing_value_to_attr_val(value))],
+    })
+}
+
+fn indexed_attrs(key: &str, list: &str) -> Vec<(String, AttrVal)> {
+    let elements: Vec<&str> = if list.trim().is_empty() {
+        Vec::new()
+    } else {
+        list.split(", ").map(str::trim).collect()
+    };
+
+    let mut attrs = Vec::with_capacity(elements.len() + 1);
+    attrs.push((
+        format!("{key}.len"),
+        AttrVal::Integer(elements.len() as i64),
+    ));
+    for (i, element) in elements.into_iter().enumerate() {
+        let attrval = if let Ok(n) = element.parse::<i64>() {
+            AttrVal::Integer(n)
+        } else if let Ok(n) = element.parse::<f64>() {
+            n.into()
+        } else if let Ok(b) = element.parse::<bool>() {
+            AttrVal::Bool(b)
+        } else {
+            element.trim_matches('"').to_string().into()
+        };
+        attrs.push((format!("{key}.{i}"), attrval));
+    }
+    attrs
+}
+
+/// Send `interaction.remote_timestamp` as a timestamp when it's a non-negative integer of
+/// nanoseconds.
+pub fn remote_timestamp() -> AttributeHandler {
+    let key = "interaction.remote_timestamp";
+    AttributeHandler::new(key, move |value| {
+        vec![(
+            key.to_string(),
+            coerce_timestamp(tracing_value_to_attr_val(value)),
+        )]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nonces_recorded_as_integers() {
+        for handler in [nonce(), remote_nonce()] {
+            let attrs = |value| (handler.handler)(value);
+            let key = handler.key().to_string();
+            for value in [TracingValue::U64(7), TracingValue::String("7".into())] {
+                assert_eq!(attrs(value), vec![(key.clone(), BigInt::new_attr_val(7))]);
+            }
+            assert_eq!(
+                attrs(TracingValue::I64(7)),
+                vec![(key.clone(), AttrVal::Integer(7))]
+            );
+            assert_eq!(
+                attrs(TracingValue::String("abc".into())),
+                vec![(key.clone(), AttrVal::from("abc"))]
+            );
+        }
+
+        // opt-in, they change the recorded type of existing nonces
+        assert!(default_handlers()
+            .iter()
+            .all(|h| !h.key().ends_with("nonce")));
+    }
+
+    #[test]
+    fn bool_as_int_records_integers() {
+        let handler = bool_as_int("enabled");
+        let attrs = |value| (handler.handler)(value);
+        assert_eq!(
+            attrs(TracingValue::Bool(true)),
+            vec![("enabled".to_string(), AttrVal::Integer(1))]
+        );
+        assert_eq!(
+            attrs(TracingValue::Bool(false)),
+            vec![("enabled".to_string(), AttrVal::Integer(0))]
+        );
+        assert_eq!(
+            attrs(TracingValue::String("yes".into())),
+            vec![("enabled".to_string(), AttrVal::from("yes"))]
+        );
+    }
+
+    #[test]
+    fn as_indexed_splits_debug_lists() {
+        let handler = as_indexed("ids");
+        let attrs = (handler.handler)(TracingValue::String(r#"[1, 2.5, true, "four"]"#.into()));
+        assert_eq!(
+            attrs,
+            vec![
+                ("ids.len".to_string(), AttrVal::Integer(4)),
+                ("ids.0".to_string(), AttrVal::Integer(1)),
+                ("ids.1".to_string(), 2.5.into()),
+                ("ids.2".to_string(), AttrVal::Bool(true)),
+                ("ids.3".to_string(), "four".to_string().into()),
+            ]
+        );
+
+        let attrs = (handler.handler)(TracingValue::String("[]".into()));
+        assert_eq!(attrs, vec![("ids.len".to_string(), AttrVal::Integer(0))]);
+
+        let attrs = (handler.handler)(TracingValue::I64(7));
+        assert_eq!(attrs, vec![("ids".to_string(), AttrVal::Integer(7))]);
+    }
+
+    #[test]
+    fn leading_identifier_of_debug_strings() {
+        assert_eq!(leading_identifier("Failed(3)"), Some("Failed"));
+        assert_eq!(leading_identifier("Idle"), Some("Idle"));
+        assert_eq!(leading_identifier("Conn { id: 1 }"), Some("Conn"));
+        assert_eq!(leading_identifier("_Private"), Some("_Private"));
+        assert_eq!(leading_identifier("3xx"), None);
+        assert_eq!(leading_identifier("(1, 2)"), None);
+        assert_eq!(leading_identifier(""), None);
+    }
+
+    #[test]
+    fn enum_variant_records_the_variant() {
+        let handler = enum_variant("state");
+        let attrs = (handler.handler)(TracingValue::String("Failed(3)".into()));
+        assert_eq!(
+            attrs,
+            vec![
+                ("state".to_string(), AttrVal::from("Failed(3)")),
+                ("state.variant".to_string(), AttrVal::from("Failed")),
+            ]
+        );
+
+        let attrs = (handler.handler)(TracingValue::String("[1, 2]".into()));
+        assert_eq!(attrs, vec![("state".to_string(), AttrVal::from("[1, 2]"))]);
+
+        let attrs = (handler.handler)(TracingValue::I64(7));
+        assert_eq!(attrs, vec![("state".to_string(), AttrVal::Integer(7))]);
+    }
+}
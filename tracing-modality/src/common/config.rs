@@ -0,0 +1,272 @@
+//! Loading [`Options`] from a TOML or JSON config file, with the `config` feature.
+
+use crate::Options;
+use auxon_sdk::api::AttrVal;
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use thiserror::Error;
+use tracing_core::LevelFilter;
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    /// The config file, or the auth file it names, couldn't be read.
+    #[error("Failed to read {}", path.display())]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Invalid TOML config")]
+    Toml(#[from] toml::de::Error),
+    #[error("Invalid JSON config")]
+    Json(#[from] serde_json::Error),
+    /// The auth token, given directly or in an auth file, isn't hex.
+    #[error("Auth token is not a hex string")]
+    InvalidAuth,
+    #[error("Invalid max_level '{0}', expected one of off, error, warn, info, debug, trace")]
+    InvalidLevel(String),
+}
+
+/// The options that can be set from a config file. Callbacks like the timeline identifier or
+/// attribute handlers can't be, they keep their defaults.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct OptionsFile {
+    server_address: Option<SocketAddr>,
+    additional_server_addresses: Vec<SocketAddr>,
+    /// Hex auth token.
+    auth: Option<String>,
+    /// A file containing the hex auth token, like the modality CLI's `.user_auth_token`.
+    auth_file: Option<PathBuf>,
+    name: Option<String>,
+    environment: Option<String>,
+    service_id: Option<String>,
+    clock_id: Option<u64>,
+    metadata: BTreeMap<String, MetadataValue>,
+    max_level: Option<String>,
+    attr_vec_capacity: Option<usize>,
+    max_attrs_per_event: Option<usize>,
+    timeline_churn_threshold: Option<f64>,
+    panic_capture: Option<bool>,
+    wall_clock_timestamps: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum MetadataValue {
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+    String(String),
+}
+
+impl From<MetadataValue> for AttrVal {
+    fn from(value: MetadataValue) -> Self {
+        match value {
+            MetadataValue::Bool(b) => AttrVal::Bool(b),
+            MetadataValue::Integer(n) => AttrVal::Integer(n),
+            MetadataValue::Float(n) => n.into(),
+            MetadataValue::String(s) => s.into(),
+        }
+    }
+}
+
+impl OptionsFile {
+    /// Apply the settings in the file on top of `options`.
+    fn apply(self, mut options: Options) -> Result<Options, ConfigError> {
+        if let Some(addr) = self.server_address {
+            options.set_server_address(addr);
+        }
+        for addr in self.additional_server_addresses {
+            options.add_server_address(addr);
+        }
+
+        let auth = match (self.auth, self.auth_file) {
+            (Some(auth), _) => Some(auth),
+            (None, Some(path)) => Some(
+                std::fs::read_to_string(&path)
+                    .map_err(|source| ConfigError::Io { path, source })?,
+            ),
+            (None, None) => None,
+        };
+        if let Some(auth) = auth {
+            options.auth = Some(hex::decode(auth.trim()).map_err(|_| ConfigError::InvalidAuth)?);
+        }
+
+        if let Some(name) = self.name {
+            options.set_name(name);
+        }
+        if let Some(environment) = self.environment {
+            options.set_environment(environment);
+        }
+        if let Some(service_id) = self.service_id {
+            options.set_service_id(service_id);
+        }
+        if let Some(clock_id) = self.clock_id {
+            options.set_clock_id(clock_id);
+        }
+        for (key, value) in self.metadata {
+            options.add_metadata(key, value);
+        }
+        if let Some(level) = self.max_level {
+            let level =
+                LevelFilter::from_str(&level).map_err(|_| ConfigError::InvalidLevel(level))?;
+            options.set_max_level(level);
+        }
+        if let Some(capacity) = self.attr_vec_capacity {
+            options.set_attr_vec_capacity(capacity);
+        }
+        if let Some(max) = self.max_attrs_per_event {
+            options.set_max_attrs_per_event(max);
+        }
+        if let Some(threshold) = self.timeline_churn_threshold {
+            options.set_timeline_churn_threshold(Some(threshold));
+        }
+        if let Some(enabled) = self.panic_capture {
+            options.set_panic_capture(enabled);
+        }
+        if let Some(enabled) = self.wall_clock_timestamps {
+            options.set_wall_clock_timestamps(enabled);
+        }
+
+        Ok(options)
+    }
+}
+
+impl Options {
+    /// Load options from a config file, JSON if the file name ends in `.json` and TOML otherwise.
+    ///
+    /// Settings missing from the file keep their defaults, so auth still falls back to the
+    /// `MODALITY_AUTH_TOKEN` environment variable. Most keys are the names of the corresponding
+    /// setters minus `set_`. The exceptions are `auth`, the token as a hex string rather than
+    /// bytes, `auth_file`, a file holding that hex string, and `additional_server_addresses`, a
+    /// list of addresses each passed to [`add_server_address`](Self::add_server_address):
+    ///
+    /// ```toml
+    /// server_address = "10.0.0.5:14182"
+    /// additional_server_addresses = ["127.0.0.1:14182"]
+    /// auth_file = "/etc/modality/auth_token"  # or `auth = "<hex token>"`
+    /// name = "sensor-gateway"
+    /// environment = "prod"
+    /// service_id = "sensor-gateway"
+    /// clock_id = 3
+    /// max_level = "info"
+    /// attr_vec_capacity = 16
+    /// max_attrs_per_event = 64
+    /// timeline_churn_threshold = 0.9
+    /// panic_capture = true
+    /// wall_clock_timestamps = true
+    ///
+    /// [metadata]
+    /// site = "lab-3"
+    /// ```
+    ///
+    /// Callbacks, like the timeline identifier or attribute handlers, can't be set from a file,
+    /// set them on the returned options instead.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Options, ConfigError> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path).map_err(|source| ConfigError::Io {
+            path: path.to_owned(),
+            source,
+        })?;
+
+        if path.extension().is_some_and(|ext| ext == "json") {
+            Self::from_json_str(&contents)
+        } else {
+            Self::from_toml_str(&contents)
+        }
+    }
+
+    /// Load options from TOML, see [`from_file`](Self::from_file).
+    pub fn from_toml_str(toml: &str) -> Result<Options, ConfigError> {
+        toml::from_str::<OptionsFile>(toml)?.apply(Options::new())
+    }
+
+    /// Load options from JSON, see [`from_file`](Self::from_file).
+    pub fn from_json_str(json: &str) -> Result<Options, ConfigError> {
+        serde_json::from_str::<OptionsFile>(json)?.apply(Options::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_toml_str_applies_settings() {
+        let options = Options::from_toml_str(
+            r#"
+            server_address = "10.0.0.5:14182"
+            additional_server_addresses = ["127.0.0.1:14182"]
+            auth = "beef"
+            environment = "prod"
+            max_level = "warn"
+            max_attrs_per_event = 64
+            wall_clock_timestamps = false
+
+            [metadata]
+            site = "lab-3"
+            rack = 7
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(options.server_addr, "10.0.0.5:14182".parse().unwrap());
+        assert_eq!(
+            options.additional_server_addrs,
+            vec!["127.0.0.1:14182".parse::<SocketAddr>().unwrap()]
+        );
+        assert_eq!(options.auth, Some(vec![0xbe, 0xef]));
+        assert_eq!(options.environment.as_deref(), Some("prod"));
+        assert_eq!(options.max_level.get(), LevelFilter::WARN);
+        assert_eq!(options.max_attrs_per_event, Some(64));
+        assert!(!options.wall_clock_timestamps);
+        assert!(options
+            .metadata
+            .contains(&("timeline.site".to_string(), AttrVal::from("lab-3"))));
+        assert!(options
+            .metadata
+            .contains(&("timeline.rack".to_string(), AttrVal::Integer(7))));
+
+        // and the same through JSON
+        let options = Options::from_json_str(r#"{"max_level": "debug"}"#).unwrap();
+        assert_eq!(options.max_level.get(), LevelFilter::DEBUG);
+    }
+
+    #[test]
+    fn from_toml_str_auth_file() {
+        let path = std::env::temp_dir().join(format!("auth-{}", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "beef\n").unwrap();
+        let toml = format!("auth_file = {:?}", path.display().to_string());
+        let options = Options::from_toml_str(&toml);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(options.unwrap().auth, Some(vec![0xbe, 0xef]));
+
+        let missing = Options::from_toml_str(&toml);
+        assert!(matches!(missing, Err(ConfigError::Io { path: p, .. }) if p == path));
+    }
+
+    #[test]
+    fn from_toml_str_errors() {
+        for (toml, check) in [
+            (
+                "unknown_key = 1",
+                (|e| matches!(e, ConfigError::Toml(_))) as fn(&ConfigError) -> bool,
+            ),
+            ("auth = \"not hex\"", |e| {
+                matches!(e, ConfigError::InvalidAuth)
+            }),
+            (
+                "max_level = \"loud\"",
+                |e| matches!(e, ConfigError::InvalidLevel(l) if l == "loud"),
+            ),
+        ] {
+            let err = Options::from_toml_str(toml).err().unwrap();
+            assert!(check(&err), "{toml}: {err:?}");
+        }
+    }
+}
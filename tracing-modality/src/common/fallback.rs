@@ -0,0 +1,60 @@
+use crate::Options;
+use tracing::dispatcher::SetGlobalDefaultError;
+use tracing_core::Dispatch;
+use tracing_subscriber::{filter::filter_fn, layer::SubscriberExt, Layer, Registry};
+
+/// Install a [`console_dispatch`] as the global default tracer after initializing modality failed
+/// with `error`, along with the panic hook if `options` ask for one.
+pub(crate) fn install_console_fallback(
+    options: &Options,
+    error: &anyhow::Error,
+) -> Result<(), SetGlobalDefaultError> {
+    tracing::dispatcher::set_global_default(console_dispatch(options))?;
+    warn_console_fallback(error);
+    if options.panic_capture {
+        crate::panic::install_panic_hook();
+    }
+    Ok(())
+}
+
+/// A plain tracer printing trace events to stderr, used in place of modality when
+/// [console fallback](crate::Options::set_console_fallback) is on and modality can't be reached.
+///
+/// It follows the options' max level, including changes made at runtime.
+fn console_dispatch(options: &Options) -> Dispatch {
+    let max_level = options.max_level.clone();
+    let layer = tracing_subscriber::fmt::layer()
+        .with_writer(std::io::stderr)
+        .with_filter(filter_fn(move |metadata| {
+            *metadata.level() <= max_level.get()
+        }));
+    Dispatch::new(Registry::default().with(layer))
+}
+
+/// Say once, at init, why trace events are going to stderr instead of modality.
+fn warn_console_fallback(error: &anyhow::Error) {
+    eprintln!(
+        "warning: tracing modality couldn't connect to modality, printing trace events to stderr \
+         instead: {error:#}"
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing::Level;
+    use tracing_core::LevelFilter;
+
+    #[test]
+    fn console_follows_max_level() {
+        let options = Options::new().with_max_level(Level::INFO);
+        let dispatch = console_dispatch(&options);
+        tracing::dispatcher::with_default(&dispatch, || {
+            assert!(tracing::enabled!(Level::INFO));
+            assert!(!tracing::enabled!(Level::DEBUG));
+
+            options.max_level.set(LevelFilter::DEBUG);
+            assert!(tracing::enabled!(Level::DEBUG));
+        });
+    }
+}
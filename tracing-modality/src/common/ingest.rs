@@ -1,8 +1,12 @@
 pub use auxon_sdk::api::TimelineId;
 
 use crate::{
-    layer::{RecordMap, TracingValue},
-    Options,
+    attr_handlers::{HandlerFn, TimelinePredicate},
+    kind,
+    layer::{forget_announced_timelines, wrap_message, RecordMap, TickFn, TracingValue},
+    options::{timeline_metadata_key, ComponentFn, IngestExitFn, SeverityFn, UNIT_SUFFIXES},
+    timeline::{derive_timeline_id, RunId},
+    Options, UserTimelineInfo,
 };
 use anyhow::Context;
 use auxon_sdk::{
@@ -10,15 +14,30 @@ use auxon_sdk::{
     ingest_client::{BoundTimelineState, IngestClient, IngestError as SdkIngestError},
     ingest_protocol::InternedAttrKey,
 };
-use once_cell::sync::Lazy;
-use std::{collections::HashMap, num::NonZeroU64, time::Duration};
+use once_cell::unsync::OnceCell;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    error::Error as StdError,
+    io,
+    num::NonZeroU64,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, Once,
+    },
+    time::{Duration, Instant},
+};
 use thiserror::Error;
 use tokio::{
     select,
-    sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    sync::mpsc::{
+        self,
+        error::{TryRecvError, TrySendError},
+        UnboundedReceiver, UnboundedSender,
+    },
     sync::oneshot,
+    time::{Interval, MissedTickBehavior},
 };
-use tracing_core::Metadata;
+use tracing_core::{Level, Metadata};
 
 #[cfg(feature = "blocking")]
 use std::thread::{self, JoinHandle};
@@ -27,8 +46,144 @@ use tokio::runtime::Runtime;
 #[cfg(feature = "async")]
 use tokio::task;
 
+/// The ingest thread's lookup tables, keyed by attribute names and IDs this crate controls, so a
+/// faster non-DoS-resistant hasher is fine with the `fast-hash` feature.
+#[cfg(feature = "fast-hash")]
+type FastHashMap<K, V> = rustc_hash::FxHashMap<K, V>;
+#[cfg(not(feature = "fast-hash"))]
+type FastHashMap<K, V> = std::collections::HashMap<K, V>;
+
+/// The sending side of the channel to the ingest thread or task, bounded unless the
+/// [channel capacity](crate::Options::set_channel_capacity) is `None`.
+#[derive(Clone, Debug)]
+pub(crate) struct IngestSender {
+    channel: ChannelSender,
+    /// The last `event.internal.rs.timeline_seq` given out on each timeline from a timeline
+    /// identifier or scope, which any thread may record to. One entry per such timeline, kept until
+    /// it's [closed](crate::TracingModality::close_timeline). Shared by every clone, so that
+    /// numbering is per ingest rather than per process.
+    timeline_seqs: Arc<Mutex<HashMap<TimelineId, u64>>>,
+}
+
+#[derive(Clone, Debug)]
+enum ChannelSender {
+    Bounded {
+        sender: mpsc::Sender<WrappedMessage>,
+        /// Messages dropped because the channel was full, shared by every clone.
+        dropped: Arc<AtomicU64>,
+    },
+    Unbounded(UnboundedSender<WrappedMessage>),
+}
+
+impl IngestSender {
+    fn new(channel: ChannelSender) -> Self {
+        IngestSender {
+            channel,
+            timeline_seqs: Default::default(),
+        }
+    }
+
+    /// Send without ever blocking the caller. A message that doesn't fit in a full channel is
+    /// dropped and counted.
+    pub(crate) fn send(&self, message: WrappedMessage) -> Result<(), TrySendError<()>> {
+        match &self.channel {
+            ChannelSender::Bounded { sender, dropped } => {
+                sender.try_send(message).map_err(|e| match e {
+                    TrySendError::Full(_) => {
+                        dropped.fetch_add(1, Ordering::Relaxed);
+                        TrySendError::Full(())
+                    }
+                    TrySendError::Closed(_) => TrySendError::Closed(()),
+                })
+            }
+            ChannelSender::Unbounded(sender) => {
+                sender.send(message).map_err(|_| TrySendError::Closed(()))
+            }
+        }
+    }
+
+    /// How many messages have been dropped because the channel was full.
+    pub(crate) fn dropped(&self) -> u64 {
+        match &self.channel {
+            ChannelSender::Bounded { dropped, .. } => dropped.load(Ordering::Relaxed),
+            ChannelSender::Unbounded(_) => 0,
+        }
+    }
+
+    /// The next `event.internal.rs.timeline_seq` on a timeline shared between threads.
+    pub(crate) fn next_timeline_seq(&self, timeline: TimelineId) -> u64 {
+        let mut seqs = self.timeline_seqs.lock().unwrap_or_else(|e| e.into_inner());
+        let seq = seqs.entry(timeline).or_insert(0);
+        *seq += 1;
+        *seq
+    }
+
+    /// Forget the `event.internal.rs.timeline_seq` of a timeline that has been closed.
+    pub(crate) fn forget_timeline_seq(&self, timeline: TimelineId) {
+        self.timeline_seqs
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&timeline);
+    }
+}
+
+/// The receiving side of an [`IngestSender`]'s channel.
+pub(crate) enum IngestReceiver {
+    Bounded(mpsc::Receiver<WrappedMessage>),
+    Unbounded(UnboundedReceiver<WrappedMessage>),
+}
+
+impl IngestReceiver {
+    pub(crate) async fn recv(&mut self) -> Option<WrappedMessage> {
+        match self {
+            IngestReceiver::Bounded(recv) => recv.recv().await,
+            IngestReceiver::Unbounded(recv) => recv.recv().await,
+        }
+    }
+
+    pub(crate) fn try_recv(&mut self) -> Result<WrappedMessage, TryRecvError> {
+        match self {
+            IngestReceiver::Bounded(recv) => recv.try_recv(),
+            IngestReceiver::Unbounded(recv) => recv.try_recv(),
+        }
+    }
+
+    fn close(&mut self) {
+        match self {
+            IngestReceiver::Bounded(recv) => recv.close(),
+            IngestReceiver::Unbounded(recv) => recv.close(),
+        }
+    }
+}
+
+/// A channel to the ingest thread or task holding at most `capacity` messages, or any number of
+/// them if `capacity` is `None`.
+pub(crate) fn ingest_channel(capacity: Option<usize>) -> (IngestSender, IngestReceiver) {
+    match capacity {
+        Some(capacity) => {
+            // tokio panics on a zero capacity channel
+            let (sender, recv) = mpsc::channel(capacity.max(1));
+            let dropped = Arc::new(AtomicU64::new(0));
+            (
+                IngestSender::new(ChannelSender::Bounded { sender, dropped }),
+                IngestReceiver::Bounded(recv),
+            )
+        }
+        None => {
+            let (sender, recv) = mpsc::unbounded_channel();
+            (
+                IngestSender::new(ChannelSender::Unbounded(sender)),
+                IngestReceiver::Unbounded(recv),
+            )
+        }
+    }
+}
+
+/// Asks the ingest to flush what it has queued so far, replying with whether the flush succeeded.
+pub(crate) type FlushRequests = UnboundedSender<oneshot::Sender<bool>>;
+
 thread_local! {
-    static THREAD_TIMELINE_ID: Lazy<TimelineId> = Lazy::new(TimelineId::allocate);
+    static THREAD_TIMELINE_ID: OnceCell<TimelineId> = const { OnceCell::new() };
 }
 
 #[derive(Debug, Error)]
@@ -36,15 +191,75 @@ pub enum ConnectError {
     /// No auth was provided
     #[error("Authentication required")]
     AuthRequired,
-    /// Auth was provided, but was not accepted by modality
+    /// Auth was provided, but was rejected by modality. Retrying with the same auth won't help.
     #[error("Authenticating with the provided auth failed")]
-    AuthFailed(#[from] SdkIngestError),
+    AuthRejected(SdkIngestError),
+    /// The connection failed while authenticating, before modality accepted or rejected the auth.
+    /// This may be transient.
+    #[error("Connection failed while authenticating")]
+    AuthTransport(SdkIngestError),
     /// Errors that it is assumed there is no way to handle without human intervention, meant for
     /// consumers to just print and carry on or panic.
     #[error(transparent)]
     UnexpectedFailure(#[from] anyhow::Error),
 }
 
+impl ConnectError {
+    /// Whether trying to connect again might succeed, e.g. modality wasn't listening yet or the
+    /// connection was reset or timed out.
+    ///
+    /// This is decided by the I/O error the failure was caused by, if any. A missing or rejected
+    /// auth, a protocol error, or a misconfiguration like an unparseable address is never
+    /// transient.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            ConnectError::AuthRequired | ConnectError::AuthRejected(_) => false,
+            ConnectError::AuthTransport(err) => caused_by_transient_io(err),
+            ConnectError::UnexpectedFailure(err) => caused_by_transient_io(err.as_ref()),
+        }
+    }
+
+    #[doc(hidden)]
+    pub fn from_auth_error(err: SdkIngestError) -> Self {
+        match err {
+            SdkIngestError::AuthenticationError { .. } => ConnectError::AuthRejected(err),
+            _ => ConnectError::AuthTransport(err),
+        }
+    }
+}
+
+/// Whether `err`, or any error in its chain of sources, is an I/O error or timeout that could go
+/// away by itself.
+fn caused_by_transient_io(err: &(dyn StdError + 'static)) -> bool {
+    let mut source = Some(err);
+    while let Some(err) = source {
+        if err.is::<tokio::time::error::Elapsed>() {
+            return true;
+        }
+        if let Some(err) = err.downcast_ref::<io::Error>() {
+            use io::ErrorKind::*;
+            if matches!(
+                err.kind(),
+                ConnectionRefused
+                    | ConnectionReset
+                    | ConnectionAborted
+                    | NotConnected
+                    | BrokenPipe
+                    | TimedOut
+                    | Interrupted
+                    | WouldBlock
+                    | UnexpectedEof
+                    | HostUnreachable
+                    | NetworkUnreachable
+            ) {
+                return true;
+            }
+        }
+        source = err.source();
+    }
+    false
+}
+
 #[derive(Debug, Error)]
 pub enum IngestError {
     /// Errors that it is assumed there is no way to handle without human intervention, meant for
@@ -54,23 +269,48 @@ pub enum IngestError {
 }
 
 pub(crate) fn current_timeline() -> TimelineId {
-    THREAD_TIMELINE_ID.with(|id| **id)
+    THREAD_TIMELINE_ID.with(|id| *id.get_or_init(TimelineId::allocate))
+}
+
+/// Pin the current thread's timeline ID, fails with the existing ID if one was already assigned.
+pub(crate) fn set_current_timeline(timeline: TimelineId) -> Result<(), TimelineId> {
+    THREAD_TIMELINE_ID.with(|id| id.set(timeline).map_err(|_| *id.get().unwrap()))
 }
 
 pub(crate) type SpanId = NonZeroU64;
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub(crate) struct WrappedMessage {
     pub message: Message,
+    /// Time since the layer was first used, sampled on the producing thread.
     pub tick: Duration,
+    /// Wall clock time at the same instant as `tick`, see [`wrap_message`]. This is used for
+    /// `event.timestamp` rather than the time the ingest thread gets around to the message.
     pub nanos_since_unix_epoch: Option<Nanoseconds>,
     pub timeline: TimelineId,
+    /// The `event.internal.rs.timeline_seq` of the event this message is sent as, numbered by the
+    /// layer so that messages lost before reaching the ingest still leave a gap. Only set for
+    /// messages that are always sent as exactly one event.
+    pub seq: Option<u64>,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub(crate) enum Message {
     NewTimeline {
         name: String,
+        /// The run the timeline's ID was derived from, for timelines from a timeline identifier.
+        run_id: Option<Uuid>,
+        /// The `user_id` of timelines from a timeline identifier.
+        user_id: Option<u64>,
+        /// The parent timeline, recorded as `timeline.parent`.
+        parent: Option<TimelineId>,
+    },
+    /// The run ID was rotated, later timelines belong to the new run.
+    NewRun {
+        run_id: Uuid,
+    },
+    TimelineMetadata {
+        metadata: Vec<(String, AttrVal)>,
     },
     NewSpan {
         id: SpanId,
@@ -88,30 +328,137 @@ pub(crate) enum Message {
     Event {
         metadata: &'static Metadata<'static>,
         records: RecordMap,
+        /// Attributes from the context provider, recorded unless the event has a field by the
+        /// same name. Boxed to keep messages for events without context small.
+        context: Box<[(String, AttrVal)]>,
     },
     Enter {
         span: SpanId,
+        /// The span's current field set, only present if fields on enter are enabled.
+        records: Option<RecordMap>,
     },
     Exit {
         span: SpanId,
     },
     Close {
         span: SpanId,
+        metadata: &'static Metadata<'static>,
+        /// The span's complete field set, only present if span close snapshots or poll counts are
+        /// enabled. The span's close event is only sent when this is present.
+        records: Option<RecordMap>,
+        /// How many times the span was entered, if poll counts are enabled.
+        poll_count: Option<u64>,
     },
     IdChange {
         old: SpanId,
         new: SpanId,
     },
+    /// The thread that owns this timeline has exited.
+    TimelineEnd,
+    /// A timeline from a timeline identifier is finished and can be forgotten.
+    CloseTimeline,
+    /// Send the events the flight recorder is holding, as if an error had been recorded.
+    DumpFlightRecorder,
+    /// Diagnostic marker, a final flush is about to start.
+    FlushRequested,
+    /// Diagnostic marker, the final flush finished after `elapsed`.
+    FlushCompleted {
+        elapsed: Duration,
+    },
+    /// Diagnostic, a callsite was used for the first time. Always recorded on the root timeline.
+    CallsiteSchema {
+        metadata: &'static Metadata<'static>,
+    },
+    /// A timeline has been quiet for the heartbeat interval. Only sent by the ingest thread.
+    Heartbeat,
+    /// Diagnostic, the layer's overhead over the last batch of callbacks on one thread. Always
+    /// recorded on the root timeline.
+    LayerOverhead {
+        p50: Duration,
+        p99: Duration,
+    },
+}
+
+/// A summary of what happened while shutting down the ingest thread or task, returned from
+/// `finish`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ShutdownReport {
+    /// How many messages were still queued when `finish` was called, and were drained before
+    /// shutting down.
+    pub drained: usize,
+    /// How many messages were dropped over the life of the ingest because its channel was full,
+    /// see [`Options::set_channel_capacity`](crate::Options::set_channel_capacity).
+    pub dropped: u64,
+    /// How many events the [flight recorder](crate::Options::set_flight_recorder) discarded
+    /// without sending, to make room for newer events or because they were still held at
+    /// `finish`. Each one leaves a gap in its timeline's `event.internal.rs.timeline_seq`.
+    pub discarded: u64,
+    /// Whether the final flush out to modality succeeded.
+    pub flushed: bool,
+    /// How long draining and flushing took.
+    pub elapsed: Duration,
+}
+
+/// Why the ingest thread or task stopped, see
+/// [`Options::set_on_ingest_exit`](crate::Options::set_on_ingest_exit).
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum IngestExitReason {
+    /// `finish` was called and everything was flushed out to modality.
+    Finished,
+    /// The ingest handle was dropped without calling `finish`. Messages queued by then were still
+    /// flushed, later ones are lost.
+    ChannelClosed,
+    /// The final flush out to modality failed, so trace data was likely lost. Holds the error.
+    ClientError(String),
+    /// The ingest thread or task panicked.
+    Panicked,
+    /// The ingest task was dropped before it stopped, e.g. because its tokio runtime shut down.
+    Cancelled,
+}
+
+/// Reports why the ingest thread stopped when dropped, which covers it unwinding from a panic too.
+struct ExitGuard {
+    callback: Option<Arc<IngestExitFn>>,
+    reason: Option<IngestExitReason>,
+}
+
+impl Drop for ExitGuard {
+    fn drop(&mut self) {
+        let Some(callback) = &self.callback else {
+            return;
+        };
+        let reason = self.reason.take().unwrap_or(if std::thread::panicking() {
+            IngestExitReason::Panicked
+        } else {
+            IngestExitReason::Cancelled
+        });
+        callback(reason);
+    }
 }
 
+/// Attribute keys and the IDs modality interned them as, see
+/// [`ModalityIngestThreadHandle::export_key_dictionary`].
+pub(crate) type KeyDictionary = Arc<Mutex<Vec<(String, u64)>>>;
+
 pub trait ModalityIngestHandle {}
 
 #[cfg(feature = "blocking")]
 /// A handle to control the spawned ingest thread.
 pub struct ModalityIngestThreadHandle {
-    pub(crate) ingest_sender: UnboundedSender<WrappedMessage>,
+    pub(crate) ingest_sender: IngestSender,
+    pub(crate) root_timeline: TimelineId,
+    pub(crate) run_id: RunId,
+    pub(crate) tick_fn: Option<Arc<TickFn>>,
+    pub(crate) timeline_count: Arc<AtomicUsize>,
+    pub(crate) key_dictionary: KeyDictionary,
+    /// Set once `finish` is called, so layers know sends failing after that are expected.
+    pub(crate) shutting_down: Arc<AtomicBool>,
     pub(crate) finish_sender: Option<oneshot::Sender<()>>,
-    pub(crate) thread: Option<JoinHandle<()>>,
+    pub(crate) flush_requests: FlushRequests,
+    pub(crate) thread: Option<JoinHandle<ShutdownReport>>,
+    /// Ingest threads for additional server addresses, fed by this one.
+    pub(crate) mirrors: Vec<ModalityIngestThreadHandle>,
 }
 
 #[cfg(feature = "blocking")]
@@ -119,6 +466,115 @@ impl ModalityIngestHandle for ModalityIngestThreadHandle {}
 
 #[cfg(feature = "blocking")]
 impl ModalityIngestThreadHandle {
+    /// Add metadata to the root timeline after initialization, e.g. for facts that are only
+    /// learned once the program is running.
+    pub fn add_root_metadata<K: AsRef<str>, V: Into<AttrVal>>(&self, key: K, value: V) {
+        let message = Message::TimelineMetadata {
+            metadata: vec![(timeline_metadata_key(key.as_ref()), value.into())],
+        };
+        let _ = self.ingest_sender.send(wrap_message(
+            message,
+            self.root_timeline,
+            self.tick_fn.as_deref(),
+        ));
+    }
+
+    /// Start a new run: flush what has been recorded so far and switch to a fresh `run_id`,
+    /// returning it.
+    ///
+    /// Timelines from a [timeline identifier](crate::Options::set_timeline_identifier) are
+    /// derived from the `run_id`, so after rotating the same `user_id` maps to a new timeline and
+    /// events no longer continue the old one. Per-thread timelines are allocated rather than
+    /// derived, they continue across the rotation and keep their original `timeline.run_id`.
+    /// Timelines first seen after the rotation get the new `timeline.run_id`.
+    pub fn rotate_run_id(&self) -> Uuid {
+        let run_id = self.run_id.rotate();
+        let message = Message::NewRun { run_id };
+        let _ = self.ingest_sender.send(wrap_message(
+            message,
+            self.root_timeline,
+            self.tick_fn.as_deref(),
+        ));
+        run_id
+    }
+
+    /// How many distinct timelines have been recorded so far, e.g. to catch a timeline identifier
+    /// that creates a new timeline for every event. This only grows over the life of the ingest,
+    /// closed timelines still count.
+    pub fn timeline_count(&self) -> usize {
+        self.timeline_count.load(Ordering::Relaxed)
+    }
+
+    /// Every attribute key declared to modality so far, with the ID it was interned as, in the
+    /// order they were declared. Event and timeline keys share one ID space.
+    ///
+    /// Save this alongside captured data that refers to keys by ID, so it can be decoded without
+    /// a live server.
+    pub fn export_key_dictionary(&self) -> Vec<(String, u64)> {
+        self.key_dictionary
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// The ID of the timeline `user_id` maps to in the current run, e.g. to check the
+    /// `interaction.remote_timeline_id` a sender records matches the timeline the receiver's
+    /// [timeline identifier](crate::Options::set_timeline_identifier) puts its events on.
+    ///
+    /// This changes when the run ID is [rotated](Self::rotate_run_id).
+    pub fn timeline_id_for(&self, user_id: u64) -> TimelineId {
+        derive_timeline_id(&self.run_id.get(), user_id)
+    }
+
+    /// Close the timeline with `user_id` in the current run, for a
+    /// [timeline identifier](crate::Options::set_timeline_identifier) timeline that is known to be
+    /// finished, e.g. its connection closed.
+    ///
+    /// A `timeline:end` event is recorded on it and the ingest forgets it, freeing the memory it
+    /// used. Closing a timeline that was never used, or was already closed, does nothing. Using
+    /// the timeline again afterwards opens it anew, with its metadata sent again.
+    pub fn close_timeline(&self, user_id: u64) {
+        let timeline = derive_timeline_id(&self.run_id.get(), user_id);
+        self.ingest_sender.forget_timeline_seq(timeline);
+        forget_announced_timelines();
+        let _ = self.ingest_sender.send(wrap_message(
+            Message::CloseTimeline,
+            timeline,
+            self.tick_fn.as_deref(),
+        ));
+    }
+
+    /// Send the events held by the [flight recorder](crate::Options::set_flight_recorder) to
+    /// modality now, e.g. when something's gone wrong that wasn't recorded as an error.
+    pub fn dump_flight_recorder(&self) {
+        let _ = self.ingest_sender.send(wrap_message(
+            Message::DumpFlightRecorder,
+            self.root_timeline,
+            self.tick_fn.as_deref(),
+        ));
+    }
+
+    /// How many messages have been dropped so far because the ingest's channel was full, see
+    /// [`Options::set_channel_capacity`](crate::Options::set_channel_capacity). This only grows,
+    /// a rising count means the ingest can't keep up with the program or modality.
+    pub fn dropped_messages(&self) -> u64 {
+        self.ingest_sender.dropped()
+            + self
+                .mirrors
+                .iter()
+                .map(|mirror| mirror.dropped_messages())
+                .sum::<u64>()
+    }
+
+    /// Where to ask this ingest, then each of its mirrors, to flush, in that order.
+    pub(crate) fn flush_requests(&self) -> Vec<FlushRequests> {
+        let mut requests = vec![self.flush_requests.clone()];
+        for mirror in &self.mirrors {
+            requests.extend(mirror.flush_requests());
+        }
+        requests
+    }
+
     /// Stop accepting new trace events, flush all existing events, and stop ingest thread.
     ///
     /// This function must be called at the end of your main thread to give the ingest thread a
@@ -130,23 +586,48 @@ impl ModalityIngestThreadHandle {
     /// thread attempts to join itself or otherwise may create a deadlock with joining threads.
     /// This case should be incredibly unlikely, if not impossible, but can not be statically
     /// guarenteed.
-    pub fn finish(mut self) {
+    pub fn finish(mut self) -> ShutdownReport {
+        let start = Instant::now();
+        self.shutting_down.store(true, Ordering::Relaxed);
         if let Some(finish) = self.finish_sender.take() {
             let _ = finish.send(());
         }
 
-        if let Some(thread) = self.thread.take() {
-            let _ = thread.join();
+        let mut report = self
+            .thread
+            .take()
+            .and_then(|thread| thread.join().ok())
+            .unwrap_or_default();
+        report.dropped = self.ingest_sender.dropped();
+
+        // mirrors are only finished once this thread has forwarded everything to them
+        for mirror in std::mem::take(&mut self.mirrors) {
+            let mirror_report = mirror.finish();
+            report.flushed &= mirror_report.flushed;
+            report.dropped += mirror_report.dropped;
         }
+
+        report.elapsed = start.elapsed();
+        report
     }
 }
 
 #[cfg(feature = "async")]
 /// A handle to control the spawned ingest task.
 pub struct ModalityIngestTaskHandle {
-    pub(crate) ingest_sender: UnboundedSender<WrappedMessage>,
+    pub(crate) ingest_sender: IngestSender,
+    pub(crate) root_timeline: TimelineId,
+    pub(crate) run_id: RunId,
+    pub(crate) tick_fn: Option<Arc<TickFn>>,
+    pub(crate) timeline_count: Arc<AtomicUsize>,
+    pub(crate) key_dictionary: KeyDictionary,
+    /// Set once `finish` is called, so layers know sends failing after that are expected.
+    pub(crate) shutting_down: Arc<AtomicBool>,
     pub(crate) finish_sender: Option<oneshot::Sender<()>>,
-    pub(crate) task: Option<task::JoinHandle<()>>,
+    pub(crate) flush_requests: FlushRequests,
+    pub(crate) task: Option<task::JoinHandle<ShutdownReport>>,
+    /// Ingest tasks for additional server addresses, fed by this one.
+    pub(crate) mirrors: Vec<ModalityIngestTaskHandle>,
 }
 
 #[cfg(feature = "async")]
@@ -154,27 +635,289 @@ impl ModalityIngestHandle for ModalityIngestTaskHandle {}
 
 #[cfg(feature = "async")]
 impl ModalityIngestTaskHandle {
+    /// Add metadata to the root timeline after initialization, e.g. for facts that are only
+    /// learned once the program is running.
+    pub fn add_root_metadata<K: AsRef<str>, V: Into<AttrVal>>(&self, key: K, value: V) {
+        let message = Message::TimelineMetadata {
+            metadata: vec![(timeline_metadata_key(key.as_ref()), value.into())],
+        };
+        let _ = self.ingest_sender.send(wrap_message(
+            message,
+            self.root_timeline,
+            self.tick_fn.as_deref(),
+        ));
+    }
+
+    /// Start a new run: flush what has been recorded so far and switch to a fresh `run_id`,
+    /// returning it.
+    ///
+    /// Timelines from a [timeline identifier](crate::Options::set_timeline_identifier) are
+    /// derived from the `run_id`, so after rotating the same `user_id` maps to a new timeline and
+    /// events no longer continue the old one. Per-thread timelines are allocated rather than
+    /// derived, they continue across the rotation and keep their original `timeline.run_id`.
+    /// Timelines first seen after the rotation get the new `timeline.run_id`.
+    pub fn rotate_run_id(&self) -> Uuid {
+        let run_id = self.run_id.rotate();
+        let message = Message::NewRun { run_id };
+        let _ = self.ingest_sender.send(wrap_message(
+            message,
+            self.root_timeline,
+            self.tick_fn.as_deref(),
+        ));
+        run_id
+    }
+
+    /// How many distinct timelines have been recorded so far, e.g. to catch a timeline identifier
+    /// that creates a new timeline for every event. This only grows over the life of the ingest,
+    /// closed timelines still count.
+    pub fn timeline_count(&self) -> usize {
+        self.timeline_count.load(Ordering::Relaxed)
+    }
+
+    /// Every attribute key declared to modality so far, with the ID it was interned as, in the
+    /// order they were declared. Event and timeline keys share one ID space.
+    ///
+    /// Save this alongside captured data that refers to keys by ID, so it can be decoded without
+    /// a live server.
+    pub fn export_key_dictionary(&self) -> Vec<(String, u64)> {
+        self.key_dictionary
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// The ID of the timeline `user_id` maps to in the current run, e.g. to check the
+    /// `interaction.remote_timeline_id` a sender records matches the timeline the receiver's
+    /// [timeline identifier](crate::Options::set_timeline_identifier) puts its events on.
+    ///
+    /// This changes when the run ID is [rotated](Self::rotate_run_id).
+    pub fn timeline_id_for(&self, user_id: u64) -> TimelineId {
+        derive_timeline_id(&self.run_id.get(), user_id)
+    }
+
+    /// Close the timeline with `user_id` in the current run, for a
+    /// [timeline identifier](crate::Options::set_timeline_identifier) timeline that is known to be
+    /// finished, e.g. its connection closed.
+    ///
+    /// A `timeline:end` event is recorded on it and the ingest forgets it, freeing the memory it
+    /// used. Closing a timeline that was never used, or was already closed, does nothing. Using
+    /// the timeline again afterwards opens it anew, with its metadata sent again.
+    pub fn close_timeline(&self, user_id: u64) {
+        let timeline = derive_timeline_id(&self.run_id.get(), user_id);
+        self.ingest_sender.forget_timeline_seq(timeline);
+        forget_announced_timelines();
+        let _ = self.ingest_sender.send(wrap_message(
+            Message::CloseTimeline,
+            timeline,
+            self.tick_fn.as_deref(),
+        ));
+    }
+
+    /// Send the events held by the [flight recorder](crate::Options::set_flight_recorder) to
+    /// modality now, e.g. when something's gone wrong that wasn't recorded as an error.
+    pub fn dump_flight_recorder(&self) {
+        let _ = self.ingest_sender.send(wrap_message(
+            Message::DumpFlightRecorder,
+            self.root_timeline,
+            self.tick_fn.as_deref(),
+        ));
+    }
+
+    /// How many messages have been dropped so far because the ingest's channel was full, see
+    /// [`Options::set_channel_capacity`](crate::Options::set_channel_capacity). This only grows,
+    /// a rising count means the ingest can't keep up with the program or modality.
+    pub fn dropped_messages(&self) -> u64 {
+        self.ingest_sender.dropped()
+            + self
+                .mirrors
+                .iter()
+                .map(|mirror| mirror.dropped_messages())
+                .sum::<u64>()
+    }
+
+    /// Where to ask this ingest, then each of its mirrors, to flush, in that order.
+    pub(crate) fn flush_requests(&self) -> Vec<FlushRequests> {
+        let mut requests = vec![self.flush_requests.clone()];
+        for mirror in &self.mirrors {
+            requests.extend(mirror.flush_requests());
+        }
+        requests
+    }
+
     /// Stop accepting new trace events, flush all existing events, and stop ingest thread.
     ///
     /// This function must be called at the end of your main thread to give the ingest thread a
     /// chance to flush all queued trace events out to modality.
-    pub async fn finish(mut self) {
+    pub async fn finish(mut self) -> ShutdownReport {
+        let start = Instant::now();
+        self.shutting_down.store(true, Ordering::Relaxed);
         if let Some(finish) = self.finish_sender.take() {
             let _ = finish.send(());
         }
 
-        if let Some(task) = self.task.take() {
-            let _ = task.await;
+        let report = match self.task.take() {
+            Some(task) => task.await.ok(),
+            None => None,
+        };
+        let mut report = report.unwrap_or_default();
+        report.dropped = self.ingest_sender.dropped();
+
+        // mirrors are only finished once this task has forwarded everything to them
+        for mirror in std::mem::take(&mut self.mirrors) {
+            let mirror_report = Box::pin(mirror.finish()).await;
+            report.flushed &= mirror_report.flushed;
+            report.dropped += mirror_report.dropped;
+        }
+
+        report.elapsed = start.elapsed();
+        report
+    }
+}
+
+/// The connection the ingest sends trace data over. Tests record what would have been sent instead.
+enum Client {
+    Modality(IngestClient<BoundTimelineState>),
+    #[cfg(test)]
+    Recording(tests::RecordingClient),
+}
+
+impl Client {
+    fn bound_timeline(&self) -> TimelineId {
+        match self {
+            Client::Modality(client) => client.bound_timeline(),
+            #[cfg(test)]
+            Client::Recording(client) => client.timeline,
+        }
+    }
+
+    async fn open_timeline(&mut self, id: TimelineId) -> Result<(), SdkIngestError> {
+        match self {
+            Client::Modality(client) => client.open_timeline(id).await,
+            #[cfg(test)]
+            Client::Recording(client) => {
+                client.timeline = id;
+                Ok(())
+            }
+        }
+    }
+
+    async fn declare_attr_key(&mut self, key: String) -> Result<InternedAttrKey, SdkIngestError> {
+        match self {
+            Client::Modality(client) => client.declare_attr_key(key).await,
+            #[cfg(test)]
+            Client::Recording(client) => Ok(client.declare_attr_key(key)),
+        }
+    }
+
+    async fn timeline_metadata(
+        &mut self,
+        attrs: Vec<(InternedAttrKey, AttrVal)>,
+    ) -> Result<(), SdkIngestError> {
+        match self {
+            Client::Modality(client) => client.timeline_metadata(attrs).await,
+            #[cfg(test)]
+            Client::Recording(client) => {
+                client.timeline_metadata(attrs);
+                Ok(())
+            }
+        }
+    }
+
+    async fn event(
+        &mut self,
+        ordering: u128,
+        attrs: Vec<(InternedAttrKey, AttrVal)>,
+    ) -> Result<(), SdkIngestError> {
+        match self {
+            Client::Modality(client) => client.event(ordering, attrs).await,
+            #[cfg(test)]
+            Client::Recording(client) => {
+                client.event(ordering, attrs);
+                Ok(())
+            }
+        }
+    }
+
+    async fn flush(&mut self) -> Result<(), SdkIngestError> {
+        match self {
+            Client::Modality(client) => client.flush().await,
+            #[cfg(test)]
+            Client::Recording(client) => client.flush(),
         }
     }
 }
 
 pub(crate) struct ModalityIngest {
-    client: IngestClient<BoundTimelineState>,
+    client: Client,
+    root_timeline: TimelineId,
+    run_id: RunId,
+    tick_fn: Option<Arc<TickFn>>,
+    /// Recorded as `event.internal.rs.clock_source`.
+    clock_source: String,
     global_metadata: Vec<(String, AttrVal)>,
-    event_keys: HashMap<String, InternedAttrKey>,
-    timeline_keys: HashMap<String, InternedAttrKey>,
-    span_names: HashMap<NonZeroU64, String>,
+    event_keys: FastHashMap<String, InternedAttrKey>,
+    /// The interned event keys that [`is_well_known_key`], kept when capping attributes.
+    well_known_keys: HashSet<InternedAttrKey>,
+    timeline_keys: FastHashMap<String, InternedAttrKey>,
+    span_names: FastHashMap<NonZeroU64, String>,
+    poll_coalescing: Option<Duration>,
+    /// Polls of each span not yet summarized, when coalescing polls.
+    busy_spans: FastHashMap<SpanId, BusySpan>,
+    /// The timeline each open span was created on, which with a timeline identifier isn't
+    /// necessarily the timeline of later messages about it.
+    span_timelines: FastHashMap<SpanId, TimelineId>,
+    announced_timelines: HashSet<TimelineId>,
+    /// Timelines from a timeline identifier or scope that haven't been closed, only tracked when
+    /// [timeline end markers](crate::Options::set_timeline_end_marker) are on, to end them on
+    /// shutdown. Thread timelines end with their thread instead.
+    open_user_timelines: Option<HashSet<TimelineId>>,
+    /// How many timelines have been announced, shared with the handle.
+    timeline_count: Arc<AtomicUsize>,
+    /// Every key declared so far, shared with the handle.
+    key_dictionary: KeyDictionary,
+    timeline_churn: TimelineChurn,
+    flight_recorder: Option<FlightRecorder>,
+    occurrence_counts: Option<OccurrenceCounts>,
+    /// Timelines that have had an event, and with it `event.internal.rs.schema_version`. One entry
+    /// per timeline, about 16 bytes each, kept until the timeline is closed. Thread timelines are
+    /// never closed.
+    versioned_timelines: HashSet<TimelineId>,
+    timeline_name_separator: Option<String>,
+    unnamed_event_name: Option<String>,
+    module_path_strip_prefix: Option<String>,
+    severity_fn: Option<Arc<SeverityFn>>,
+    component_fn: Option<Arc<ComponentFn>>,
+    flush_diagnostics: bool,
+    channel_capacity: Option<usize>,
+    on_ingest_exit: Option<Arc<IngestExitFn>>,
+    field_type_tags: bool,
+    wall_clock_timestamps: bool,
+    attr_vec_capacity: usize,
+    max_attrs_per_event: Option<usize>,
+    field_allowlist: Option<HashSet<String>>,
+    /// The approved event attribute keys, with their `event.` prefix.
+    approved_keys: Option<HashSet<String>>,
+    strict_approved_keys: bool,
+    /// Unapproved keys that have already been warned about.
+    unapproved_keys: HashSet<String>,
+    redactions: Vec<String>,
+    field_units: FastHashMap<String, String>,
+    unit_suffixes: bool,
+    /// Handlers by field name, built so later registrations replace earlier ones.
+    attr_handlers: FastHashMap<String, Arc<HandlerFn>>,
+    /// Handlers for some timelines, in the order they were given.
+    scoped_attr_handlers: Vec<(String, Arc<TimelinePredicate>, Arc<HandlerFn>)>,
+    /// What the timeline identifier said about each timeline, only kept while there are scoped
+    /// attribute handlers to consult it.
+    timeline_infos: FastHashMap<TimelineId, UserTimelineInfo>,
+    /// Ingest threads or tasks for additional server addresses, every message received is
+    /// forwarded to each of them.
+    mirrors: Vec<IngestSender>,
+    /// Zero is treated as no heartbeats, an interval can't tick that often.
+    heartbeat_interval: Option<Duration>,
+    /// When each timeline last had an event, only tracked for heartbeats. Uses tokio's clock, like
+    /// the heartbeat interval.
+    timeline_activity: FastHashMap<TimelineId, tokio::time::Instant>,
 
     #[cfg(feature = "blocking")]
     rt: Option<Runtime>,
@@ -202,11 +945,11 @@ impl ModalityIngest {
             .await
             .context("init ingest client")?;
 
-        let auth_key = options.auth.ok_or(ConnectError::AuthRequired)?;
+        let auth_key = options.auth.clone().ok_or(ConnectError::AuthRequired)?;
         let client = unauth_client
             .authenticate(auth_key)
             .await
-            .map_err(ConnectError::AuthFailed)?;
+            .map_err(ConnectError::from_auth_error)?;
 
         // open a timeline for the current thread because we need to open something to make the
         // types work
@@ -216,21 +959,95 @@ impl ModalityIngest {
             .await
             .context("open new timeline")?;
 
-        Ok(Self {
+        Ok(Self::new(Client::Modality(client), timeline_id, options))
+    }
+
+    /// An ingest sending over `client`, which is bound to the root timeline.
+    fn new(client: Client, root_timeline: TimelineId, options: Options) -> Self {
+        let (scoped_handlers, global_handlers): (Vec<_>, Vec<_>) = options
+            .attr_handlers
+            .into_iter()
+            .partition(|h| h.timelines.is_some());
+
+        Self {
             client,
+            root_timeline,
+            run_id: options.run_id,
+            clock_source: match (&options.tick_fn, options.clock_source) {
+                (None, _) => "system_monotonic".to_string(),
+                (Some(_), Some(name)) => name,
+                (Some(_), None) => "custom".to_string(),
+            },
+            tick_fn: options.tick_fn,
             global_metadata: options.metadata,
-            event_keys: HashMap::new(),
-            timeline_keys: HashMap::new(),
-            span_names: HashMap::new(),
+            event_keys: FastHashMap::default(),
+            well_known_keys: HashSet::new(),
+            timeline_keys: FastHashMap::default(),
+            span_names: FastHashMap::default(),
+            poll_coalescing: options.poll_coalescing,
+            busy_spans: FastHashMap::default(),
+            span_timelines: FastHashMap::default(),
+            announced_timelines: HashSet::new(),
+            open_user_timelines: options.timeline_end_marker.then(HashSet::new),
+            timeline_count: Arc::new(AtomicUsize::new(0)),
+            key_dictionary: KeyDictionary::default(),
+            timeline_churn: TimelineChurn::new(options.timeline_churn_threshold),
+            flight_recorder: options.flight_recorder.map(FlightRecorder::new),
+            occurrence_counts: options.occurrence_counts.map(OccurrenceCounts::new),
+            versioned_timelines: HashSet::new(),
+            timeline_name_separator: options.timeline_name_separator,
+            unnamed_event_name: options.unnamed_event_name,
+            module_path_strip_prefix: options.module_path_strip_prefix,
+            severity_fn: options.severity_fn,
+            component_fn: options.component_fn,
+            flush_diagnostics: options.flush_diagnostics,
+            channel_capacity: options.channel_capacity,
+            on_ingest_exit: options.on_ingest_exit,
+            field_type_tags: options.field_type_tags,
+            wall_clock_timestamps: options.wall_clock_timestamps,
+            attr_vec_capacity: options.attr_vec_capacity,
+            max_attrs_per_event: options.max_attrs_per_event,
+            field_allowlist: options.field_allowlist,
+            approved_keys: options
+                .approved_keys
+                .map(|keys| keys.into_iter().map(event_attr_key).collect()),
+            strict_approved_keys: options.strict_approved_keys,
+            unapproved_keys: HashSet::new(),
+            redactions: options.redactions,
+            field_units: options.field_units.into_iter().collect(),
+            unit_suffixes: options.unit_suffixes,
+            attr_handlers: global_handlers
+                .into_iter()
+                .map(|h| (h.key, h.handler))
+                .collect(),
+            scoped_attr_handlers: scoped_handlers
+                .into_iter()
+                .filter_map(|h| Some((h.key, h.timelines?, h.handler)))
+                .collect(),
+            timeline_infos: FastHashMap::default(),
+            heartbeat_interval: options.heartbeat_interval.filter(|i| !i.is_zero()),
+            timeline_activity: FastHashMap::default(),
+            mirrors: Vec::new(),
             #[cfg(feature = "blocking")]
             rt: None,
-        })
+        }
     }
 
+    /// Spawn the ingest thread, forwarding every message it receives to `mirrors` as well.
     #[cfg(feature = "blocking")]
-    pub(crate) fn spawn_thread(mut self) -> ModalityIngestThreadHandle {
-        let (sender, recv) = mpsc::unbounded_channel();
+    pub(crate) fn spawn_thread(
+        mut self,
+        mirrors: Vec<ModalityIngestThreadHandle>,
+    ) -> ModalityIngestThreadHandle {
+        self.mirrors = mirrors.iter().map(|m| m.ingest_sender.clone()).collect();
+        let (sender, recv) = ingest_channel(self.channel_capacity);
         let (finish_sender, finish_receiver) = oneshot::channel();
+        let (flush_requests, flush_receiver) = mpsc::unbounded_channel();
+        let root_timeline = self.root_timeline;
+        let run_id = self.run_id.clone();
+        let tick_fn = self.tick_fn.clone();
+        let timeline_count = self.timeline_count.clone();
+        let key_dictionary = self.key_dictionary.clone();
 
         let join_handle = thread::spawn(move || {
             // ensure this thread doesn't send trace events to the global dispatcher
@@ -238,65 +1055,235 @@ impl ModalityIngest {
 
             let rt = self.rt.take().unwrap_or_else(|| {
                 tokio::runtime::Builder::new_current_thread()
+                    .enable_time()
                     .build()
                     .expect("build local tokio current thread runtime")
             });
 
-            rt.block_on(self.handler_task(recv, finish_receiver))
+            let exit = self.exit_guard();
+            rt.block_on(self.handler_task(recv, finish_receiver, flush_receiver, exit))
         });
 
         ModalityIngestThreadHandle {
             ingest_sender: sender,
+            root_timeline,
+            run_id,
+            tick_fn,
+            timeline_count,
+            key_dictionary,
+            shutting_down: Arc::new(AtomicBool::new(false)),
             finish_sender: Some(finish_sender),
+            flush_requests,
             thread: Some(join_handle),
+            mirrors,
         }
     }
 
+    /// Spawn the ingest task, forwarding every message it receives to `mirrors` as well.
     #[cfg(feature = "async")]
-    pub(crate) async fn spawn_task(self) -> ModalityIngestTaskHandle {
-        let (ingest_sender, recv) = mpsc::unbounded_channel();
+    pub(crate) async fn spawn_task(
+        mut self,
+        mirrors: Vec<ModalityIngestTaskHandle>,
+    ) -> ModalityIngestTaskHandle {
+        self.mirrors = mirrors.iter().map(|m| m.ingest_sender.clone()).collect();
+        let (ingest_sender, recv) = ingest_channel(self.channel_capacity);
         let (finish_sender, finish_receiver) = oneshot::channel();
+        let (flush_requests, flush_receiver) = mpsc::unbounded_channel();
+        let root_timeline = self.root_timeline;
+        let run_id = self.run_id.clone();
+        let tick_fn = self.tick_fn.clone();
+        let timeline_count = self.timeline_count.clone();
+        let key_dictionary = self.key_dictionary.clone();
 
-        let task = tokio::spawn(self.handler_task(recv, finish_receiver));
+        // created up front so a task dropped before it first runs still reports being cancelled
+        let exit = self.exit_guard();
+        let task = tokio::spawn(self.handler_task(recv, finish_receiver, flush_receiver, exit));
 
         ModalityIngestTaskHandle {
             ingest_sender,
+            root_timeline,
+            run_id,
+            tick_fn,
+            timeline_count,
+            key_dictionary,
+            shutting_down: Arc::new(AtomicBool::new(false)),
             finish_sender: Some(finish_sender),
+            flush_requests,
             task: Some(task),
+            mirrors,
+        }
+    }
+
+    fn exit_guard(&self) -> ExitGuard {
+        ExitGuard {
+            callback: self.on_ingest_exit.clone(),
+            reason: None,
         }
     }
 
     async fn handler_task(
         mut self,
-        mut recv: UnboundedReceiver<WrappedMessage>,
+        mut recv: IngestReceiver,
         mut finish: oneshot::Receiver<()>,
-    ) {
-        loop {
+        mut flush_requests: UnboundedReceiver<oneshot::Sender<bool>>,
+        mut exit: ExitGuard,
+    ) -> ShutdownReport {
+        let mut heartbeat = self.heartbeat_interval.map(|period| {
+            let mut interval =
+                tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            interval
+        });
+
+        // the finish sender is only dropped without sending when the handle is dropped
+        let finished = loop {
             select! {
+                // `finish` is checked first, so everything still queued when it's called is
+                // counted as drained, and new messages last so a busy queue can't hold up the rest
+                biased;
+                res = &mut finish => {
+                    break res.is_ok()
+                },
+                Some(reply) = flush_requests.recv() => {
+                    // include everything queued before the request
+                    while let Ok(message) = recv.try_recv() {
+                        self.forward(&message);
+                        let _ = self.handle_packet(message).await;
+                    }
+                    let _ = reply.send(self.client.flush().await.is_ok());
+                },
+                _ = next_heartbeat(&mut heartbeat) => {
+                    let _ = self.send_heartbeats().await;
+                },
                 Some(message) = recv.recv() => {
+                    self.forward(&message);
                     let _ = self.handle_packet(message).await;
                 },
-                _ = &mut finish => {
-                    break
-                }
             }
-        }
+        };
+
+        let start = Instant::now();
 
         // close channel and drain existing messages
         recv.close();
+        let mut drained = 0;
         while let Some(message) = recv.recv().await {
+            drained += 1;
+            self.forward(&message);
+            let _ = self.handle_packet(message).await;
+        }
+
+        // timelines from a timeline identifier have no thread to end with, they end with the ingest
+        for timeline in self.open_user_timelines.take().unwrap_or_default() {
+            let message = wrap_message(Message::TimelineEnd, timeline, self.tick_fn.as_deref());
+            let _ = self.handle_packet(message).await;
+        }
+
+        if self.flush_diagnostics {
+            let message = wrap_message(
+                Message::FlushRequested,
+                self.root_timeline,
+                self.tick_fn.as_deref(),
+            );
+            let _ = self.handle_packet(message).await;
+        }
+
+        let flush_start = Instant::now();
+        let flush_result = self.client.flush().await;
+        let mut flushed = flush_result.is_ok();
+
+        if self.flush_diagnostics {
+            let elapsed = flush_start.elapsed();
+            let message = wrap_message(
+                Message::FlushCompleted { elapsed },
+                self.root_timeline,
+                self.tick_fn.as_deref(),
+            );
             let _ = self.handle_packet(message).await;
+            // the completion marker itself needs flushing out too
+            flushed &= self.client.flush().await.is_ok();
+        }
+
+        exit.reason = Some(match flush_result {
+            Err(e) => IngestExitReason::ClientError(e.to_string()),
+            Ok(()) if finished => IngestExitReason::Finished,
+            Ok(()) => IngestExitReason::ChannelClosed,
+        });
+
+        ShutdownReport {
+            drained,
+            // counted by the senders, the handle fills it in
+            dropped: 0,
+            discarded: self
+                .flight_recorder
+                .as_ref()
+                .map_or(0, FlightRecorder::discarded),
+            flushed,
+            elapsed: start.elapsed(),
+        }
+    }
+
+    /// Send a heartbeat on every timeline that hasn't had an event for the heartbeat interval.
+    async fn send_heartbeats(&mut self) -> Result<(), IngestError> {
+        let Some(interval) = self.heartbeat_interval else {
+            return Ok(());
+        };
+
+        let quiet: Vec<TimelineId> = self
+            .timeline_activity
+            .iter()
+            .filter(|(_, last_event)| last_event.elapsed() >= interval)
+            .map(|(timeline, _)| *timeline)
+            .collect();
+        for timeline in quiet {
+            let message = wrap_message(Message::Heartbeat, timeline, self.tick_fn.as_deref());
+            self.handle_packet(message).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Pass a message on to the mirrors. A mirror that has gone away doesn't affect the others.
+    fn forward(&self, message: &WrappedMessage) {
+        for mirror in &self.mirrors {
+            let _ = mirror.send(message.clone());
         }
-        let _ = self.client.flush().await;
     }
 
     async fn handle_packet(&mut self, message: WrappedMessage) -> Result<(), IngestError> {
+        let Some(recorder) = &mut self.flight_recorder else {
+            return self.process_packet(message).await;
+        };
+
+        // events are held back until an error, or a dump, shows they're worth sending
+        match &message.message {
+            Message::Event { metadata, .. } if *metadata.level() != Level::ERROR => {
+                recorder.hold(message);
+                Ok(())
+            }
+            Message::Event { .. } | Message::DumpFlightRecorder => {
+                for held in recorder.take() {
+                    self.process_packet(held).await?;
+                }
+                self.process_packet(message).await
+            }
+            _ => self.process_packet(message).await,
+        }
+    }
+
+    async fn process_packet(&mut self, message: WrappedMessage) -> Result<(), IngestError> {
         let WrappedMessage {
             message,
             tick,
             nanos_since_unix_epoch,
             timeline,
+            seq,
         } = message;
+        let nanos_since_unix_epoch = nanos_since_unix_epoch.filter(|_| self.wall_clock_timestamps);
+        let timeline = match message {
+            Message::LayerOverhead { .. } | Message::CallsiteSchema { .. } => self.root_timeline,
+            _ => timeline,
+        };
 
         if self.client.bound_timeline() != timeline {
             self.client
@@ -305,25 +1292,62 @@ impl ModalityIngest {
                 .context("open new timeline")?;
         }
 
+        if !matches!(message, Message::NewTimeline { .. }) && self.timeline_churn.message() {
+            eprintln!(
+                "warning: nearly every trace event is going to a new timeline, the timeline \
+                 identifier may not be returning the same user_id for the same timeline"
+            );
+        }
+
         match message {
-            Message::NewTimeline { name } => {
+            // timelines from a timeline identifier are announced by each thread that uses them
+            Message::NewTimeline {
+                name,
+                run_id,
+                user_id,
+                parent,
+            } if self.announced_timelines.insert(timeline) => {
+                if let (Some(user_id), false) = (user_id, self.scoped_attr_handlers.is_empty()) {
+                    self.timeline_infos
+                        .insert(timeline, UserTimelineInfo::new(name.clone(), user_id));
+                }
+
+                self.timeline_count.fetch_add(1, Ordering::Relaxed);
+                if run_id.is_some() {
+                    self.timeline_churn.new_timeline();
+                    if let Some(open) = &mut self.open_user_timelines {
+                        open.insert(timeline);
+                    }
+                }
+
                 let mut timeline_metadata = self.global_metadata.clone();
 
-                if !timeline_metadata.iter().any(|(k, _v)| k == "name") {
-                    timeline_metadata.push(("timeline.name".to_string(), name.into()));
+                // a derived timeline belongs to the run it was derived from, even if the run has
+                // since been rotated
+                if let Some(run_id) = run_id {
+                    set_run_id_metadata(&mut timeline_metadata, run_id);
                 }
 
-                for (key, value) in timeline_metadata {
-                    let timeline_key_name = self
-                        .get_or_create_timeline_attr_key(key)
-                        .await
-                        .context("get or define timeline attr key")?;
+                if let Some(parent) = parent {
+                    timeline_metadata.push((
+                        "timeline.parent".to_string(),
+                        AttrVal::TimelineId(Box::new(parent)),
+                    ));
+                }
 
-                    self.client
-                        .timeline_metadata([(timeline_key_name, value)])
-                        .await
-                        .context("apply timeline metadata")?;
+                if !timeline_metadata.iter().any(|(k, _v)| k == "name") {
+                    timeline_metadata.extend(self.timeline_name_metadata(name));
                 }
+
+                self.apply_timeline_metadata(timeline_metadata).await?;
+            }
+            Message::NewTimeline { .. } => {}
+            Message::NewRun { run_id } => {
+                set_run_id_metadata(&mut self.global_metadata, run_id);
+                self.client.flush().await.context("flush before new run")?;
+            }
+            Message::TimelineMetadata { metadata } => {
+                self.apply_timeline_metadata(metadata).await?;
             }
             Message::NewSpan {
                 id,
@@ -332,18 +1356,15 @@ impl ModalityIngest {
             } => {
                 let name = {
                     // store name for future use
-                    let name = records
-                        .get("name")
-                        .or_else(|| records.get("message"))
-                        .map(|n| format!("{:?}", n))
-                        .unwrap_or_else(|| metadata.name().to_string());
+                    let name = span_name(&records).unwrap_or_else(|| metadata.name().to_string());
 
                     self.span_names.insert(id, name.clone());
+                    self.span_timelines.insert(id, timeline);
 
                     name
                 };
 
-                let mut packed_attrs = Vec::new();
+                let mut packed_attrs = Vec::with_capacity(self.attr_vec_capacity);
 
                 packed_attrs.push((
                     self.get_or_create_event_attr_key("event.name".to_string())
@@ -371,66 +1392,150 @@ impl ModalityIngest {
                     span_id,
                 ));
 
+                // the span's own level, kept apart from `event.severity` so spans can be filtered
+                // by level without also matching events
+                packed_attrs.push((
+                    self.get_or_create_event_attr_key("event.internal.rs.span_level".to_string())
+                        .await?,
+                    format!("{}", metadata.level()).to_lowercase().into(),
+                ));
+
                 self.pack_common_attrs(
                     &mut packed_attrs,
                     metadata,
                     records,
+                    Box::default(),
                     tick,
                     nanos_since_unix_epoch,
                 )
                 .await?;
 
-                self.client
-                    .event(tick.as_nanos(), packed_attrs)
-                    .await
-                    .context("send packed event")?;
+                self.send_event(tick, seq, packed_attrs).await?;
             }
+            // span events can't be added to after being sent, so later records and follows from
+            // relationships are events of their own
             Message::Record { span, records } => {
-                // TODO: span events can't be added to after being sent, impl this once we can use
-                // timelines to represent spans
+                // a recorded `name`, or `message`, renames the span from here on, including this
+                // event
+                if let Some(name) = span_name(&records) {
+                    self.span_names.insert(span, name);
+                }
+
+                let name = self.span_names.get(&span).map(String::as_str);
+                let attrs = span_event_attrs(kind::SPAN_RECORD, span, name);
+
+                let mut packed_attrs = Vec::with_capacity(self.attr_vec_capacity);
+                for (key, attrval) in attrs {
+                    packed_attrs.push((self.get_or_create_event_attr_key(key).await?, attrval));
+                }
+                self.pack_records(&mut packed_attrs, records, Box::default())
+                    .await?;
+                self.pack_tick(&mut packed_attrs, tick).await?;
 
-                let _ = span;
-                let _ = records;
+                self.send_event(tick, seq, packed_attrs).await?;
             }
             Message::RecordFollowsFrom { span, follows } => {
-                // TODO: span events can't be added to after being sent, impl this once we can use
-                // timelines to represent spans
+                let name = self.span_names.get(&span).map(String::as_str);
+                let mut attrs = span_event_attrs(kind::SPAN_FOLLOWS_FROM, span, name);
+                attrs.push((
+                    "event.internal.rs.follows_span_id".to_string(),
+                    BigInt::new_attr_val(u64::from(follows).into()),
+                ));
+                // following a span from another timeline is an interaction between the two
+                if let Some(remote) = remote_timeline(&self.span_timelines, follows, timeline) {
+                    attrs.push((
+                        "event.interaction.remote_timeline_id".to_string(),
+                        AttrVal::TimelineId(Box::new(remote)),
+                    ));
+                }
 
-                let _ = span;
-                let _ = follows;
+                let mut packed_attrs = Vec::with_capacity(self.attr_vec_capacity);
+                for (key, attrval) in attrs {
+                    packed_attrs.push((self.get_or_create_event_attr_key(key).await?, attrval));
+                }
+                self.pack_tick(&mut packed_attrs, tick).await?;
+
+                self.send_event(tick, seq, packed_attrs).await?;
             }
             Message::Event {
                 metadata,
                 mut records,
+                context,
             } => {
-                let mut packed_attrs = Vec::new();
+                let mut packed_attrs = Vec::with_capacity(self.attr_vec_capacity);
 
                 let kind = records
                     .remove("modality.kind")
                     .map(tracing_value_to_attr_val)
-                    .unwrap_or_else(|| "event".into());
+                    .unwrap_or_else(|| kind::EVENT.into());
                 packed_attrs.push((
                     self.get_or_create_event_attr_key("event.internal.rs.kind".to_string())
                         .await?,
                     kind,
                 ));
 
-                self.pack_common_attrs(
-                    &mut packed_attrs,
-                    metadata,
-                    records,
-                    tick,
-                    nanos_since_unix_epoch,
+                if let Some(elapsed) = records.remove("modality.span_elapsed_ns") {
+                    packed_attrs.push((
+                        self.get_or_create_event_attr_key(
+                            "event.internal.rs.span_elapsed_ns".to_string(),
+                        )
+                        .await?,
+                        tracing_value_to_attr_val(elapsed),
+                    ));
+                }
+
+                if let Some(event_id) = records.remove("modality.event_id") {
+                    packed_attrs.push((
+                        self.get_or_create_event_attr_key("event.internal.rs.event_id".to_string())
+                            .await?,
+                        tracing_value_to_attr_val(event_id),
+                    ));
+                }
+
+                // a time in another logical clock domain, see `Options::set_clock_id`
+                if let Some(clock_id) = records.remove("modality.clock_id") {
+                    packed_attrs.push((
+                        self.get_or_create_event_attr_key("event.internal.rs.clock_id".to_string())
+                            .await?,
+                        tracing_value_to_attr_val(clock_id),
+                    ));
+                }
+
+                self.pack_common_attrs(
+                    &mut packed_attrs,
+                    metadata,
+                    records,
+                    context,
+                    tick,
+                    nanos_since_unix_epoch,
                 )
                 .await?;
 
-                self.client
-                    .event(tick.as_nanos(), packed_attrs)
-                    .await
-                    .context("send packed event")?;
+                self.send_event(tick, seq, packed_attrs).await?;
+            }
+            Message::Enter { span, .. } if self.poll_coalescing.is_some() => {
+                self.busy_spans
+                    .entry(span)
+                    .or_insert_with(|| BusySpan::new(tick))
+                    .entered = Some(tick);
+            }
+            Message::Exit { span } if self.poll_coalescing.is_some() => {
+                let window = self.poll_coalescing.unwrap_or_default();
+                let window_done = match self.busy_spans.get_mut(&span) {
+                    Some(busy) => {
+                        busy.exit(tick);
+                        tick.saturating_sub(busy.window_start) >= window
+                    }
+                    None => false,
+                };
+                if window_done {
+                    if let Some(busy) = self.busy_spans.remove(&span) {
+                        self.send_busy_event(span, busy, tick).await?;
+                    }
+                }
             }
-            Message::Enter { span } => {
-                let mut packed_attrs = Vec::new();
+            Message::Enter { span, records } => {
+                let mut packed_attrs = Vec::with_capacity(self.attr_vec_capacity);
 
                 {
                     // get stored span name
@@ -457,22 +1562,21 @@ impl ModalityIngest {
                     BigInt::new_attr_val(u64::from(span).into()),
                 ));
 
-                // only record tick directly during the first ~5.8 centuries this program is running
-                if let Ok(tick) = TryInto::<u64>::try_into(tick.as_nanos()) {
-                    packed_attrs.push((
-                        self.get_or_create_event_attr_key("event.internal.rs.tick".to_string())
-                            .await?,
-                        AttrVal::LogicalTime(LogicalTime::unary(tick)),
-                    ));
+                if let Some(mut records) = records {
+                    // the span's name, kind and ID are already recorded above or on its definition
+                    for key in ["name", "modality.kind", "modality.span_id"] {
+                        records.remove(key);
+                    }
+                    self.pack_records(&mut packed_attrs, records, Box::default())
+                        .await?;
                 }
 
-                self.client
-                    .event(tick.as_nanos(), packed_attrs)
-                    .await
-                    .context("send packed event")?;
+                self.pack_tick(&mut packed_attrs, tick).await?;
+
+                self.send_event(tick, seq, packed_attrs).await?;
             }
             Message::Exit { span } => {
-                let mut packed_attrs = Vec::new();
+                let mut packed_attrs = Vec::with_capacity(self.attr_vec_capacity);
 
                 {
                     // get stored span name
@@ -499,34 +1603,245 @@ impl ModalityIngest {
                     BigInt::new_attr_val(u64::from(span).into()),
                 ));
 
-                // only record tick directly during the first ~5.8 centuries this program is running
-                if let Ok(tick) = TryInto::<u64>::try_into(tick.as_nanos()) {
+                self.pack_tick(&mut packed_attrs, tick).await?;
+
+                self.send_event(tick, seq, packed_attrs).await?;
+            }
+            Message::Close {
+                span,
+                metadata,
+                records,
+                poll_count,
+            } => {
+                if let Some(busy) = self.busy_spans.remove(&span) {
+                    if busy.polls > 0 {
+                        self.send_busy_event(span, busy, tick).await?;
+                    }
+                }
+
+                let name = self.span_names.remove(&span);
+                self.span_timelines.remove(&span);
+
+                if let Some(mut records) = records {
+                    if let Some(name) = name {
+                        records.insert(
+                            "name".to_string(),
+                            TracingValue::String(format!("close: {}", name)),
+                        );
+                    }
+
+                    let mut packed_attrs = Vec::with_capacity(self.attr_vec_capacity);
+
+                    // the span's own kind applies to its defined event, not to this one
+                    records.remove("modality.kind");
                     packed_attrs.push((
-                        self.get_or_create_event_attr_key("event.internal.rs.tick".to_string())
+                        self.get_or_create_event_attr_key("event.internal.rs.kind".to_string())
                             .await?,
-                        AttrVal::LogicalTime(LogicalTime::unary(tick)),
+                        AttrVal::String("span:close".to_string().into()),
                     ));
-                }
 
-                self.client
-                    .event(tick.as_nanos(), packed_attrs)
-                    .await
-                    .context("send packed event")?;
+                    let span_id = records
+                        .remove("modality.span_id")
+                        .map(tracing_value_to_attr_val)
+                        .unwrap_or_else(|| BigInt::new_attr_val(u64::from(span) as i128));
+                    packed_attrs.push((
+                        self.get_or_create_event_attr_key("event.internal.rs.span_id".to_string())
+                            .await?,
+                        span_id,
+                    ));
+
+                    if let Some(poll_count) = poll_count {
+                        packed_attrs.push((
+                            self.get_or_create_event_attr_key(
+                                "event.internal.rs.poll_count".to_string(),
+                            )
+                            .await?,
+                            BigInt::new_attr_val(poll_count as i128),
+                        ));
+                    }
+
+                    self.pack_common_attrs(
+                        &mut packed_attrs,
+                        metadata,
+                        records,
+                        Box::default(),
+                        tick,
+                        nanos_since_unix_epoch,
+                    )
+                    .await?;
+
+                    self.send_event(tick, seq, packed_attrs).await?;
+                }
+            }
+            Message::TimelineEnd => {
+                self.send_marker_event("timeline:end", tick, nanos_since_unix_epoch, Vec::new())
+                    .await?;
+                self.timeline_activity.remove(&timeline);
+            }
+            Message::Heartbeat => {
+                // a heartbeat isn't activity, the timeline stays quiet until it has an event of
+                // its own
+                let last_event = self.timeline_activity.get(&timeline).copied();
+                self.send_marker_event(kind::HEARTBEAT, tick, nanos_since_unix_epoch, Vec::new())
+                    .await?;
+                if let Some(last_event) = last_event {
+                    self.timeline_activity.insert(timeline, last_event);
+                }
+            }
+            // only known timelines are closed, there's nothing to forget about the others
+            Message::CloseTimeline if self.announced_timelines.remove(&timeline) => {
+                self.send_marker_event("timeline:end", tick, nanos_since_unix_epoch, Vec::new())
+                    .await?;
+                self.versioned_timelines.remove(&timeline);
+                self.timeline_infos.remove(&timeline);
+                if let Some(open) = &mut self.open_user_timelines {
+                    open.remove(&timeline);
+                }
+                self.timeline_activity.remove(&timeline);
+            }
+            Message::CloseTimeline | Message::DumpFlightRecorder => {}
+            Message::FlushRequested => {
+                self.send_marker_event(
+                    "internal.rs.flush_requested",
+                    tick,
+                    nanos_since_unix_epoch,
+                    Vec::new(),
+                )
+                .await?;
+            }
+            Message::FlushCompleted { elapsed } => {
+                let elapsed_attr = (
+                    self.get_or_create_event_attr_key(
+                        "event.internal.rs.flush_elapsed".to_string(),
+                    )
+                    .await?,
+                    BigInt::new_attr_val(elapsed.as_nanos() as i128),
+                );
+                self.send_marker_event(
+                    "internal.rs.flush_completed",
+                    tick,
+                    nanos_since_unix_epoch,
+                    vec![elapsed_attr],
+                )
+                .await?;
+            }
+            Message::LayerOverhead { p50, p99 } => {
+                let mut attrs = Vec::with_capacity(2);
+                for (key, value) in [("p50", p50), ("p99", p99)] {
+                    attrs.push((
+                        self.get_or_create_event_attr_key(format!(
+                            "event.internal.rs.layer_overhead_ns.{key}"
+                        ))
+                        .await?,
+                        BigInt::new_attr_val(value.as_nanos() as i128),
+                    ));
+                }
+                self.send_marker_event(
+                    "internal.rs.layer_overhead",
+                    tick,
+                    nanos_since_unix_epoch,
+                    attrs,
+                )
+                .await?;
             }
-            Message::Close { span } => {
-                self.span_names.remove(&span);
+            Message::CallsiteSchema { metadata } => {
+                let fields: Vec<&str> = metadata.fields().iter().map(|f| f.name()).collect();
+                let kind = if metadata.is_span() { "span" } else { "event" };
+                let schema = [
+                    ("name", metadata.name().to_string()),
+                    ("target", metadata.target().to_string()),
+                    ("kind", kind.to_string()),
+                    ("fields", fields.join(",")),
+                ];
+
+                let mut attrs = Vec::with_capacity(schema.len());
+                for (key, value) in schema {
+                    attrs.push((
+                        self.get_or_create_event_attr_key(format!(
+                            "event.internal.rs.callsite.{key}"
+                        ))
+                        .await?,
+                        AttrVal::String(value.into()),
+                    ));
+                }
+                self.send_marker_event(
+                    "internal.rs.callsite_schema",
+                    tick,
+                    nanos_since_unix_epoch,
+                    attrs,
+                )
+                .await?;
             }
             Message::IdChange { old, new } => {
                 let name = self.span_names.get(&old).cloned();
                 if let Some(name) = name {
                     self.span_names.insert(new, name);
                 }
+                if let Some(span_timeline) = self.span_timelines.get(&old).copied() {
+                    self.span_timelines.insert(new, span_timeline);
+                }
+                if let Some(busy) = self.busy_spans.remove(&old) {
+                    self.busy_spans.insert(new, busy);
+                }
             }
         }
 
         Ok(())
     }
 
+    /// Apply metadata to the currently bound timeline.
+    async fn apply_timeline_metadata(
+        &mut self,
+        metadata: Vec<(String, AttrVal)>,
+    ) -> Result<(), IngestError> {
+        for (key, value) in metadata {
+            let timeline_key_name = self
+                .get_or_create_timeline_attr_key(key)
+                .await
+                .context("get or define timeline attr key")?;
+
+            self.client
+                .timeline_metadata(vec![(timeline_key_name, value)])
+                .await
+                .context("apply timeline metadata")?;
+        }
+
+        Ok(())
+    }
+
+    /// Build the name metadata for a timeline, splitting it into path components if a separator was
+    /// configured.
+    fn timeline_name_metadata(&self, name: String) -> Vec<(String, AttrVal)> {
+        let separator = match self.timeline_name_separator.as_deref() {
+            Some(separator) if !separator.is_empty() && name.contains(separator) => separator,
+            _ => return vec![("timeline.name".to_string(), name.into())],
+        };
+
+        let mut metadata: Vec<(String, AttrVal)> = name
+            .split(separator)
+            .enumerate()
+            .map(|(i, component)| (format!("timeline.path.{i}"), component.into()))
+            .collect();
+
+        let leaf = name
+            .rsplit(separator)
+            .next()
+            .unwrap_or_default()
+            .to_string();
+        metadata.push(("timeline.name".to_string(), leaf.into()));
+        metadata.push(("timeline.name.full".to_string(), name.into()));
+
+        metadata
+    }
+
+    /// Add a newly declared key to the dictionary the handle exports.
+    fn record_key(&self, key: &str, interned_key: InternedAttrKey) {
+        self.key_dictionary
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push((key.to_string(), u32::from(interned_key).into()));
+    }
+
     async fn get_or_create_timeline_attr_key(
         &mut self,
         key: String,
@@ -541,6 +1856,7 @@ impl ModalityIngest {
             .await
             .context("define timeline attr key")?;
 
+        self.record_key(&key, interned_key);
         self.timeline_keys.insert(key, interned_key);
 
         Ok(interned_key)
@@ -550,11 +1866,7 @@ impl ModalityIngest {
         &mut self,
         key: String,
     ) -> Result<InternedAttrKey, IngestError> {
-        let key = if key.starts_with("event.") {
-            key
-        } else {
-            format!("event.{key}")
-        };
+        let key = event_attr_key(key);
 
         if let Some(id) = self.event_keys.get(&key) {
             return Ok(*id);
@@ -566,16 +1878,223 @@ impl ModalityIngest {
             .await
             .context("define event attr key")?;
 
+        self.record_key(&key, interned_key);
+        if is_well_known_key(&key) {
+            self.well_known_keys.insert(interned_key);
+        }
         self.event_keys.insert(key, interned_key);
 
         Ok(interned_key)
     }
 
+    /// Whether an attribute with this key may be sent, warning the first time an unapproved key is
+    /// seen.
+    fn is_approved_key(&mut self, key: &str) -> bool {
+        let Some(approved_keys) = &self.approved_keys else {
+            return true;
+        };
+
+        let key = event_attr_key(key.to_string());
+        if approved_keys.contains(&key) {
+            return true;
+        }
+
+        if !self.unapproved_keys.contains(&key) {
+            eprintln!(
+                "warning: attribute `{}` is not an approved key, {}",
+                key,
+                if self.strict_approved_keys {
+                    "dropping it"
+                } else {
+                    "sending it anyway"
+                }
+            );
+            self.unapproved_keys.insert(key);
+        }
+
+        !self.strict_approved_keys
+    }
+
+    /// The unit to record for a field, if it was given one or is named with a unit suffix.
+    fn field_unit(&self, name: &str) -> Option<String> {
+        if let Some(unit) = self.field_units.get(name) {
+            return Some(unit.clone());
+        }
+        if !self.unit_suffixes {
+            return None;
+        }
+        UNIT_SUFFIXES
+            .iter()
+            .find(|(suffix, _)| name.len() > suffix.len() && name.ends_with(suffix))
+            .map(|(_, unit)| unit.to_string())
+    }
+
+    /// Whether a field's value should be replaced, i.e. its name contains one of the configured
+    /// redaction patterns, ignoring case.
+    fn is_redacted(&self, name: &str) -> bool {
+        if self.redactions.is_empty() {
+            return false;
+        }
+
+        let name = name.to_lowercase();
+        self.redactions
+            .iter()
+            .any(|pattern| name.contains(pattern.as_str()))
+    }
+
+    /// Send a `span:busy` summary of a span's polls.
+    async fn send_busy_event(
+        &mut self,
+        span: SpanId,
+        busy: BusySpan,
+        tick: Duration,
+    ) -> Result<(), IngestError> {
+        let name = self.span_names.get(&span).map(String::as_str);
+        let mut attrs = span_event_attrs(kind::SPAN_BUSY, span, name);
+        attrs.push((
+            "event.internal.rs.poll_count".to_string(),
+            BigInt::new_attr_val(busy.polls.into()),
+        ));
+        attrs.push((
+            "event.internal.rs.busy_ns".to_string(),
+            BigInt::new_attr_val(busy.busy.as_nanos() as i128),
+        ));
+
+        let mut packed_attrs = Vec::with_capacity(self.attr_vec_capacity);
+        for (key, attrval) in attrs {
+            packed_attrs.push((self.get_or_create_event_attr_key(key).await?, attrval));
+        }
+        self.pack_tick(&mut packed_attrs, tick).await?;
+
+        self.send_event(tick, None, packed_attrs).await
+    }
+
+    /// Send an event that marks something the layer itself did, rather than something traced, with
+    /// `kind` as both its name and `event.internal.rs.kind`.
+    async fn send_marker_event(
+        &mut self,
+        kind: &str,
+        tick: Duration,
+        nanos_since_unix_epoch: Option<Nanoseconds>,
+        mut packed_attrs: Vec<(InternedAttrKey, AttrVal)>,
+    ) -> Result<(), IngestError> {
+        packed_attrs.push((
+            self.get_or_create_event_attr_key("event.name".to_string())
+                .await?,
+            AttrVal::String(kind.to_string().into()),
+        ));
+        packed_attrs.push((
+            self.get_or_create_event_attr_key("event.internal.rs.kind".to_string())
+                .await?,
+            AttrVal::String(kind.to_string().into()),
+        ));
+
+        if let Some(nanos_since_unix_epoch) = nanos_since_unix_epoch {
+            packed_attrs.push((
+                self.get_or_create_event_attr_key("event.timestamp".into())
+                    .await?,
+                AttrVal::Timestamp(nanos_since_unix_epoch),
+            ));
+        }
+
+        self.send_event(tick, None, packed_attrs).await
+    }
+
+    /// Send an event on the bound timeline, tagged with `seq` as its
+    /// `event.internal.rs.timeline_seq` if the layer numbered it.
+    ///
+    /// Repeated attribute keys are collapsed first so modality never sees the same key twice on
+    /// one event. The last value for a key wins.
+    async fn send_event(
+        &mut self,
+        tick: Duration,
+        seq: Option<u64>,
+        mut packed_attrs: Vec<(InternedAttrKey, AttrVal)>,
+    ) -> Result<(), IngestError> {
+        if let Some(truncated) = self.max_attrs_per_event.and_then(|max| {
+            cap_attrs(&mut packed_attrs, max, |(key, _)| {
+                self.well_known_keys.contains(key)
+            })
+        }) {
+            packed_attrs.push((
+                self.get_or_create_event_attr_key("event.internal.rs.attrs_truncated".to_string())
+                    .await?,
+                truncated,
+            ));
+        }
+
+        if self.heartbeat_interval.is_some() {
+            self.timeline_activity
+                .insert(self.client.bound_timeline(), tokio::time::Instant::now());
+        }
+
+        if let Some(seq) = seq {
+            packed_attrs.push((
+                self.get_or_create_event_attr_key("event.internal.rs.timeline_seq".to_string())
+                    .await?,
+                BigInt::new_attr_val(seq.into()),
+            ));
+        }
+
+        // the first event on each timeline says which attribute conventions the rest follow
+        if self
+            .versioned_timelines
+            .insert(self.client.bound_timeline())
+        {
+            packed_attrs.push((
+                self.get_or_create_event_attr_key("event.internal.rs.schema_version".to_string())
+                    .await?,
+                AttrVal::Integer(crate::SCHEMA_VERSION.into()),
+            ));
+        }
+
+        let (packed_attrs, collisions) = dedup_attrs(packed_attrs);
+
+        if let (true, Some(key)) = (cfg!(debug_assertions), collisions.first()) {
+            static COLLISION_LATCH: Once = Once::new();
+            COLLISION_LATCH.call_once(|| {
+                let name = self
+                    .event_keys
+                    .iter()
+                    .find(|(_name, k)| *k == key)
+                    .map(|(name, _k)| name.as_str())
+                    .unwrap_or("<unknown>");
+                eprintln!(
+                    "warning: attribute `{}` was set more than once on one event, keeping the \
+                     last value. Later collisions aren't reported",
+                    name
+                );
+            });
+        }
+
+        self.client
+            .event(tick.as_nanos(), packed_attrs)
+            .await
+            .context("send packed event")?;
+
+        Ok(())
+    }
+
+    /// Pack the tick as `event.internal.rs.tick`, see [`tick_logical_time`].
+    async fn pack_tick(
+        &mut self,
+        packed_attrs: &mut Vec<(InternedAttrKey, AttrVal)>,
+        tick: Duration,
+    ) -> Result<(), IngestError> {
+        packed_attrs.push((
+            self.get_or_create_event_attr_key("event.internal.rs.tick".to_string())
+                .await?,
+            AttrVal::LogicalTime(tick_logical_time(tick)),
+        ));
+        Ok(())
+    }
+
     async fn pack_common_attrs<'a>(
         &mut self,
         packed_attrs: &mut Vec<(InternedAttrKey, AttrVal)>,
         metadata: &'a Metadata<'static>,
         mut records: RecordMap,
+        context: Box<[(String, AttrVal)]>,
         tick: Duration,
         maybe_nanos_since_unix_epoch: Option<Nanoseconds>,
     ) -> Result<(), IngestError> {
@@ -583,27 +2102,63 @@ impl ModalityIngest {
             .remove("name")
             .or_else(|| records.remove("message"))
             .map(tracing_value_to_attr_val)
-            .unwrap_or_else(|| metadata.name().into());
+            .unwrap_or_else(|| match &self.unnamed_event_name {
+                Some(unnamed) if metadata.is_event() => unnamed.as_str().into(),
+                _ => metadata.name().into(),
+            });
         packed_attrs.push((
             self.get_or_create_event_attr_key("event.name".to_string())
                 .await?,
-            name,
+            name.clone(),
         ));
 
+        let occurrence = match &mut self.occurrence_counts {
+            Some(counts) if metadata.is_event() => Some(counts.count(name.to_string())),
+            _ => None,
+        };
+        if let Some(occurrence) = occurrence {
+            packed_attrs.push((
+                self.get_or_create_event_attr_key("event.internal.rs.occurrence".to_string())
+                    .await?,
+                BigInt::new_attr_val(occurrence.into()),
+            ));
+        }
+
         let severity = records
             .remove("severity")
             .map(tracing_value_to_attr_val)
-            .unwrap_or_else(|| format!("{}", metadata.level()).to_lowercase().into());
+            .unwrap_or_else(|| match &self.severity_fn {
+                Some(severity_fn) => severity_fn(metadata.level()),
+                None => format!("{}", metadata.level()).to_lowercase().into(),
+            });
         packed_attrs.push((
             self.get_or_create_event_attr_key("event.severity".to_string())
                 .await?,
             severity,
         ));
 
+        // an explicit `component` field is packed with the other fields
+        let component = match &self.component_fn {
+            Some(component_fn) if !records.contains_key("component") => component_fn(metadata),
+            _ => None,
+        };
+        if let Some(component) = component {
+            packed_attrs.push((
+                self.get_or_create_event_attr_key("event.component".to_string())
+                    .await?,
+                AttrVal::String(component.into()),
+            ));
+        }
+
         let module_path = records
             .remove("source.module")
             .map(tracing_value_to_attr_val)
-            .or_else(|| metadata.module_path().map(|mp| mp.into()));
+            .or_else(|| {
+                metadata
+                    .module_path()
+                    .map(|mp| strip_module_prefix(mp, self.module_path_strip_prefix.as_deref()))
+                    .map(|mp| mp.into())
+            });
         if let Some(module_path) = module_path {
             packed_attrs.push((
                 self.get_or_create_event_attr_key("event.source.module".to_string())
@@ -636,70 +2191,17 @@ impl ModalityIngest {
             ));
         }
 
-        // only record tick directly during the first ~5.8 centuries this program is running
-        if let Ok(tick) = TryInto::<u64>::try_into(tick.as_nanos()) {
-            packed_attrs.push((
-                self.get_or_create_event_attr_key("event.internal.rs.tick".to_string())
-                    .await?,
-                AttrVal::LogicalTime(LogicalTime::unary(tick)),
-            ));
-        }
-
-        // handle manually to type the AttrVal correctly
-        let remote_timeline_id = records
-            .remove("interaction.remote_timeline_id")
-            .map(tracing_value_to_attr_val);
-        if let Some(attrval) = remote_timeline_id {
-            let remote_timeline_id = if let AttrVal::String(string) = attrval {
-                use std::str::FromStr;
-                if let Ok(uuid) = Uuid::from_str(&string) {
-                    AttrVal::TimelineId(Box::new(uuid.into()))
-                } else {
-                    AttrVal::String(string)
-                }
-            } else {
-                attrval
-            };
-
-            packed_attrs.push((
-                self.get_or_create_event_attr_key("event.interaction.remote_timeline_id".into())
-                    .await?,
-                remote_timeline_id,
-            ));
-        }
-
-        // Manually retype the remote_timestamp
-        let remote_timestamp = records
-            .remove("interaction.remote_timestamp")
-            .map(tracing_value_to_attr_val);
-        if let Some(attrval) = remote_timestamp {
-            let remote_timestamp = match attrval {
-                AttrVal::Integer(i) if i >= 0 => AttrVal::Timestamp(Nanoseconds::from(i as u64)),
-                AttrVal::BigInt(i) if *i >= 0 && *i <= u64::MAX as i128 => {
-                    AttrVal::Timestamp(Nanoseconds::from(*i as u64))
-                }
-                AttrVal::Timestamp(t) => AttrVal::Timestamp(t),
-                x => x,
-            };
-
-            packed_attrs.push((
-                self.get_or_create_event_attr_key("event.interaction.remote_timestamp".into())
-                    .await?,
-                remote_timestamp,
-            ));
-        }
+        self.pack_tick(packed_attrs, tick).await?;
+        packed_attrs.push((
+            self.get_or_create_event_attr_key("event.internal.rs.clock_source".to_string())
+                .await?,
+            AttrVal::String(self.clock_source.clone().into()),
+        ));
 
         // Manually retype the local timestamp
         let local_timestamp = records.remove("timestamp").map(tracing_value_to_attr_val);
         if let Some(attrval) = local_timestamp {
-            let remote_timestamp = match attrval {
-                AttrVal::Integer(i) if i >= 0 => AttrVal::Timestamp(Nanoseconds::from(i as u64)),
-                AttrVal::BigInt(i) if *i >= 0 && *i <= u64::MAX as i128 => {
-                    AttrVal::Timestamp(Nanoseconds::from(*i as u64))
-                }
-                AttrVal::Timestamp(t) => AttrVal::Timestamp(t),
-                x => x,
-            };
+            let remote_timestamp = coerce_timestamp(attrval);
 
             packed_attrs.push((
                 self.get_or_create_event_attr_key("event.timestamp".into())
@@ -714,29 +2216,1819 @@ impl ModalityIngest {
             ));
         }
 
-        // pack any remaining records
-        for (name, value) in records {
-            let attrval = tracing_value_to_attr_val(value);
-
-            let key = if name.starts_with("event.") {
-                name.to_string()
-            } else {
-                format!("event.{}", name.as_str())
-            };
+        self.pack_records(packed_attrs, records, context).await
+    }
 
-            packed_attrs.push((self.get_or_create_event_attr_key(key).await?, attrval));
+    /// The last given timeline-scoped handler for `name` that matches the current timeline.
+    fn scoped_attr_handler(&self, name: &str) -> Option<Arc<HandlerFn>> {
+        if self.scoped_attr_handlers.is_empty() {
+            return None;
         }
-
-        Ok(())
+        let info = self.timeline_infos.get(&self.client.bound_timeline())?;
+        self.scoped_attr_handlers
+            .iter()
+            .rev()
+            .find(|(key, timelines, _)| key == name && timelines(info))
+            .map(|(_, _, handler)| handler.clone())
     }
-}
 
-fn tracing_value_to_attr_val(value: TracingValue) -> AttrVal {
-    match value {
-        TracingValue::String(s) => s.into(),
-        TracingValue::F64(n) => n.into(),
-        TracingValue::I64(n) => n.into(),
-        TracingValue::U64(n) => (n as i128).into(),
-        TracingValue::Bool(b) => b.into(),
+    /// Pack fields as `event.<field>` attributes, applying the allowlist, redactions, attribute
+    /// handlers, and approved keys, in that order. See the `attr_handlers` module docs.
+    ///
+    /// `context` from the [context provider](crate::Options::set_context_provider) goes through
+    /// the same steps, except attribute handlers since its values are already attribute values,
+    /// and is skipped where `records` has a field by the same name.
+    async fn pack_records(
+        &mut self,
+        packed_attrs: &mut Vec<(InternedAttrKey, AttrVal)>,
+        records: RecordMap,
+        context: Box<[(String, AttrVal)]>,
+    ) -> Result<(), IngestError> {
+        let context: Vec<_> = context
+            .into_vec()
+            .into_iter()
+            .filter(|(key, _)| !records.contains_key(key))
+            .map(|(key, value)| (key, Field::Context(value)))
+            .collect();
+        let fields = records
+            .into_iter()
+            .map(|(name, value)| (name, Field::Captured(value)))
+            .chain(context);
+
+        for (name, value) in fields {
+            let handler = match value {
+                Field::Captured(_) => self
+                    .scoped_attr_handler(&name)
+                    .or_else(|| self.attr_handlers.get(&name).cloned()),
+                Field::Context(_) => None,
+            };
+
+            // interactions are how events link up, they're always kept
+            if let Some(allowlist) = &self.field_allowlist {
+                if !allowlist.contains(&name) && !name.starts_with("interaction.") {
+                    continue;
+                }
+            }
+
+            // a handler's keys are checked as it returns them
+            let redacted = self.is_redacted(&name);
+            if (handler.is_none() || redacted) && !self.is_approved_key(&name) {
+                continue;
+            }
+
+            if let (true, Field::Captured(value)) = (self.field_type_tags, &value) {
+                let key = format!("{name}.__type");
+                if self.is_approved_key(&key) {
+                    packed_attrs.push((
+                        self.get_or_create_event_attr_key(key).await?,
+                        value.type_name().into(),
+                    ));
+                }
+            }
+
+            let attrval = match (value, handler) {
+                _ if redacted => AttrVal::String("<redacted>".to_string().into()),
+                (Field::Captured(value), Some(handler)) => {
+                    for (key, attrval) in handler(value) {
+                        if !self.is_approved_key(&key) {
+                            continue;
+                        }
+                        packed_attrs.push((self.get_or_create_event_attr_key(key).await?, attrval));
+                    }
+                    continue;
+                }
+                (Field::Captured(value), None) => tracing_value_to_attr_val(value),
+                (Field::Context(value), _) => value,
+            };
+
+            // a redacted value is a string by now, so it doesn't get a unit either
+            let numeric = matches!(
+                attrval,
+                AttrVal::Integer(_) | AttrVal::BigInt(_) | AttrVal::Float(_)
+            );
+            if let Some(unit) = self.field_unit(&name).filter(|_| numeric) {
+                let key = format!("{name}.unit");
+                if self.is_approved_key(&key) {
+                    packed_attrs.push((
+                        self.get_or_create_event_attr_key(key).await?,
+                        AttrVal::String(unit.into()),
+                    ));
+                }
+            }
+
+            packed_attrs.push((self.get_or_create_event_attr_key(name).await?, attrval));
+        }
+
+        Ok(())
+    }
+}
+
+/// A value for [`ModalityIngest::pack_records`] to pack.
+enum Field {
+    /// Captured from the trace event's fields.
+    Captured(TracingValue),
+    /// From the context provider.
+    Context(AttrVal),
+}
+
+/// A span's name from its `name` field, or `message` if it has no `name`, the same rule as
+/// `event.name`.
+fn span_name(records: &RecordMap) -> Option<String> {
+    let name = records.get("name").or_else(|| records.get("message"))?;
+    Some(match name {
+        TracingValue::String(s) => s.clone(),
+        value => tracing_value_to_attr_val(value.clone()).to_string(),
+    })
+}
+
+/// The name, kind, and span ID of an event about a span with the kind `span:<label>`, named
+/// `<label>: <span name>` if the span's name is known.
+fn span_event_attrs(
+    kind: &'static str,
+    span: SpanId,
+    span_name: Option<&str>,
+) -> Vec<(String, AttrVal)> {
+    let label = kind.strip_prefix("span:").unwrap_or(kind);
+
+    let mut attrs = Vec::with_capacity(3);
+    if let Some(span_name) = span_name {
+        attrs.push((
+            "event.name".to_string(),
+            format!("{label}: {span_name}").into(),
+        ));
+    }
+    attrs.push(("event.internal.rs.kind".to_string(), kind.into()));
+    attrs.push((
+        "event.internal.rs.span_id".to_string(),
+        BigInt::new_attr_val(u64::from(span).into()),
+    ));
+    attrs
+}
+
+/// The tick as a logical time. Ticks that don't fit in a `u64`, after ~584 years, spill into a
+/// second component rather than being dropped, so they still order after every earlier tick.
+fn tick_logical_time(tick: Duration) -> LogicalTime {
+    let nanos = tick.as_nanos();
+    match u64::try_from(nanos) {
+        Ok(nanos) => LogicalTime::unary(nanos),
+        Err(_) => LogicalTime::binary((nanos >> 64) as u64, nanos as u64),
+    }
+}
+
+/// Whether an event attribute key is one this crate records itself, rather than a field. These are
+/// never dropped by [`cap_attrs`], wherever they were packed.
+fn is_well_known_key(key: &str) -> bool {
+    const KEYS: [&str; 4] = [
+        "event.name",
+        "event.severity",
+        "event.timestamp",
+        "event.component",
+    ];
+    const PREFIXES: [&str; 3] = ["event.internal.rs.", "event.interaction.", "event.source."];
+
+    KEYS.contains(&key) || PREFIXES.iter().any(|prefix| key.starts_with(prefix))
+}
+
+/// Drop attributes so there are at most `max`, returning the `event.internal.rs.attrs_truncated`
+/// value if any were dropped. Only attributes that aren't `well_known` are dropped, the last ones
+/// first, so an event with more well-known attributes than `max` keeps all of them.
+fn cap_attrs<T>(
+    attrs: &mut Vec<T>,
+    max: usize,
+    well_known: impl Fn(&T) -> bool,
+) -> Option<AttrVal> {
+    if attrs.len() <= max {
+        return None;
+    }
+
+    let mut fields_left = max.saturating_sub(attrs.iter().filter(|a| well_known(a)).count());
+    let before = attrs.len();
+    attrs.retain(|attr| {
+        if well_known(attr) {
+            return true;
+        }
+        let keep = fields_left > 0;
+        fields_left = fields_left.saturating_sub(1);
+        keep
+    });
+
+    let dropped = before - attrs.len();
+    (dropped > 0).then_some(AttrVal::Integer(dropped as i64))
+}
+
+/// The timeline `span` was created on, if that isn't `timeline`.
+fn remote_timeline(
+    span_timelines: &FastHashMap<SpanId, TimelineId>,
+    span: SpanId,
+    timeline: TimelineId,
+) -> Option<TimelineId> {
+    span_timelines
+        .get(&span)
+        .copied()
+        .filter(|span_timeline| *span_timeline != timeline)
+}
+
+/// A span's polls since its last `span:busy` summary.
+#[derive(Debug)]
+struct BusySpan {
+    /// When the first poll in this summary started.
+    window_start: Duration,
+    /// When the current poll started, if the span is entered.
+    entered: Option<Duration>,
+    polls: u64,
+    busy: Duration,
+}
+
+impl BusySpan {
+    fn new(window_start: Duration) -> Self {
+        BusySpan {
+            window_start,
+            entered: None,
+            polls: 0,
+            busy: Duration::ZERO,
+        }
+    }
+
+    fn exit(&mut self, tick: Duration) {
+        if let Some(entered) = self.entered.take() {
+            self.polls += 1;
+            self.busy += tick.saturating_sub(entered);
+        }
+    }
+}
+
+/// Watches for a timeline identifier that gives nearly every message a new timeline, which is
+/// almost certainly a bug in the identifier and makes every message pay for a timeline's metadata.
+#[derive(Debug)]
+struct TimelineChurn {
+    threshold: Option<f64>,
+    messages: u32,
+    new_timelines: u32,
+    warned: bool,
+}
+
+impl TimelineChurn {
+    /// How many messages the new timeline rate is measured over.
+    const WINDOW: u32 = 1000;
+
+    fn new(threshold: Option<f64>) -> Self {
+        TimelineChurn {
+            threshold,
+            messages: 0,
+            new_timelines: 0,
+            warned: false,
+        }
+    }
+
+    /// A timeline from a timeline identifier was seen for the first time.
+    fn new_timeline(&mut self) {
+        self.new_timelines += 1;
+    }
+
+    /// A message was sent, other than a new timeline. Returns `true` the first time a window ends
+    /// with more new timelines per message than the threshold, the caller should warn then.
+    fn message(&mut self) -> bool {
+        let Some(threshold) = self.threshold.filter(|_| !self.warned) else {
+            return false;
+        };
+
+        self.messages += 1;
+        if self.messages < Self::WINDOW {
+            return false;
+        }
+
+        let rate = f64::from(self.new_timelines) / f64::from(self.messages);
+        self.messages = 0;
+        self.new_timelines = 0;
+        self.warned = rate > threshold;
+        self.warned
+    }
+}
+
+/// The last events below `error` level, held back from modality until an error event needs them
+/// for context.
+#[derive(Debug)]
+struct FlightRecorder {
+    capacity: usize,
+    held: VecDeque<WrappedMessage>,
+    /// Events dropped to make room for newer ones.
+    dropped: u64,
+}
+
+impl FlightRecorder {
+    fn new(capacity: usize) -> Self {
+        FlightRecorder {
+            capacity,
+            held: VecDeque::with_capacity(capacity),
+            dropped: 0,
+        }
+    }
+
+    /// Hold on to an event, dropping the oldest one held if full.
+    fn hold(&mut self, message: WrappedMessage) {
+        if self.capacity == 0 {
+            self.dropped += 1;
+            return;
+        }
+        if self.held.len() == self.capacity {
+            self.held.pop_front();
+            self.dropped += 1;
+        }
+        self.held.push_back(message);
+    }
+
+    /// How many events were never sent, those dropped so far and those still held.
+    fn discarded(&self) -> u64 {
+        self.dropped + self.held.len() as u64
+    }
+
+    /// Take every held event, oldest first.
+    fn take(&mut self) -> VecDeque<WrappedMessage> {
+        std::mem::take(&mut self.held)
+    }
+}
+
+/// How many times each event name has been recorded, for `event.internal.rs.occurrence`.
+///
+/// Holds at most `max_names` names, forgetting the least recently recorded name to make room, so
+/// a name that comes back after being forgotten starts counting from 1 again.
+#[derive(Debug)]
+struct OccurrenceCounts {
+    max_names: usize,
+    /// The count for each name, and when it was last recorded, in calls to `count`.
+    counts: FastHashMap<String, (u64, u64)>,
+    /// Each name as it was recorded, oldest first, with when. An entry is stale once its name has
+    /// been recorded again, or forgotten.
+    recency: VecDeque<(String, u64)>,
+    clock: u64,
+}
+
+impl OccurrenceCounts {
+    fn new(max_names: usize) -> Self {
+        OccurrenceCounts {
+            max_names: max_names.max(1),
+            counts: FastHashMap::default(),
+            recency: VecDeque::new(),
+            clock: 0,
+        }
+    }
+
+    /// Record an event named `name`, returning how many times it's been recorded, this time
+    /// included.
+    fn count(&mut self, name: String) -> u64 {
+        self.clock += 1;
+        if !self.counts.contains_key(&name) && self.counts.len() >= self.max_names {
+            self.forget_least_recent();
+        }
+
+        let (count, last_used) = self.counts.entry(name.clone()).or_insert((0, 0));
+        *count += 1;
+        *last_used = self.clock;
+        let count = *count;
+
+        self.recency.push_back((name, self.clock));
+        // stale entries are otherwise only skipped over when forgetting a name
+        if self.recency.len() > 2 * self.max_names {
+            let counts = &self.counts;
+            self.recency.retain(|(name, recorded)| {
+                counts.get(name).map(|(_, last_used)| last_used) == Some(recorded)
+            });
+        }
+
+        count
+    }
+
+    fn forget_least_recent(&mut self) {
+        while let Some((name, recorded)) = self.recency.pop_front() {
+            if self.counts.get(&name).map(|(_, last_used)| *last_used) == Some(recorded) {
+                self.counts.remove(&name);
+                return;
+            }
+        }
+    }
+}
+
+/// Remove `prefix` from the start of `module_path`, unless that would leave nothing. The prefix
+/// only matches whole path segments, with or without its trailing `::`.
+fn strip_module_prefix<'a>(module_path: &'a str, prefix: Option<&str>) -> &'a str {
+    let stripped = prefix.and_then(|prefix| {
+        let rest = module_path.strip_prefix(prefix)?;
+        if prefix.ends_with("::") {
+            Some(rest)
+        } else {
+            rest.strip_prefix("::")
+        }
+    });
+    match stripped {
+        Some(stripped) if !stripped.is_empty() => stripped,
+        _ => module_path,
+    }
+}
+
+/// Wait for the next heartbeat tick, or forever if heartbeats are off.
+async fn next_heartbeat(heartbeat: &mut Option<Interval>) {
+    match heartbeat {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// Prefix an event attribute key with `event.` if it isn't already. This is the one place event
+/// keys get their prefix, everything else passes keys through as given.
+fn event_attr_key(key: String) -> String {
+    if key.starts_with("event.") {
+        key
+    } else {
+        format!("event.{key}")
+    }
+}
+
+/// Point the `timeline.run_id` metadata at `run_id`, if run ID metadata is being recorded.
+fn set_run_id_metadata(metadata: &mut [(String, AttrVal)], run_id: Uuid) {
+    for (key, value) in metadata.iter_mut() {
+        if key == "timeline.run_id" {
+            *value = AttrVal::String(run_id.to_string().into());
+        }
+    }
+}
+
+/// Remove repeated keys from `attrs`, keeping the last value for each key in the position of its
+/// first occurrence. Returns the deduplicated attrs and the keys that had collisions.
+fn dedup_attrs(
+    attrs: Vec<(InternedAttrKey, AttrVal)>,
+) -> (Vec<(InternedAttrKey, AttrVal)>, Vec<InternedAttrKey>) {
+    let mut deduped: Vec<(InternedAttrKey, AttrVal)> = Vec::with_capacity(attrs.len());
+    // where each key ended up in `deduped`
+    let mut seen: FastHashMap<InternedAttrKey, usize> = FastHashMap::default();
+    let mut collisions = Vec::new();
+
+    for (key, value) in attrs {
+        match seen.get(&key) {
+            Some(&index) => {
+                deduped[index].1 = value;
+                if !collisions.contains(&key) {
+                    collisions.push(key);
+                }
+            }
+            None => {
+                seen.insert(key, deduped.len());
+                deduped.push((key, value));
+            }
+        }
+    }
+
+    (deduped, collisions)
+}
+
+/// Retype a timestamp attribute as an [`AttrVal::Timestamp`] in nanoseconds when it is an integer
+/// that fits in a `u64`. Anything else, including negative and out of range integers, is returned
+/// as is.
+///
+/// Also used by `tracing-serde-modality-ingest`, so both crates coerce timestamps the same way.
+pub fn coerce_timestamp(attrval: AttrVal) -> AttrVal {
+    match attrval {
+        AttrVal::Integer(i) if i >= 0 => AttrVal::Timestamp(Nanoseconds::from(i as u64)),
+        AttrVal::BigInt(i) if *i >= 0 && *i <= u64::MAX as i128 => {
+            AttrVal::Timestamp(Nanoseconds::from(*i as u64))
+        }
+        x => x,
+    }
+}
+
+pub(crate) fn tracing_value_to_attr_val(value: TracingValue) -> AttrVal {
+    match value {
+        TracingValue::String(s) => s.into(),
+        TracingValue::F64(n) => n.into(),
+        TracingValue::I64(n) => n.into(),
+        TracingValue::U64(n) => (n as i128).into(),
+        TracingValue::Bool(b) => b.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attr_handlers::AttributeHandler;
+    #[cfg(all(feature = "async", feature = "blocking"))]
+    use crate::layer::tests::trace_with;
+
+    /// What an ingest sent, with attribute keys by name rather than interned ID.
+    #[derive(Default)]
+    pub(super) struct Recording {
+        keys: Vec<String>,
+        events: Vec<(TimelineId, Vec<(String, AttrVal)>)>,
+        metadata: Vec<(TimelineId, String, AttrVal)>,
+        fail_flush: bool,
+    }
+
+    impl Recording {
+        /// The attributes of each event sent, in order.
+        fn events(&self) -> Vec<&[(String, AttrVal)]> {
+            self.events
+                .iter()
+                .map(|(_, attrs)| attrs.as_slice())
+                .collect()
+        }
+    }
+
+    pub(super) struct RecordingClient {
+        pub(super) timeline: TimelineId,
+        recording: Arc<Mutex<Recording>>,
+    }
+
+    impl RecordingClient {
+        fn recording(&self) -> std::sync::MutexGuard<'_, Recording> {
+            self.recording.lock().unwrap()
+        }
+
+        pub(super) fn declare_attr_key(&mut self, key: String) -> InternedAttrKey {
+            let mut recording = self.recording();
+            recording.keys.push(key);
+            InternedAttrKey::from(recording.keys.len() as u32 - 1)
+        }
+
+        fn named(&self, attrs: Vec<(InternedAttrKey, AttrVal)>) -> Vec<(String, AttrVal)> {
+            let recording = self.recording();
+            attrs
+                .into_iter()
+                .map(|(key, value)| (recording.keys[u32::from(key) as usize].clone(), value))
+                .collect()
+        }
+
+        pub(super) fn timeline_metadata(&mut self, attrs: Vec<(InternedAttrKey, AttrVal)>) {
+            let attrs = self.named(attrs);
+            let timeline = self.timeline;
+            self.recording()
+                .metadata
+                .extend(attrs.into_iter().map(|(k, v)| (timeline, k, v)));
+        }
+
+        pub(super) fn event(&mut self, _ordering: u128, attrs: Vec<(InternedAttrKey, AttrVal)>) {
+            let attrs = self.named(attrs);
+            let timeline = self.timeline;
+            self.recording().events.push((timeline, attrs));
+        }
+
+        pub(super) fn flush(&mut self) -> Result<(), SdkIngestError> {
+            match self.recording().fail_flush {
+                true => Err(SdkIngestError::ProtocolError("flush failed")),
+                false => Ok(()),
+            }
+        }
+    }
+
+    /// An ingest that records what it sends rather than sending it to modality.
+    fn recording_ingest(options: Options) -> (ModalityIngest, Arc<Mutex<Recording>>) {
+        let recording = Arc::new(Mutex::new(Recording::default()));
+        let root_timeline = TimelineId::from(Uuid::new_v4());
+        let client = Client::Recording(RecordingClient {
+            timeline: root_timeline,
+            recording: recording.clone(),
+        });
+        (
+            ModalityIngest::new(client, root_timeline, options),
+            recording,
+        )
+    }
+
+    /// The attributes `records` are sent with, as the fields of an event.
+    async fn packed_event(options: Options, records: RecordMap) -> Vec<(String, AttrVal)> {
+        let (mut ingest, recording) = recording_ingest(options);
+        let message = event_message(info_metadata(), records, ingest.root_timeline);
+        ingest.process_packet(message).await.unwrap();
+        let recording = recording.lock().unwrap();
+        recording.events()[0].to_vec()
+    }
+
+    fn attr<'a>(event: &'a [(String, AttrVal)], key: &str) -> Option<&'a AttrVal> {
+        event.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+
+    fn event_message(
+        metadata: &'static Metadata<'static>,
+        records: RecordMap,
+        timeline: TimelineId,
+    ) -> WrappedMessage {
+        let message = Message::Event {
+            metadata,
+            records,
+            context: Box::default(),
+        };
+        wrap_message(message, timeline, None)
+    }
+
+    /// The metadata of an `info` level event callsite.
+    fn info_metadata() -> &'static Metadata<'static> {
+        struct Callsite;
+        impl tracing_core::Callsite for Callsite {
+            fn set_interest(&self, _interest: tracing_core::Interest) {}
+            fn metadata(&self) -> &Metadata<'_> {
+                &METADATA
+            }
+        }
+        static CALLSITE: Callsite = Callsite;
+        static METADATA: Metadata<'static> = tracing_core::metadata!(
+            name: "test event",
+            target: "tracing_modality::test",
+            level: Level::INFO,
+            fields: &[],
+            callsite: &CALLSITE,
+            kind: tracing_core::metadata::Kind::EVENT,
+        );
+        &METADATA
+    }
+
+    #[test]
+    fn connect_error_transience() {
+        let io_failure = |kind| {
+            ConnectError::from(
+                anyhow::Error::new(io::Error::from(kind)).context("init ingest client"),
+            )
+        };
+        assert!(io_failure(io::ErrorKind::ConnectionRefused).is_transient());
+        assert!(io_failure(io::ErrorKind::TimedOut).is_transient());
+        assert!(!io_failure(io::ErrorKind::PermissionDenied).is_transient());
+        assert!(!ConnectError::from(anyhow::anyhow!("bad address")).is_transient());
+
+        let reset = SdkIngestError::from(io::Error::from(io::ErrorKind::ConnectionReset));
+        assert!(ConnectError::from_auth_error(reset).is_transient());
+        let protocol = SdkIngestError::ProtocolError("unexpected response");
+        assert!(!ConnectError::from_auth_error(protocol).is_transient());
+
+        let rejected = SdkIngestError::from(io::Error::from(io::ErrorKind::ConnectionReset));
+        assert!(!ConnectError::AuthRejected(rejected).is_transient());
+        assert!(!ConnectError::AuthRequired.is_transient());
+    }
+
+    #[test]
+    fn coerce_timestamp_in_range() {
+        assert_eq!(
+            coerce_timestamp(AttrVal::Integer(0)),
+            AttrVal::Timestamp(Nanoseconds::from(0))
+        );
+        assert_eq!(
+            coerce_timestamp(AttrVal::Integer(i64::MAX)),
+            AttrVal::Timestamp(Nanoseconds::from(i64::MAX as u64))
+        );
+        assert_eq!(
+            coerce_timestamp(BigInt::new_attr_val(u64::MAX as i128)),
+            AttrVal::Timestamp(Nanoseconds::from(u64::MAX))
+        );
+        assert_eq!(
+            coerce_timestamp(AttrVal::Timestamp(Nanoseconds::from(42))),
+            AttrVal::Timestamp(Nanoseconds::from(42))
+        );
+    }
+
+    #[test]
+    fn coerce_timestamp_out_of_range() {
+        assert_eq!(coerce_timestamp(AttrVal::Integer(-1)), AttrVal::Integer(-1));
+        assert_eq!(
+            coerce_timestamp(BigInt::new_attr_val(-1)),
+            BigInt::new_attr_val(-1)
+        );
+        assert_eq!(
+            coerce_timestamp(BigInt::new_attr_val(u64::MAX as i128 + 1)),
+            BigInt::new_attr_val(u64::MAX as i128 + 1)
+        );
+        assert_eq!(
+            coerce_timestamp(AttrVal::String("soon".into())),
+            AttrVal::String("soon".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn schema_version_on_first_event_per_timeline() {
+        let (mut ingest, recording) = recording_ingest(Options::new());
+        for _ in 0..2 {
+            let message = event_message(info_metadata(), RecordMap::new(), ingest.root_timeline);
+            ingest.process_packet(message).await.unwrap();
+        }
+
+        let recording = recording.lock().unwrap();
+        let events = recording.events();
+        assert_eq!(
+            attr(events[0], "event.internal.rs.schema_version"),
+            Some(&AttrVal::Integer(crate::SCHEMA_VERSION.into()))
+        );
+        assert!(attr(events[1], "event.internal.rs.schema_version").is_none());
+    }
+
+    #[test]
+    fn strip_module_prefix_at_segment_boundaries() {
+        for prefix in ["my_app", "my_app::"] {
+            let strip = |module_path| strip_module_prefix(module_path, Some(prefix));
+            assert_eq!(strip("my_app::services::billing"), "services::billing");
+            assert_eq!(strip("my_appx::foo"), "my_appx::foo");
+            assert_eq!(strip("my_app"), "my_app");
+            assert_eq!(strip("other::my_app::foo"), "other::my_app::foo");
+        }
+        assert_eq!(strip_module_prefix("my_app::foo", None), "my_app::foo");
+    }
+
+    #[tokio::test]
+    async fn attr_vec_capacity_only_sizes_the_initial_vec() {
+        let (ingest, _) = recording_ingest(Options::new().with_attr_vec_capacity(2));
+        assert_eq!(ingest.attr_vec_capacity, 2);
+
+        // events with more attributes than the capacity still carry all of them
+        let records = RecordMap::from_iter((0..4).map(|i| (format!("f{i}"), TracingValue::U64(i))));
+        let small = packed_event(Options::new().with_attr_vec_capacity(0), records.clone()).await;
+        let default = packed_event(Options::new(), records).await;
+        assert_eq!(small.len(), default.len());
+        for i in 0..4 {
+            assert!(attr(&small, &format!("event.f{i}")).is_some());
+        }
+    }
+
+    #[tokio::test]
+    async fn event_attr_key_prefixes_once() {
+        // handlers may return keys either way, and fields may already be named `event.*`
+        let options = Options::new().with_attr_handler(AttributeHandler::new("handled", |value| {
+            vec![
+                ("foo".to_string(), tracing_value_to_attr_val(value.clone())),
+                ("event.bar".to_string(), tracing_value_to_attr_val(value)),
+            ]
+        }));
+        let records = RecordMap::from_iter([
+            ("handled".to_string(), TracingValue::Bool(true)),
+            ("event.baz".to_string(), TracingValue::Bool(true)),
+        ]);
+
+        let event = packed_event(options, records).await;
+        for key in ["event.foo", "event.bar", "event.baz"] {
+            assert_eq!(attr(&event, key), Some(&AttrVal::Bool(true)), "{key}");
+        }
+        assert!(!event.iter().any(|(key, _)| key.starts_with("event.event.")));
+    }
+
+    #[test]
+    fn timeline_churn_warns_once() {
+        let mut churn = TimelineChurn::new(Some(0.9));
+        let mut warnings = 0;
+        for _ in 0..TimelineChurn::WINDOW * 3 {
+            churn.new_timeline();
+            warnings += churn.message() as u32;
+        }
+        assert_eq!(warnings, 1);
+    }
+
+    #[test]
+    fn timeline_churn_ignores_reused_timelines() {
+        let mut churn = TimelineChurn::new(Some(0.9));
+        for i in 0..TimelineChurn::WINDOW * 3 {
+            if i % 2 == 0 {
+                churn.new_timeline();
+            }
+            assert!(!churn.message());
+        }
+
+        let mut disabled = TimelineChurn::new(None);
+        for _ in 0..TimelineChurn::WINDOW * 3 {
+            disabled.new_timeline();
+            assert!(!disabled.message());
+        }
+    }
+
+    #[test]
+    fn occurrence_counts_forget_least_recent_name() {
+        let mut counts = OccurrenceCounts::new(2);
+        assert_eq!(counts.count("a".into()), 1);
+        assert_eq!(counts.count("b".into()), 1);
+        assert_eq!(counts.count("a".into()), 2);
+
+        // `b` was recorded least recently, so it makes room for `c`
+        assert_eq!(counts.count("c".into()), 1);
+        assert_eq!(counts.count("a".into()), 3);
+        assert_eq!(counts.count("b".into()), 1);
+
+        // only when a name was last recorded matters, not how often
+        for n in 4..10 {
+            assert_eq!(counts.count("a".into()), n);
+        }
+        assert_eq!(counts.count("b".into()), 2);
+        assert_eq!(counts.count("d".into()), 1);
+        assert_eq!(counts.count("b".into()), 3);
+        assert_eq!(counts.count("a".into()), 1);
+    }
+
+    #[test]
+    fn full_channel_drops_and_counts() {
+        let (sender, mut recv) = ingest_channel(Some(2));
+        let timeline = TimelineId::from(Uuid::new_v4());
+        for _ in 0..5 {
+            let _ = sender.send(wrap_message(Message::Heartbeat, timeline, None));
+        }
+        assert_eq!(sender.dropped(), 3);
+        assert!(recv.try_recv().is_ok());
+        assert!(recv.try_recv().is_ok());
+        assert!(recv.try_recv().is_err());
+
+        drop(recv);
+        assert!(matches!(
+            sender.send(wrap_message(Message::Heartbeat, timeline, None)),
+            Err(TrySendError::Closed(()))
+        ));
+        assert_eq!(sender.dropped(), 3);
+    }
+
+    #[test]
+    fn flight_recorder_keeps_newest_events() {
+        let mut recorder = FlightRecorder::new(2);
+        let timelines: Vec<TimelineId> = (0..3).map(|_| TimelineId::from(Uuid::new_v4())).collect();
+        for timeline in &timelines {
+            recorder.hold(wrap_message(Message::Heartbeat, *timeline, None));
+        }
+
+        assert_eq!(recorder.discarded(), 3);
+        let held: Vec<TimelineId> = recorder.take().into_iter().map(|m| m.timeline).collect();
+        assert_eq!(held, timelines[1..]);
+        assert!(recorder.take().is_empty());
+        assert_eq!(recorder.discarded(), 1);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn flight_recorder_discards_reported() {
+        let (ingest, recording) = recording_ingest(Options::new().with_flight_recorder(2));
+        let handle = ingest.spawn_task(Vec::new()).await;
+        for _ in 0..3 {
+            let message = event_message(info_metadata(), RecordMap::new(), handle.root_timeline);
+            handle.ingest_sender.send(message).unwrap();
+        }
+
+        let report = handle.finish().await;
+        // the oldest event made room for the third, the other two were still held
+        assert_eq!(report.discarded, 3);
+        assert!(recording.lock().unwrap().events().is_empty());
+    }
+
+    #[test]
+    fn span_record_and_follows_from_kinds() {
+        let span = SpanId::new(7).unwrap();
+
+        let attrs = span_event_attrs(kind::SPAN_RECORD, span, Some("work"));
+        assert_eq!(attrs[0], ("event.name".to_string(), "record: work".into()));
+        assert_eq!(
+            attrs[1],
+            ("event.internal.rs.kind".to_string(), "span:record".into())
+        );
+
+        let attrs = span_event_attrs(kind::SPAN_FOLLOWS_FROM, span, None);
+        assert_eq!(
+            attrs[0],
+            (
+                "event.internal.rs.kind".to_string(),
+                "span:follows_from".into()
+            )
+        );
+    }
+
+    #[test]
+    fn follows_from_across_timelines() {
+        let (a, b) = (
+            TimelineId::from(Uuid::new_v4()),
+            TimelineId::from(Uuid::new_v4()),
+        );
+        let (span_a, span_b) = (SpanId::new(1).unwrap(), SpanId::new(2).unwrap());
+        let span_timelines: FastHashMap<_, _> = [(span_a, a), (span_b, b)].into_iter().collect();
+
+        // a span on timeline `a` following `span_b` interacts with timeline `b`
+        assert_eq!(remote_timeline(&span_timelines, span_b, a), Some(b));
+        assert_eq!(remote_timeline(&span_timelines, span_a, a), None);
+        assert_eq!(
+            remote_timeline(&span_timelines, SpanId::new(3).unwrap(), a),
+            None
+        );
+    }
+
+    #[test]
+    fn cap_attrs_keeps_well_known_keys() {
+        let well_known = |(key, _): &(&str, AttrVal)| is_well_known_key(key);
+        let mut attrs = vec![
+            ("event.internal.rs.kind", AttrVal::from("event")),
+            ("event.name", AttrVal::from("busy")),
+        ];
+        attrs.extend((0..10).map(|i| ("event.field", AttrVal::Integer(i))));
+        // packed after the fields, like the tick on a span:record event
+        attrs.push(("event.internal.rs.tick", AttrVal::Integer(0)));
+
+        assert_eq!(
+            cap_attrs(&mut attrs, 5, well_known),
+            Some(AttrVal::Integer(8))
+        );
+        assert_eq!(
+            attrs.iter().map(|(k, _)| *k).collect::<Vec<_>>(),
+            vec![
+                "event.internal.rs.kind",
+                "event.name",
+                "event.field",
+                "event.field",
+                "event.internal.rs.tick"
+            ]
+        );
+        assert_eq!(cap_attrs(&mut attrs, 5, well_known), None);
+
+        // well-known attributes are kept even past the cap
+        assert_eq!(
+            cap_attrs(&mut attrs, 1, well_known),
+            Some(AttrVal::Integer(2))
+        );
+        assert_eq!(attrs.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn max_attrs_keeps_well_known_keys_on_record() {
+        let options = Options::new().with_max_attrs_per_event(4);
+        let (mut ingest, recording) = recording_ingest(options);
+        let span = SpanId::new(1).unwrap();
+        let records = RecordMap::from_iter((0..8).map(|i| (format!("f{i}"), TracingValue::U64(i))));
+        let message = wrap_message(
+            Message::Record { span, records },
+            ingest.root_timeline,
+            None,
+        );
+        ingest.process_packet(message).await.unwrap();
+
+        let recording = recording.lock().unwrap();
+        let event = recording.events()[0];
+        assert_eq!(
+            attr(event, "event.internal.rs.kind"),
+            Some(&AttrVal::from("span:record"))
+        );
+        assert!(attr(event, "event.internal.rs.tick").is_some());
+        assert!(attr(event, "event.internal.rs.attrs_truncated").is_some());
+    }
+
+    #[tokio::test]
+    async fn recorded_name_renames_span() {
+        let (mut ingest, recording) = recording_ingest(Options::new());
+        let (named, unnamed) = (SpanId::new(1).unwrap(), SpanId::new(2).unwrap());
+        let string = |s: &str| TracingValue::String(s.to_string());
+        let messages = [
+            Message::NewSpan {
+                id: named,
+                metadata: info_metadata(),
+                records: RecordMap::from_iter([("name".to_string(), string("request"))]),
+            },
+            Message::Record {
+                span: named,
+                records: RecordMap::from_iter([("name".to_string(), string("GET /users"))]),
+            },
+            Message::Enter {
+                span: named,
+                records: None,
+            },
+            Message::NewSpan {
+                id: unnamed,
+                metadata: info_metadata(),
+                records: RecordMap::new(),
+            },
+            Message::Record {
+                span: unnamed,
+                records: RecordMap::from_iter([("message".to_string(), string("polling"))]),
+            },
+            Message::Exit { span: unnamed },
+        ];
+        for message in messages {
+            let message = wrap_message(message, ingest.root_timeline, None);
+            ingest.process_packet(message).await.unwrap();
+        }
+
+        let recording = recording.lock().unwrap();
+        let names: Vec<_> = recording
+            .events()
+            .iter()
+            .filter_map(|event| attr(event, "event.name").cloned())
+            .collect();
+        // the recorded `name` field itself is also packed onto its span:record event
+        assert_eq!(
+            names,
+            [
+                "request",
+                "GET /users",
+                "enter: GET /users",
+                "test event",
+                "record: polling",
+                "exit: polling",
+            ]
+            .map(AttrVal::from)
+        );
+    }
+
+    #[tokio::test]
+    async fn poll_coalescing_summarizes_polls() {
+        let options = Options::new().with_poll_coalescing(Some(Duration::from_millis(10)));
+        let (mut ingest, recording) = recording_ingest(options);
+        let span = |id| SpanId::new(id).unwrap();
+        let enter = |id| Message::Enter {
+            span: span(id),
+            records: None,
+        };
+        let exit = |id| Message::Exit { span: span(id) };
+        let close = |id| Message::Close {
+            span: span(id),
+            metadata: info_metadata(),
+            records: None,
+            poll_count: None,
+        };
+        let messages = [
+            (0, enter(1)),
+            (2, exit(1)),
+            (5, enter(1)),
+            // the window is over, summarized as 2 polls busy for 2 + 7ms
+            (12, exit(1)),
+            (20, enter(1)),
+            (21, exit(1)),
+            // the open window carries over to the new ID
+            (
+                22,
+                Message::IdChange {
+                    old: span(1),
+                    new: span(2),
+                },
+            ),
+            (23, enter(2)),
+            (25, exit(2)),
+            // polls left over at close are summarized, 2 polls busy for 1 + 2ms
+            (26, close(2)),
+            // a span closed without a finished poll has nothing to summarize
+            (30, enter(3)),
+            (31, close(3)),
+        ];
+        for (ms, message) in messages {
+            let mut message = wrap_message(message, ingest.root_timeline, None);
+            message.tick = Duration::from_millis(ms);
+            ingest.process_packet(message).await.unwrap();
+        }
+
+        let recording = recording.lock().unwrap();
+        let summaries: Vec<_> = recording
+            .events()
+            .iter()
+            .map(|event| {
+                assert_eq!(
+                    attr(event, "event.internal.rs.kind"),
+                    Some(&AttrVal::from("span:busy"))
+                );
+                (
+                    attr(event, "event.internal.rs.span_id").cloned(),
+                    attr(event, "event.internal.rs.poll_count").cloned(),
+                    attr(event, "event.internal.rs.busy_ns").cloned(),
+                )
+            })
+            .collect();
+        let summary = |id: u64, polls: u64, busy_ms: i128| {
+            (
+                Some(BigInt::new_attr_val(id.into())),
+                Some(BigInt::new_attr_val(polls.into())),
+                Some(BigInt::new_attr_val(busy_ms * 1_000_000)),
+            )
+        };
+        assert_eq!(summaries, vec![summary(1, 2, 9), summary(2, 2, 3)]);
+    }
+
+    #[test]
+    fn dedup_attrs_last_wins() {
+        let foo = InternedAttrKey::from(1);
+        let bar = InternedAttrKey::from(2);
+
+        // as if `foo = 1, bar = true, foo = 2` were all packed onto one event
+        let (attrs, collisions) = dedup_attrs(vec![
+            (foo, AttrVal::from(1i64)),
+            (bar, AttrVal::from(true)),
+            (foo, AttrVal::from(2i64)),
+        ]);
+
+        assert_eq!(
+            attrs,
+            vec![(foo, AttrVal::from(2i64)), (bar, AttrVal::from(true))]
+        );
+        assert_eq!(collisions, vec![foo]);
+
+        // each colliding key is reported once, however many times it repeats
+        let (attrs, collisions) = dedup_attrs(
+            (0..3i64)
+                .flat_map(|i| [(bar, AttrVal::from(i)), (foo, AttrVal::from(-i))])
+                .collect(),
+        );
+        assert_eq!(
+            attrs,
+            vec![(bar, AttrVal::from(2i64)), (foo, AttrVal::from(-2i64))]
+        );
+        assert_eq!(collisions, vec![bar, foo]);
+    }
+
+    #[tokio::test]
+    async fn allowlist_applies_to_handled_fields() {
+        let options = Options::new()
+            .with_field_allowlist(["kept"])
+            .with_attr_handler(AttributeHandler::new("dropped", |_| {
+                vec![("handled".to_string(), AttrVal::from(true))]
+            }));
+        let records = RecordMap::from_iter([
+            ("kept".to_string(), TracingValue::U64(1)),
+            ("dropped".to_string(), TracingValue::U64(2)),
+            ("interaction.nonce".to_string(), TracingValue::U64(3)),
+        ]);
+
+        let event = packed_event(options, records).await;
+        assert!(attr(&event, "event.kept").is_some());
+        assert!(attr(&event, "event.dropped").is_none());
+        assert!(attr(&event, "event.handled").is_none());
+        assert_eq!(
+            attr(&event, "event.interaction.nonce"),
+            Some(&BigInt::new_attr_val(3))
+        );
+    }
+
+    #[tokio::test]
+    async fn context_goes_through_pack_records() {
+        let options = Options::new()
+            .with_redaction("token")
+            .with_field_allowlist(["request_id", "auth_token", "status"]);
+        let (mut ingest, recording) = recording_ingest(options);
+        let context = vec![
+            ("request_id".to_string(), AttrVal::from("r-1")),
+            ("auth_token".to_string(), AttrVal::from("hunter2")),
+            ("tenant".to_string(), AttrVal::from("acme")),
+            ("status".to_string(), AttrVal::from(500i64)),
+        ];
+        let message = Message::Event {
+            metadata: info_metadata(),
+            records: RecordMap::from_iter([("status".to_string(), TracingValue::I64(200))]),
+            context: context.into_boxed_slice(),
+        };
+        let message = wrap_message(message, ingest.root_timeline, None);
+        ingest.process_packet(message).await.unwrap();
+
+        let recording = recording.lock().unwrap();
+        let event = recording.events()[0];
+        assert_eq!(attr(event, "event.request_id"), Some(&AttrVal::from("r-1")));
+        assert_eq!(
+            attr(event, "event.auth_token"),
+            Some(&AttrVal::String("<redacted>".to_string().into()))
+        );
+        assert!(attr(event, "event.tenant").is_none());
+        // the event's own field wins
+        assert_eq!(attr(event, "event.status"), Some(&AttrVal::from(200i64)));
+    }
+
+    #[tokio::test]
+    async fn redaction_overrides_handlers() {
+        let options =
+            Options::new()
+                .with_redaction("token")
+                .with_attr_handler(AttributeHandler::new("auth_token", |value| {
+                    vec![("token_copy".to_string(), tracing_value_to_attr_val(value))]
+                }));
+        let records = RecordMap::from_iter([(
+            "auth_token".to_string(),
+            TracingValue::String("hunter2".to_string()),
+        )]);
+
+        let event = packed_event(options, records).await;
+        assert_eq!(
+            attr(&event, "event.auth_token"),
+            Some(&AttrVal::String("<redacted>".to_string().into()))
+        );
+        assert!(attr(&event, "event.token_copy").is_none());
+    }
+
+    #[tokio::test]
+    async fn clock_id_field_names_the_clock_domain() {
+        let records =
+            RecordMap::from_iter([("modality.clock_id".to_string(), TracingValue::I64(3))]);
+
+        let event = packed_event(Options::new().with_clock_id(1), records).await;
+        assert_eq!(
+            attr(&event, "event.internal.rs.clock_id"),
+            Some(&AttrVal::Integer(3))
+        );
+        assert!(attr(&event, "event.modality.clock_id").is_none());
+    }
+
+    #[tokio::test]
+    async fn component_fn_classifies_events() {
+        let options = || {
+            Options::new().with_component_fn(|metadata| {
+                metadata
+                    .target()
+                    .starts_with("tracing_modality::")
+                    .then(|| "tracing".to_string())
+            })
+        };
+
+        let event = packed_event(options(), RecordMap::new()).await;
+        assert_eq!(
+            attr(&event, "event.component"),
+            Some(&AttrVal::from("tracing"))
+        );
+
+        // an explicit field wins
+        let records = RecordMap::from_iter([(
+            "component".to_string(),
+            TracingValue::String("api".to_string()),
+        )]);
+        let event = packed_event(options(), records).await;
+        assert_eq!(attr(&event, "event.component"), Some(&AttrVal::from("api")));
+
+        let options = Options::new().with_component_fn(|_| None);
+        let event = packed_event(options, RecordMap::new()).await;
+        assert!(attr(&event, "event.component").is_none());
+    }
+
+    #[tokio::test]
+    async fn clock_source_follows_tick_fn() {
+        let tick = || Duration::from_secs(1);
+        for (options, expected) in [
+            (Options::new(), "system_monotonic"),
+            (Options::new().with_tick_fn(tick), "custom"),
+            (
+                Options::new().with_tick_fn(tick).with_clock_source("ptp"),
+                "ptp",
+            ),
+            // only names a custom tick function
+            (Options::new().with_clock_source("ptp"), "system_monotonic"),
+        ] {
+            let event = packed_event(options, RecordMap::new()).await;
+            assert_eq!(
+                attr(&event, "event.internal.rs.clock_source"),
+                Some(&AttrVal::from(expected))
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn units_only_on_numeric_fields() {
+        let options = || {
+            Options::new()
+                .with_field_unit("latency", "ms")
+                .with_field_unit("token", "chars")
+                .with_unit_suffixes(true)
+                .with_redaction("token")
+        };
+        let records = || {
+            RecordMap::from_iter([
+                ("latency".to_string(), TracingValue::F64(1.5)),
+                ("size_bytes".to_string(), TracingValue::U64(10)),
+                (
+                    "label_ms".to_string(),
+                    TracingValue::String("slow".to_string()),
+                ),
+                ("token".to_string(), TracingValue::I64(42)),
+            ])
+        };
+
+        let event = packed_event(options(), records()).await;
+        assert_eq!(
+            attr(&event, "event.latency.unit"),
+            Some(&AttrVal::from("ms"))
+        );
+        assert_eq!(
+            attr(&event, "event.size_bytes.unit"),
+            Some(&AttrVal::from("bytes"))
+        );
+        assert!(attr(&event, "event.label_ms.unit").is_none());
+        assert!(attr(&event, "event.token.unit").is_none());
+
+        // unit keys are checked against the approved keys like any other
+        let options = options()
+            .with_approved_keys(HashSet::from([
+                "latency".to_string(),
+                "size_bytes".to_string(),
+            ]))
+            .with_strict_approved_keys(true);
+        let event = packed_event(options, records()).await;
+        assert!(attr(&event, "event.latency").is_some());
+        assert!(attr(&event, "event.latency.unit").is_none());
+        assert!(attr(&event, "event.size_bytes.unit").is_none());
+    }
+
+    #[tokio::test]
+    async fn type_tag_keys_are_approved() {
+        let options = Options::new()
+            .with_field_type_tags(true)
+            .with_approved_keys(HashSet::from([
+                "status".to_string(),
+                "status.__type".to_string(),
+                "latency".to_string(),
+            ]))
+            .with_strict_approved_keys(true);
+        let records = RecordMap::from_iter([
+            ("status".to_string(), TracingValue::I64(200)),
+            ("latency".to_string(), TracingValue::F64(1.5)),
+        ]);
+
+        let event = packed_event(options, records).await;
+        assert_eq!(
+            attr(&event, "event.status.__type"),
+            Some(&AttrVal::from("i64"))
+        );
+        assert!(attr(&event, "event.latency").is_some());
+        assert!(attr(&event, "event.latency.__type").is_none());
+    }
+
+    #[tokio::test]
+    async fn unapproved_keys_warned_or_dropped() {
+        for strict in [false, true] {
+            let options = Options::new()
+                .with_approved_keys(HashSet::from(["status".to_string()]))
+                .with_strict_approved_keys(strict);
+            let records = RecordMap::from_iter([
+                ("status".to_string(), TracingValue::I64(200)),
+                ("extra".to_string(), TracingValue::I64(1)),
+            ]);
+
+            let event = packed_event(options, records).await;
+            assert_eq!(attr(&event, "event.status"), Some(&AttrVal::Integer(200)));
+            // only sent with a warning when not strict
+            assert_eq!(
+                attr(&event, "event.extra"),
+                (!strict).then_some(&AttrVal::Integer(1))
+            );
+            // the crate's own attributes aren't checked
+            assert!(attr(&event, "event.name").is_some());
+        }
+    }
+
+    #[tokio::test]
+    async fn handler_keys_are_approved() {
+        let options = Options::new()
+            .with_approved_keys(HashSet::from(["status".to_string()]))
+            .with_strict_approved_keys(true)
+            .with_attr_handler(AttributeHandler::new("response", |_| {
+                vec![
+                    ("status".to_string(), AttrVal::from(200i64)),
+                    ("body".to_string(), AttrVal::from("ok")),
+                ]
+            }));
+        let records = RecordMap::from_iter([("response".to_string(), TracingValue::U64(0))]);
+
+        let event = packed_event(options, records).await;
+        // the handler's keys are checked rather than the field's own name
+        assert_eq!(attr(&event, "event.status"), Some(&AttrVal::from(200i64)));
+        assert!(attr(&event, "event.body").is_none());
+        assert!(attr(&event, "event.response").is_none());
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn identifier_timelines_end_on_shutdown() {
+        let options = Options::new().with_timeline_end_marker(true);
+        let (ingest, recording) = recording_ingest(options);
+        let handle = ingest.spawn_task(Vec::new()).await;
+
+        let timelines: Vec<TimelineId> = (1..=2)
+            .map(|user_id| handle.timeline_id_for(user_id))
+            .collect();
+        for (user_id, timeline) in (1..=2).zip(&timelines) {
+            let message = Message::NewTimeline {
+                name: format!("connection-{user_id}"),
+                run_id: Some(handle.run_id.get()),
+                user_id: Some(user_id),
+                parent: None,
+            };
+            let _ = handle
+                .ingest_sender
+                .send(wrap_message(message, *timeline, None));
+        }
+        handle.close_timeline(1);
+        handle.finish().await;
+
+        let recording = recording.lock().unwrap();
+        let ends: Vec<TimelineId> = recording
+            .events
+            .iter()
+            .filter(|(_, attrs)| {
+                attrs.contains(&("event.name".to_string(), AttrVal::from("timeline:end")))
+            })
+            .map(|(timeline, _)| *timeline)
+            .collect();
+        // the closed timeline isn't ended a second time
+        assert_eq!(ends, timelines);
+    }
+
+    #[cfg(all(feature = "async", feature = "blocking"))]
+    #[tokio::test]
+    async fn closed_timeline_is_announced_again() {
+        let options =
+            Options::new().with_timeline_identifier(|| UserTimelineInfo::new("connection", 1));
+        let (ingest, recording) = recording_ingest(options.clone());
+        let handle = ingest.spawn_task(Vec::new()).await;
+
+        trace_with(handle.ingest_sender.clone(), options, || {
+            tracing::info!("before close");
+            handle.close_timeline(1);
+            tracing::info!("after close");
+        });
+        let timeline = handle.timeline_id_for(1);
+        handle.finish().await;
+
+        // the timeline is reopened on this thread, rather than recorded without its metadata
+        let recording = recording.lock().unwrap();
+        let names = recording
+            .metadata
+            .iter()
+            .filter(|(t, key, _)| *t == timeline && key == "timeline.name")
+            .count();
+        assert_eq!(names, 2);
+    }
+
+    #[cfg(all(feature = "async", feature = "blocking"))]
+    #[tokio::test]
+    async fn parent_user_id_recorded_as_timeline_parent() {
+        let options = Options::new()
+            .with_timeline_identifier(|| UserTimelineInfo::new("request", 2).with_parent(1));
+        let (ingest, recording) = recording_ingest(options.clone());
+        let handle = ingest.spawn_task(Vec::new()).await;
+
+        trace_with(handle.ingest_sender.clone(), options, || {
+            tracing::info!("handled")
+        });
+        let (parent, child) = (handle.timeline_id_for(1), handle.timeline_id_for(2));
+        handle.finish().await;
+
+        let recording = recording.lock().unwrap();
+        let parents: Vec<_> = recording
+            .metadata
+            .iter()
+            .filter(|(_, key, _)| key == "timeline.parent")
+            .collect();
+        assert_eq!(
+            parents,
+            vec![&(
+                child,
+                "timeline.parent".to_string(),
+                AttrVal::TimelineId(Box::new(parent))
+            )]
+        );
+    }
+
+    #[cfg(all(feature = "async", feature = "blocking"))]
+    #[tokio::test]
+    async fn scoped_attr_handlers_take_precedence() {
+        let handler = |tag: &'static str| {
+            AttributeHandler::new("n", move |_| vec![("n".to_string(), AttrVal::from(tag))])
+        };
+        let options = Options::new()
+            .with_attr_handler(handler("scoped").for_timelines(|info| info.name() == "a"))
+            .with_attr_handler(handler("global"));
+        let (ingest, recording) = recording_ingest(options.clone());
+        let handle = ingest.spawn_task(Vec::new()).await;
+
+        trace_with(handle.ingest_sender.clone(), options, || {
+            for (user_id, name) in [(1, "a"), (2, "b")] {
+                let _scope = crate::timeline_scope(UserTimelineInfo::new(name, user_id));
+                tracing::info!(n = 1, "event");
+            }
+        });
+        handle.finish().await;
+
+        let recording = recording.lock().unwrap();
+        let tags: Vec<_> = recording
+            .events()
+            .iter()
+            .filter_map(|event| attr(event, "event.n").cloned())
+            .collect();
+        // the scoped handler applies on "a", the global one is the fallback elsewhere
+        assert_eq!(tags, vec![AttrVal::from("scoped"), AttrVal::from("global")]);
+    }
+
+    #[cfg(all(feature = "async", feature = "blocking"))]
+    #[tokio::test]
+    async fn timeline_id_for_matches_identifier_timelines() {
+        let options =
+            Options::new().with_timeline_identifier(|| UserTimelineInfo::new("worker", 5));
+        let (ingest, recording) = recording_ingest(options.clone());
+        let handle = ingest.spawn_task(Vec::new()).await;
+
+        let mut expected = Vec::new();
+        trace_with(handle.ingest_sender.clone(), options, || {
+            expected.push(handle.timeline_id_for(5));
+            tracing::info!("first run");
+            handle.rotate_run_id();
+            expected.push(handle.timeline_id_for(5));
+            tracing::info!("second run");
+        });
+        assert_ne!(expected[0], expected[1]);
+        handle.finish().await;
+
+        let recording = recording.lock().unwrap();
+        let named: Vec<_> = recording
+            .metadata
+            .iter()
+            .filter(|(_, key, value)| key == "timeline.name" && *value == AttrVal::from("worker"))
+            .map(|(timeline, _, _)| *timeline)
+            .collect();
+        assert_eq!(named, expected);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn heartbeats_every_interval_while_quiet() {
+        let options = Options::new().with_heartbeat_interval(Duration::from_secs(10));
+        let (mut ingest, recording) = recording_ingest(options);
+        let timeline = TimelineId::from(Uuid::new_v4());
+        let heartbeats = |recording: &Arc<Mutex<Recording>>| {
+            let recording = recording.lock().unwrap();
+            recording
+                .events()
+                .iter()
+                .filter(|event| {
+                    attr(event, "event.internal.rs.kind") == Some(&AttrVal::from(kind::HEARTBEAT))
+                })
+                .count()
+        };
+
+        let message = event_message(info_metadata(), RecordMap::new(), timeline);
+        ingest.process_packet(message).await.unwrap();
+        tokio::time::advance(Duration::from_secs(5)).await;
+        ingest.send_heartbeats().await.unwrap();
+        assert_eq!(heartbeats(&recording), 0);
+
+        tokio::time::advance(Duration::from_secs(10)).await;
+        ingest.send_heartbeats().await.unwrap();
+        assert_eq!(heartbeats(&recording), 1);
+
+        // still quiet, the heartbeat itself doesn't count as activity
+        tokio::time::advance(Duration::from_secs(1)).await;
+        ingest.send_heartbeats().await.unwrap();
+        assert_eq!(heartbeats(&recording), 2);
+
+        let message = event_message(info_metadata(), RecordMap::new(), timeline);
+        ingest.process_packet(message).await.unwrap();
+        tokio::time::advance(Duration::from_secs(5)).await;
+        ingest.send_heartbeats().await.unwrap();
+        assert_eq!(heartbeats(&recording), 2);
+    }
+
+    #[cfg(all(feature = "async", feature = "blocking"))]
+    #[tokio::test]
+    async fn callsite_schema_once_per_callsite() {
+        let options = Options::new().with_callsite_schema(true);
+        let (ingest, recording) = recording_ingest(options.clone());
+        let handle = ingest.spawn_task(Vec::new()).await;
+
+        trace_with(handle.ingest_sender.clone(), options, || {
+            for i in 0..3 {
+                tracing::info!(attempt = i, retry = tracing::field::Empty, "connecting");
+            }
+        });
+        handle.finish().await;
+
+        let recording = recording.lock().unwrap();
+        let schemas: Vec<_> = recording
+            .events()
+            .into_iter()
+            .filter(|event| {
+                attr(event, "event.internal.rs.kind")
+                    == Some(&AttrVal::from("internal.rs.callsite_schema"))
+            })
+            .collect();
+        assert_eq!(schemas.len(), 1);
+        let schema = schemas[0];
+        assert_eq!(
+            attr(schema, "event.internal.rs.callsite.kind"),
+            Some(&AttrVal::from("event"))
+        );
+        // declared fields are listed even when never set
+        let Some(AttrVal::String(fields)) = attr(schema, "event.internal.rs.callsite.fields")
+        else {
+            panic!("no fields in {schema:?}");
+        };
+        let mut fields: Vec<_> = fields.as_ref().split(',').collect();
+        fields.sort_unstable();
+        assert_eq!(fields, ["attempt", "message", "retry"]);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn mirrors_receive_every_event() {
+        let (failing, failing_recording) = recording_ingest(Options::new());
+        let (healthy, healthy_recording) = recording_ingest(Options::new());
+        failing_recording.lock().unwrap().fail_flush = true;
+        let mirrors = vec![
+            failing.spawn_task(Vec::new()).await,
+            healthy.spawn_task(Vec::new()).await,
+        ];
+
+        let (ingest, recording) = recording_ingest(Options::new());
+        let handle = ingest.spawn_task(mirrors).await;
+        for _ in 0..3 {
+            let message = event_message(info_metadata(), RecordMap::new(), handle.root_timeline);
+            let _ = handle.ingest_sender.send(message);
+        }
+
+        let report = handle.finish().await;
+        // one mirror failing to flush is reported, but doesn't keep events from the others
+        assert!(!report.flushed);
+        for recording in [recording, failing_recording, healthy_recording] {
+            assert_eq!(recording.lock().unwrap().events().len(), 3);
+        }
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn ingest_exit_reasons() {
+        async fn exit_reason(
+            fail_flush: bool,
+            stop: impl FnOnce(ModalityIngestTaskHandle) -> task::JoinHandle<()>,
+        ) -> IngestExitReason {
+            let (sender, mut reasons) = mpsc::unbounded_channel();
+            let options = Options::new().with_on_ingest_exit(move |reason| {
+                let _ = sender.send(reason);
+            });
+            let (ingest, recording) = recording_ingest(options);
+            recording.lock().unwrap().fail_flush = fail_flush;
+            let handle = ingest.spawn_task(Vec::new()).await;
+
+            stop(handle).await.unwrap();
+            reasons.recv().await.unwrap()
+        }
+
+        let finish = |handle: ModalityIngestTaskHandle| {
+            tokio::spawn(async move {
+                handle.finish().await;
+            })
+        };
+        assert_eq!(exit_reason(false, finish).await, IngestExitReason::Finished);
+        assert!(matches!(
+            exit_reason(true, finish).await,
+            IngestExitReason::ClientError(_)
+        ));
+
+        // dropping the handle lets the task run to completion on its own
+        let drop_handle = |mut handle: ModalityIngestTaskHandle| {
+            let task = handle.task.take().unwrap();
+            drop(handle);
+            tokio::spawn(async move {
+                task.await.unwrap();
+            })
+        };
+        assert_eq!(
+            exit_reason(false, drop_handle).await,
+            IngestExitReason::ChannelClosed
+        );
+
+        let cancel = |mut handle: ModalityIngestTaskHandle| {
+            let task = handle.task.take().unwrap();
+            // before the task first runs
+            task.abort();
+            tokio::spawn(async move {
+                let _ = task.await;
+                drop(handle);
+            })
+        };
+        assert_eq!(
+            exit_reason(false, cancel).await,
+            IngestExitReason::Cancelled
+        );
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn shutdown_report_fields() {
+        let options = Options::new().with_channel_capacity(Some(2));
+        let (ingest, recording) = recording_ingest(options);
+        let handle = ingest.spawn_task(Vec::new()).await;
+        // the task doesn't get to run until `finish`, so only two of these fit in the channel
+        for _ in 0..5 {
+            let message = event_message(info_metadata(), RecordMap::new(), handle.root_timeline);
+            let _ = handle.ingest_sender.send(message);
+        }
+
+        let report = handle.finish().await;
+        assert_eq!(report.dropped, 3);
+        assert_eq!(report.drained, 2);
+        assert!(report.flushed);
+        assert_eq!(recording.lock().unwrap().events().len(), 2);
+
+        let (ingest, recording) = recording_ingest(Options::new());
+        recording.lock().unwrap().fail_flush = true;
+        let report = ingest.spawn_task(Vec::new()).await.finish().await;
+        assert_eq!(
+            report,
+            ShutdownReport {
+                elapsed: report.elapsed,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn unnamed_events_use_configured_name() {
+        let event = packed_event(Options::new(), RecordMap::new()).await;
+        assert_eq!(
+            attr(&event, "event.name"),
+            Some(&AttrVal::from("test event"))
+        );
+
+        let options = Options::new().with_unnamed_event_name(Some("log".to_string()));
+        let event = packed_event(options.clone(), RecordMap::new()).await;
+        assert_eq!(attr(&event, "event.name"), Some(&AttrVal::from("log")));
+
+        let records = RecordMap::from_iter([(
+            "message".to_string(),
+            TracingValue::String("started".to_string()),
+        )]);
+        let event = packed_event(options, records).await;
+        assert_eq!(attr(&event, "event.name"), Some(&AttrVal::from("started")));
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn severity_fn_maps_levels() {
+        let event = packed_event(Options::new(), RecordMap::new()).await;
+        assert_eq!(attr(&event, "event.severity"), Some(&AttrVal::from("info")));
+
+        let options = Options::new().with_severity_fn(|level| match *level {
+            Level::INFO => "informational".into(),
+            _ => "other".into(),
+        });
+        let event = packed_event(options.clone(), RecordMap::new()).await;
+        assert_eq!(
+            attr(&event, "event.severity"),
+            Some(&AttrVal::from("informational"))
+        );
+
+        // an explicit severity field wins over the mapping
+        let records = RecordMap::from_iter([(
+            "severity".to_string(),
+            TracingValue::String("notice".to_string()),
+        )]);
+        let event = packed_event(options, records).await;
+        assert_eq!(
+            attr(&event, "event.severity"),
+            Some(&AttrVal::from("notice"))
+        );
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn spans_record_their_level() {
+        let (mut ingest, recording) = recording_ingest(Options::new());
+        let span = Message::NewSpan {
+            id: SpanId::new(1).unwrap(),
+            metadata: info_metadata(),
+            records: RecordMap::new(),
+        };
+        let span = wrap_message(span, ingest.root_timeline, None);
+        ingest.process_packet(span).await.unwrap();
+        let event = event_message(info_metadata(), RecordMap::new(), ingest.root_timeline);
+        ingest.process_packet(event).await.unwrap();
+
+        let recording = recording.lock().unwrap();
+        let events = recording.events();
+        assert_eq!(
+            attr(events[0], "event.internal.rs.span_level"),
+            Some(&AttrVal::from("info"))
+        );
+        assert!(attr(events[1], "event.internal.rs.span_level").is_none());
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn timeline_names_split_on_separator() {
+        let (ingest, _) = recording_ingest(Options::new());
+        assert_eq!(
+            ingest.timeline_name_metadata("worker/db/pool-3".to_string()),
+            [("timeline.name".to_string(), "worker/db/pool-3".into())]
+        );
+
+        let (ingest, _) = recording_ingest(Options::new().with_timeline_name_separator("/"));
+        assert_eq!(
+            ingest.timeline_name_metadata("worker/db/pool-3".to_string()),
+            [
+                ("timeline.path.0".to_string(), "worker".into()),
+                ("timeline.path.1".to_string(), "db".into()),
+                ("timeline.path.2".to_string(), "pool-3".into()),
+                ("timeline.name".to_string(), "pool-3".into()),
+                ("timeline.name.full".to_string(), "worker/db/pool-3".into()),
+            ]
+        );
+        assert_eq!(
+            ingest.timeline_name_metadata("main".to_string()),
+            [("timeline.name".to_string(), "main".into())]
+        );
+    }
+
+    #[test]
+    fn ticks_past_u64_spill_into_second_component() {
+        let max = u64::MAX as u128;
+        let tick = |nanos: u128| {
+            Duration::new(
+                (nanos / 1_000_000_000) as u64,
+                (nanos % 1_000_000_000) as u32,
+            )
+        };
+        assert_eq!(tick_logical_time(tick(5)), LogicalTime::unary(5));
+        assert_eq!(tick_logical_time(tick(max)), LogicalTime::unary(u64::MAX));
+        assert_eq!(tick_logical_time(tick(max + 1)), LogicalTime::binary(1, 0));
+        assert_eq!(tick_logical_time(tick(max + 8)), LogicalTime::binary(1, 7));
+        // the largest tick doesn't panic either
+        let _ = tick_logical_time(Duration::MAX);
     }
 }
@@ -0,0 +1,26 @@
+//! Values for `event.internal.rs.kind`.
+//!
+//! An event's kind is set with the `modality.kind` field, which the [`metric_event!`] and
+//! [`audit_event!`] macros do for you. Events without one are recorded with the kind [`EVENT`].
+//! Events the layer records about spans have `span:` kinds.
+//!
+//! [`metric_event!`]: crate::metric_event
+//! [`audit_event!`]: crate::audit_event
+
+/// A plain trace event, the default.
+pub const EVENT: &str = "event";
+/// A measurement, with its value in `event.value`.
+pub const METRIC: &str = "metric";
+/// A record of something done, for audit trails.
+pub const AUDIT: &str = "audit";
+/// A timeline is still alive but has been quiet, see
+/// [`Options::set_heartbeat_interval`](crate::Options::set_heartbeat_interval).
+pub const HEARTBEAT: &str = "heartbeat";
+/// Values recorded on a span after it was created, with `span.record`.
+pub const SPAN_RECORD: &str = "span:record";
+/// A summary of a span's polls, instead of their enter and exit events, see
+/// [`Options::set_poll_coalescing`](crate::Options::set_poll_coalescing).
+pub const SPAN_BUSY: &str = "span:busy";
+/// A span was marked as following from another, whose ID is in
+/// `event.internal.rs.follows_span_id`.
+pub const SPAN_FOLLOWS_FROM: &str = "span:follows_from";
@@ -1,46 +1,148 @@
 use crate::ingest::TimelineId;
+use crate::options::MaxLevel;
+use crate::Options;
 
 use crate::ingest;
-use crate::ingest::WrappedMessage;
+use crate::ingest::{IngestSender, WrappedMessage};
+use crate::span_context;
+use crate::timeline::{self, derive_timeline_id};
 
 use auxon_sdk::api::Nanoseconds;
 use duplicate::duplicate_item;
 use once_cell::sync::Lazy;
 use std::time::SystemTime;
 use std::{
-    cell::Cell,
-    collections::HashMap,
+    cell::{Cell, RefCell},
+    collections::{HashMap, HashSet},
     fmt::Debug,
     num::NonZeroU64,
     sync::atomic::{AtomicU64, Ordering},
-    sync::Once,
+    sync::Arc,
+    sync::{Mutex, Once},
     thread,
     thread::LocalKey,
-    time::Instant,
+    time::{Duration, Instant},
 };
-use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
 use tracing_core::{
+    callsite::Identifier,
     field::Visit,
     span::{Attributes, Id, Record},
-    Field, Subscriber,
+    Field, LevelFilter, Metadata, Subscriber,
 };
 use tracing_subscriber::{
-    layer::{Context, Layer},
+    layer::{Context, Filter, Layer},
     registry::LookupSpan,
 };
 
-static START: Lazy<Instant> = Lazy::new(Instant::now);
+/// When the first message was sent, on both clocks, sampled together so that later wall clock
+/// times can be derived from the monotonic clock.
+static START: Lazy<(Instant, SystemTime)> = Lazy::new(|| (Instant::now(), SystemTime::now()));
 static NEXT_SPAN_ID: AtomicU64 = AtomicU64::new(1);
+static NEXT_EVENT_ID: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    static LAST_EVENT_ID: Cell<Option<u64>> = const { Cell::new(None) };
+    /// Timelines from the timeline identifier this thread has already told the ingest thread
+    /// about, forgotten in bulk once there are [`MAX_ANNOUNCED_TIMELINES`] of them or when the
+    /// [`TIMELINE_CLOSES`] generation they were announced in has passed.
+    static ANNOUNCED_TIMELINES: RefCell<(u64, HashSet<TimelineId>)> =
+        RefCell::new((0, HashSet::new()));
+    static TIMELINE_END_GUARD: RefCell<Option<TimelineEndGuard>> = const { RefCell::new(None) };
+    /// How long recent callbacks on this thread took, for layer overhead diagnostics.
+    static OVERHEAD_SAMPLES: RefCell<Vec<Duration>> = const { RefCell::new(Vec::new()) };
+    /// The last `event.internal.rs.timeline_seq` given out on this thread's own timeline.
+    static THREAD_TIMELINE_SEQ: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Callsites whose schema has already been sent, see
+/// [`Options::set_callsite_schema`](crate::Options::set_callsite_schema).
+static SEEN_CALLSITES: Lazy<Mutex<HashSet<Identifier>>> = Lazy::new(Default::default);
+
+/// Bumped each time a timeline is [closed](crate::TracingModality::close_timeline), so every
+/// thread forgets which timelines it announced and announces a reused timeline again.
+static TIMELINE_CLOSES: AtomicU64 = AtomicU64::new(0);
+
+/// How many timelines each thread remembers having announced. The ingest ignores repeated
+/// announcements, so forgetting them only costs a message each.
+const MAX_ANNOUNCED_TIMELINES: usize = 1024;
+
+/// How many callbacks a thread times before reporting the layer's overhead.
+const OVERHEAD_BATCH: usize = 1024;
+
+/// Sends a `TimelineEnd` message for its timeline when dropped, which happens as the thread that
+/// owns it exits.
+struct TimelineEndGuard {
+    sender: IngestSender,
+    timeline: TimelineId,
+    tick_fn: Option<Arc<TickFn>>,
+}
+
+impl Drop for TimelineEndGuard {
+    fn drop(&mut self) {
+        // ingest may have already finished, nothing to be done about it then
+        let message = ingest::Message::TimelineEnd;
+        let _ = self.sender.send(wrap_message(
+            message,
+            self.timeline,
+            self.tick_fn.as_deref(),
+        ));
+    }
+}
 
 /// An ID for spans that we can use directly.
 #[derive(Copy, Clone, Debug)]
-pub(crate) struct LocalSpanId(NonZeroU64);
+pub(crate) struct LocalSpanId(pub(crate) NonZeroU64);
+
+/// The timeline a span was created on, stored in the span's extensions.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct SpanTimeline(pub(crate) TimelineId);
 
 /// A newtype to store the span's name in itself for later use.
 #[allow(dead_code)]
 #[derive(Clone, Debug)]
 pub(crate) struct SpanName(String);
 
+/// Every field a span has been defined or recorded with so far, stored in the span's extensions
+/// when something needs the span's complete field set later.
+#[derive(Clone, Debug)]
+pub(crate) struct SpanFields(RecordMap);
+
+/// How many times a span has been entered, stored in the span's extensions when
+/// [poll counts](crate::Options::set_span_poll_count) are enabled. For async spans that's the
+/// number of times the future was polled.
+#[derive(Copy, Clone, Debug, Default)]
+struct PollCount(u64);
+
+/// When a span was first entered, stored in the span's extensions when
+/// [span elapsed times](crate::Options::set_span_elapsed_on_events) are enabled.
+#[derive(Copy, Clone, Debug)]
+struct FirstEntered(Instant);
+
+/// What happened to a span, passed to the
+/// [span lifecycle callback](crate::Options::set_span_lifecycle_callback).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SpanLifecycleKind {
+    New,
+    Enter,
+    Exit,
+    Close,
+}
+
+/// A span lifecycle change, passed to the
+/// [span lifecycle callback](crate::Options::set_span_lifecycle_callback).
+#[derive(Copy, Clone, Debug)]
+pub struct SpanLifecycleEvent {
+    pub kind: SpanLifecycleKind,
+    /// The span's ID, as recorded in `event.internal.rs.span_id`.
+    pub span_id: NonZeroU64,
+    /// The span's name from its callsite.
+    pub name: &'static str,
+    /// The timeline the change was recorded on, the span's own timeline when a timeline identifier
+    /// chose it.
+    pub timeline: TimelineId,
+}
+
 pub(crate) struct LocalMetadata {
     pub(crate) thread_timeline: TimelineId,
 }
@@ -51,40 +153,168 @@ impl LayerCommon for crate::r#async::ModalityLayer {}
 impl LayerCommon for crate::blocking::ModalityLayer {}
 
 pub(crate) trait LayerHandler {
-    fn send(&self, msg: WrappedMessage) -> Result<(), mpsc::error::SendError<WrappedMessage>>;
+    /// Send a message without blocking, see [`IngestSender::send`].
+    fn send(&self, msg: WrappedMessage) -> Result<(), TrySendError<()>>;
+    fn sender(&self) -> &IngestSender;
+    fn options(&self) -> &Options;
+    /// Whether the ingest thread has been asked to finish, so failing to send is expected.
+    fn shutting_down(&self) -> bool;
     fn local_metadata(&self) -> &'static LocalKey<Lazy<LocalMetadata>>;
     fn thread_timeline_initialized(&self) -> &'static LocalKey<Cell<bool>>;
 }
 
 trait LayerCommon: LayerHandler {
-    fn handle_message(&self, message: ingest::Message) {
-        self.ensure_timeline_has_been_initialized();
-        let wrapped_message = ingest::WrappedMessage {
-            message,
-            tick: START.elapsed(),
-            nanos_since_unix_epoch: SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .ok()
-                .and_then(|d| {
-                    let n: Option<u64> = d.as_nanos().try_into().ok();
-                    n.map(Nanoseconds::from)
-                }),
-            timeline: self.local_metadata().with(|m| m.thread_timeline),
+    /// Send a message to the ingest thread, on the timeline for `metadata`, the metadata of the
+    /// event or span the message is about. Returns the timeline it was sent on.
+    fn handle_message(
+        &self,
+        message: ingest::Message,
+        metadata: Option<&'static Metadata<'static>>,
+    ) -> TimelineId {
+        let timeline = self.resolve_timeline(metadata);
+        self.send_on_timeline(message, timeline);
+        timeline
+    }
+
+    /// Send a message about an existing span, on the timeline the span was created on when
+    /// that's known, otherwise on the timeline [`handle_message`](Self::handle_message) would pick.
+    /// Either way a [`timeline_scope`](crate::timeline_scope) takes precedence.
+    fn handle_span_message(
+        &self,
+        message: ingest::Message,
+        span_timeline: Option<TimelineId>,
+    ) -> TimelineId {
+        let timeline = match span_timeline {
+            Some(timeline) if timeline::scoped_timeline().is_none() => timeline,
+            _ => self.resolve_timeline(None),
         };
+        self.send_on_timeline(message, timeline);
+        timeline
+    }
+
+    fn send_on_timeline(&self, message: ingest::Message, timeline: TimelineId) {
+        // numbered here rather than by the ingest, so messages dropped on the way show up as gaps
+        let seq = is_sent_as_event(&message, self.options()).then(|| self.next_seq(timeline));
+        let mut wrapped_message =
+            wrap_message(message, timeline, self.options().tick_fn.as_deref());
+        wrapped_message.seq = seq;
 
-        if let Err(_e) = self.send(wrapped_message) {
+        // a full channel is counted by the sender, and spans closing and the like after
+        // `finish()` are an expected part of shutting down
+        let sent = self.send(wrapped_message);
+        if matches!(sent, Err(TrySendError::Closed(_))) && !self.shutting_down() {
             static WARN_LATCH: Once = Once::new();
             WARN_LATCH.call_once(|| {
                 eprintln!(
-                    "warning: attempted trace after tracing modality has stopped accepting \
-                     messages, ensure spans from all threads have closed before calling \
-                     `finish()`"
+                    "warning: tracing modality stopped accepting messages before `finish()` was \
+                     called, trace events are being dropped"
                 );
             });
         }
     }
 
+    /// The next `event.internal.rs.timeline_seq` on `timeline`.
+    fn next_seq(&self, timeline: TimelineId) -> u64 {
+        if timeline == self.local_metadata().with(|m| m.thread_timeline) {
+            return THREAD_TIMELINE_SEQ.with(|seq| {
+                seq.set(seq.get() + 1);
+                seq.get()
+            });
+        }
+
+        self.sender().next_timeline_seq(timeline)
+    }
+
+    /// Time since `start`, if layer overhead diagnostics are on. A summary is sent once this thread
+    /// has a full batch of samples.
+    fn record_overhead(&self, start: Option<Instant>) {
+        let Some(start) = start else {
+            return;
+        };
+        let elapsed = start.elapsed();
+
+        let summary = OVERHEAD_SAMPLES.with(|samples| {
+            let mut samples = samples.borrow_mut();
+            samples.push(elapsed);
+            if samples.len() < OVERHEAD_BATCH {
+                return None;
+            }
+            samples.sort_unstable();
+            let p50 = samples[samples.len() / 2];
+            let p99 = samples[samples.len() * 99 / 100];
+            samples.clear();
+            Some(ingest::Message::LayerOverhead { p50, p99 })
+        });
+
+        if let Some(message) = summary {
+            // the ingest thread puts it on the root timeline regardless
+            let timeline = self.local_metadata().with(|m| m.thread_timeline);
+            let _ = self.send(wrap_message(
+                message,
+                timeline,
+                self.options().tick_fn.as_deref(),
+            ));
+        }
+    }
+
+    /// Send the fields `metadata`'s callsite declares, if this is the first time it's been seen and
+    /// callsite schemas are on.
+    fn record_callsite_schema(&self, metadata: &'static Metadata<'static>) {
+        if !self.options().callsite_schema {
+            return;
+        }
+
+        let first_seen = SEEN_CALLSITES
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(metadata.callsite());
+        if first_seen {
+            // the ingest thread puts it on the root timeline regardless
+            let timeline = self.local_metadata().with(|m| m.thread_timeline);
+            let _ = self.send(wrap_message(
+                ingest::Message::CallsiteSchema { metadata },
+                timeline,
+                self.options().tick_fn.as_deref(),
+            ));
+        }
+    }
+
+    /// Give events and spans without an explicit kind the one from the kind function, if any.
+    fn derive_kind(&self, records: &mut RecordMap, metadata: &'static Metadata<'static>) {
+        let Some(kind_fn) = &self.options().kind_fn else {
+            return;
+        };
+        if records.contains_key("modality.kind") {
+            return;
+        }
+        if let Some(kind) = kind_fn(metadata) {
+            records.insert("modality.kind".to_string(), TracingValue::String(kind));
+        }
+    }
+
+    /// Tell the lifecycle callback, if any, about a span message just sent on `timeline`.
+    fn span_lifecycle(
+        &self,
+        kind: SpanLifecycleKind,
+        span_id: LocalSpanId,
+        metadata: &'static Metadata<'static>,
+        timeline: TimelineId,
+    ) {
+        if let Some(callback) = &self.options().span_lifecycle_callback {
+            callback(SpanLifecycleEvent {
+                kind,
+                span_id: span_id.0,
+                name: metadata.name(),
+                timeline,
+            });
+        }
+    }
+
     fn get_next_span_id(&self) -> LocalSpanId {
+        if let Some(source) = &self.options().span_id_source {
+            return LocalSpanId(source());
+        }
+
         loop {
             // ordering of IDs doesn't matter, only uniqueness, use relaxed ordering
             let id = NEXT_SPAN_ID.fetch_add(1, Ordering::Relaxed);
@@ -94,34 +324,212 @@ trait LayerCommon: LayerHandler {
         }
     }
 
+    /// The timeline a message about `metadata` belongs on. The ingest thread is told about the
+    /// timeline first if this thread hasn't used it before.
+    fn resolve_timeline(&self, metadata: Option<&'static Metadata<'static>>) -> TimelineId {
+        let info = match (
+            timeline::scoped_timeline(),
+            &self.options().timeline_identifier,
+        ) {
+            (Some(info), _) => info,
+            (None, Some(identifier)) => identifier(metadata),
+            (None, None) => {
+                self.ensure_timeline_has_been_initialized();
+                return self.local_metadata().with(|m| m.thread_timeline);
+            }
+        };
+        let run_id = self.options().run_id.get();
+        let timeline = info.timeline_id(&run_id);
+
+        let first_use = ANNOUNCED_TIMELINES.with(|announced| {
+            let (generation, announced) = &mut *announced.borrow_mut();
+            let closes = TIMELINE_CLOSES.load(Ordering::Acquire);
+            if *generation != closes
+                || (announced.len() >= MAX_ANNOUNCED_TIMELINES && !announced.contains(&timeline))
+            {
+                *generation = closes;
+                announced.clear();
+            }
+            announced.insert(timeline)
+        });
+        if first_use {
+            let message = ingest::Message::NewTimeline {
+                name: info.name.clone(),
+                run_id: Some(run_id),
+                user_id: Some(info.user_id),
+                parent: info
+                    .parent_user_id
+                    .map(|parent| derive_timeline_id(&run_id, parent)),
+            };
+            let sent = self.send(wrap_message(
+                message,
+                timeline,
+                self.options().tick_fn.as_deref(),
+            ));
+            // try announcing it again with the next message rather than leaving it unnamed
+            if sent.is_err() {
+                ANNOUNCED_TIMELINES.with(|announced| announced.borrow_mut().1.remove(&timeline));
+            }
+        }
+
+        timeline
+    }
+
     fn ensure_timeline_has_been_initialized(&self) {
         if !self.thread_timeline_initialized().with(|i| i.get()) {
-            self.thread_timeline_initialized().with(|i| i.set(true));
-
             let cur = thread::current();
             let name = cur
                 .name()
                 .map(Into::into)
                 .unwrap_or_else(|| format!("thread-{:?}", cur.id()));
 
-            let message = ingest::Message::NewTimeline { name };
-            let wrapped_message = ingest::WrappedMessage {
-                message,
-                tick: START.elapsed(),
-                nanos_since_unix_epoch: SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .ok()
-                    .and_then(|d| {
-                        let n: Option<u64> = d.as_nanos().try_into().ok();
-                        n.map(Nanoseconds::from)
-                    }),
-                timeline: self.local_metadata().with(|m| m.thread_timeline),
+            let message = ingest::Message::NewTimeline {
+                name,
+                run_id: None,
+                user_id: None,
+                parent: None,
             };
+            let wrapped_message = wrap_message(
+                message,
+                self.local_metadata().with(|m| m.thread_timeline),
+                self.options().tick_fn.as_deref(),
+            );
+
+            // only counts as initialized once the timeline has been named, otherwise try again with
+            // the next message
+            if self.send(wrapped_message).is_err() {
+                return;
+            }
+            self.thread_timeline_initialized().with(|i| i.set(true));
+
+            if self.options().timeline_end_marker {
+                let guard = TimelineEndGuard {
+                    sender: self.sender().clone(),
+                    timeline: self.local_metadata().with(|m| m.thread_timeline),
+                    tick_fn: self.options().tick_fn.clone(),
+                };
+                // can only fail while the thread is already being torn down
+                let _ = TIMELINE_END_GUARD.try_with(|g| *g.borrow_mut() = Some(guard));
+            }
+        }
+    }
+}
+
+pub(crate) type TickFn = dyn Fn() -> Duration + Send + Sync;
 
-            // ignore failures, exceedingly unlikely here, will get caught in `handle_message`
-            let _ = self.send(wrapped_message);
+/// Filters out events and spans below the [max level](crate::Options::set_max_level) for the
+/// modality layer alone, leaving any other layers on the same subscriber unaffected. Get one from
+/// the layer's `max_level_filter` and apply it with
+/// [`Layer::with_filter`](tracing_subscriber::Layer::with_filter).
+///
+/// It follows changes made to the max level at runtime.
+#[derive(Clone)]
+pub struct MaxLevelFilter(pub(crate) MaxLevel);
+
+impl<S> Filter<S> for MaxLevelFilter {
+    fn enabled(&self, metadata: &Metadata<'_>, _ctx: &Context<'_, S>) -> bool {
+        metadata.level() <= &self.0.get()
+    }
+
+    fn callsite_enabled(
+        &self,
+        metadata: &'static Metadata<'static>,
+    ) -> tracing_core::subscriber::Interest {
+        // the level is all `enabled` looks at, so the answer can be cached per callsite and the
+        // macro won't evaluate field values for callsites below the max level of every layer.
+        // Changing the level rebuilds the cache.
+        if metadata.level() <= &self.0.get() {
+            tracing_core::subscriber::Interest::always()
+        } else {
+            tracing_core::subscriber::Interest::never()
         }
     }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        Some(self.0.get())
+    }
+}
+
+/// The `event.internal.rs.event_id` of the last event recorded on this thread, to log alongside
+/// application data for cross-referencing. `None` unless
+/// [`Options::set_event_ids`](crate::Options::set_event_ids) is enabled.
+pub fn last_event_id() -> Option<u64> {
+    LAST_EVENT_ID.with(Cell::get)
+}
+
+/// Wrap a message for the ingest thread, sampling the tick and wall clock time on the producing
+/// thread so that queueing delay in front of the ingest thread doesn't skew either of them.
+///
+/// The tick is the time since the first message, and the wall clock time is derived from it, so
+/// both come from a single clock reading and refer to exactly the same instant. That also means
+/// adjustments to the system clock after the first message aren't reflected. If a `tick_fn` was
+/// [configured](crate::Options::set_tick_fn) the tick comes from that instead, and the wall clock
+/// is read separately.
+pub(crate) fn wrap_message(
+    message: ingest::Message,
+    timeline: TimelineId,
+    tick_fn: Option<&TickFn>,
+) -> WrappedMessage {
+    let (start, start_wall_clock) = *START;
+    let (tick, wall_clock) = match tick_fn {
+        Some(tick_fn) => (tick_fn(), SystemTime::now()),
+        None => {
+            let tick = start.elapsed();
+            (tick, start_wall_clock + tick)
+        }
+    };
+
+    ingest::WrappedMessage {
+        message,
+        tick,
+        nanos_since_unix_epoch: wall_clock
+            .duration_since(std::time::UNIX_EPOCH)
+            .ok()
+            .and_then(|d| {
+                let n: Option<u64> = d.as_nanos().try_into().ok();
+                n.map(Nanoseconds::from)
+            }),
+        timeline,
+        seq: None,
+    }
+}
+
+/// Whether the ingest sends `message` on as exactly one event, so it's given the next
+/// `event.internal.rs.timeline_seq`. Coalesced polls and spans closing without a close event
+/// aren't sent as events of their own.
+fn is_sent_as_event(message: &ingest::Message, options: &Options) -> bool {
+    match message {
+        ingest::Message::NewSpan { .. }
+        | ingest::Message::Record { .. }
+        | ingest::Message::RecordFollowsFrom { .. }
+        | ingest::Message::Event { .. } => true,
+        ingest::Message::Enter { .. } | ingest::Message::Exit { .. } => {
+            options.poll_coalescing.is_none()
+        }
+        ingest::Message::Close { records, .. } => records.is_some(),
+        _ => false,
+    }
+}
+
+/// Have every thread announce timelines again before next using them, after one was closed.
+pub(crate) fn forget_announced_timelines() {
+    TIMELINE_CLOSES.fetch_add(1, Ordering::Release);
+}
+
+/// The timeline a span was created on, if a timeline identifier chose it. Without an identifier
+/// every message goes on the thread's timeline, so the lookup is skipped.
+fn identifier_span_timeline<S>(
+    options: &Options,
+    span: &Id,
+    ctx: &Context<'_, S>,
+) -> Option<TimelineId>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    options.timeline_identifier.as_ref()?;
+    let span = ctx.span(span)?;
+    let timeline = span.extensions().get::<SpanTimeline>()?.0;
+    Some(timeline)
 }
 
 fn get_local_span_id<S>(span: &Id, ctx: &Context<'_, S>) -> LocalSpanId
@@ -136,6 +544,69 @@ where
         .expect("get `LocalSpanId`, should always exist on spans")
 }
 
+/// Copy the fields of each span in the event's scope onto the event's records as `span.<field>`.
+/// The scope is walked from the innermost span outwards, so inner spans take precedence, and
+/// fields already present on the event are left alone.
+fn inherit_span_fields<S>(
+    records: &mut RecordMap,
+    event: &tracing_core::Event<'_>,
+    ctx: &Context<'_, S>,
+) where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let Some(scope) = ctx.event_scope(event) else {
+        return;
+    };
+
+    for span in scope {
+        if let Some(fields) = span.extensions().get::<SpanFields>() {
+            for (name, value) in &fields.0 {
+                records
+                    .entry(format!("span.{}", name))
+                    .or_insert_with(|| value.clone());
+            }
+        }
+    }
+}
+
+/// How long the nearest entered span in the event's scope has been open.
+fn span_elapsed<S>(event: &tracing_core::Event<'_>, ctx: &Context<'_, S>) -> Option<Duration>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    ctx.event_scope(event)?
+        .find_map(|span| span.extensions().get::<FirstEntered>().copied())
+        .map(|FirstEntered(entered)| entered.elapsed())
+}
+
+/// Copy the named fields of each span in the event's scope onto the event's records as
+/// `<span name>.<field>`. Inner spans take precedence over outer spans with the same name, and
+/// fields already present on the event are left alone.
+fn collect_ancestor_fields<S>(
+    records: &mut RecordMap,
+    names: &[String],
+    event: &tracing_core::Event<'_>,
+    ctx: &Context<'_, S>,
+) where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let Some(scope) = ctx.event_scope(event) else {
+        return;
+    };
+
+    for span in scope {
+        if let Some(fields) = span.extensions().get::<SpanFields>() {
+            for name in names {
+                if let Some(value) = fields.0.get(name) {
+                    records
+                        .entry(format!("{}.{}", span.name(), name))
+                        .or_insert_with(|| value.clone());
+                }
+            }
+        }
+    }
+}
+
 #[cfg(feature = "blocking")]
 use crate::blocking::ModalityLayer as BlockingModalityLayer;
 #[cfg(feature = "async")]
@@ -151,19 +622,35 @@ impl<S> Layer<S> for layer
 where
     S: Subscriber + for<'a> LookupSpan<'a>,
 {
-    fn enabled(&self, _metadata: &tracing_core::Metadata<'_>, _ctx: Context<'_, S>) -> bool {
-        // always enabled for all levels
-        true
-    }
-
     fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let start = self.options().layer_overhead_diagnostics.then(Instant::now);
         let local_id = self.get_next_span_id();
         ctx.span(id).unwrap().extensions_mut().insert(local_id);
 
         let mut visitor = RecordMapBuilder::new();
         attrs.record(&mut visitor);
-        let records = visitor.values();
+        let mut records = visitor.values();
         let metadata = attrs.metadata();
+        self.record_callsite_schema(metadata);
+
+        if let Some(imported) = span_context::take_imported_span() {
+            records.insert(
+                "interaction.remote_timeline_id".to_string(),
+                TracingValue::String(imported.timeline_id.get_raw().to_string()),
+            );
+            records.insert(
+                "interaction.remote_span_id".to_string(),
+                TracingValue::U64(imported.span_id.get()),
+            );
+        }
+
+        if self.options().keep_span_fields() {
+            ctx.span(id)
+                .unwrap()
+                .extensions_mut()
+                .insert(SpanFields(records.clone()));
+        }
+        self.derive_kind(&mut records, metadata);
 
         let msg = ingest::Message::NewSpan {
             id: local_id.0,
@@ -171,7 +658,13 @@ where
             records,
         };
 
-        self.handle_message(msg);
+        let timeline = self.handle_message(msg, Some(metadata));
+        ctx.span(id)
+            .unwrap()
+            .extensions_mut()
+            .insert(SpanTimeline(timeline));
+        self.span_lifecycle(SpanLifecycleKind::New, local_id, metadata, timeline);
+        self.record_overhead(start);
     }
 
     fn on_record(&self, span: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
@@ -179,13 +672,25 @@ where
 
         let mut visitor = RecordMapBuilder::new();
         values.record(&mut visitor);
+        let records = visitor.values();
+
+        if self.options().keep_span_fields() {
+            if let Some(fields) = ctx
+                .span(span)
+                .unwrap()
+                .extensions_mut()
+                .get_mut::<SpanFields>()
+            {
+                fields.0.extend(records.clone());
+            }
+        }
 
         let msg = ingest::Message::Record {
             span: local_id.0,
-            records: visitor.values(),
+            records,
         };
 
-        self.handle_message(msg)
+        self.handle_span_message(msg, identifier_span_timeline(self.options(), span, &ctx));
     }
 
     fn on_follows_from(&self, span: &Id, follows: &Id, ctx: Context<'_, S>) {
@@ -197,27 +702,111 @@ where
             follows: follows_local_id.0,
         };
 
-        self.handle_message(msg)
+        self.handle_span_message(msg, identifier_span_timeline(self.options(), span, &ctx));
     }
 
-    fn on_event(&self, event: &tracing_core::Event<'_>, _ctx: Context<'_, S>) {
-        let mut visitor = RecordMapBuilder::new();
-        event.record(&mut visitor);
+    fn on_event(&self, event: &tracing_core::Event<'_>, ctx: Context<'_, S>) {
+        let start = self.options().layer_overhead_diagnostics.then(Instant::now);
+        self.record_callsite_schema(event.metadata());
+        let fields = event.metadata().fields();
+        let mut visitor = RecordMapBuilder::with_capacity(fields.len());
+        // nothing to visit for events with no fields, skip straight to sending
+        if !fields.is_empty() {
+            event.record(&mut visitor);
+        }
+        let mut records = visitor.values();
+
+        if self.options().span_field_inheritance {
+            inherit_span_fields(&mut records, event, &ctx);
+        }
+        if !self.options().ancestor_fields.is_empty() {
+            collect_ancestor_fields(&mut records, &self.options().ancestor_fields, event, &ctx);
+        }
+
+        if self.options().span_elapsed_on_events {
+            if let Some(elapsed) = span_elapsed(event, &ctx) {
+                records.insert(
+                    "modality.span_elapsed_ns".to_string(),
+                    TracingValue::U64(elapsed.as_nanos().try_into().unwrap_or(u64::MAX)),
+                );
+            }
+        }
+
+        if self.options().event_ids {
+            // like span IDs, only uniqueness matters
+            let event_id = NEXT_EVENT_ID.fetch_add(1, Ordering::Relaxed);
+            LAST_EVENT_ID.with(|last| last.set(Some(event_id)));
+            records.insert("modality.event_id".to_string(), TracingValue::U64(event_id));
+
+            // an explicit causal link to another event, by its ID
+            if let Some(cause) = records.remove("modality.cause.event_id") {
+                records.insert("interaction.cause_event_id".to_string(), cause);
+            }
+        }
+
+        self.derive_kind(&mut records, event.metadata());
+
+        if let Some(observer) = &self.options().event_observer {
+            observer(event.metadata(), &records);
+        }
+
+        let context = match &self.options().context_provider {
+            Some(provider) => provider().into_boxed_slice(),
+            None => Box::default(),
+        };
 
         let msg = ingest::Message::Event {
             metadata: event.metadata(),
-            records: visitor.values(),
+            records,
+            context,
         };
 
-        self.handle_message(msg)
+        self.handle_message(msg, Some(event.metadata()));
+        self.record_overhead(start);
     }
 
     fn on_enter(&self, span: &Id, ctx: Context<'_, S>) {
         let local_id = get_local_span_id(span, &ctx);
 
-        let msg = ingest::Message::Enter { span: local_id.0 };
+        let records = match ctx.span(span) {
+            Some(span_ref) if self.options().fields_on_enter => span_ref
+                .extensions()
+                .get::<SpanFields>()
+                .map(|fields| fields.0.clone()),
+            _ => None,
+        };
+        let msg = ingest::Message::Enter {
+            span: local_id.0,
+            records,
+        };
 
-        self.handle_message(msg)
+        if self.options().span_poll_count {
+            if let Some(span_ref) = ctx.span(span) {
+                let mut extensions = span_ref.extensions_mut();
+                match extensions.get_mut::<PollCount>() {
+                    Some(count) => count.0 += 1,
+                    None => extensions.insert(PollCount(1)),
+                }
+            }
+        }
+        if self.options().span_elapsed_on_events {
+            if let Some(span_ref) = ctx.span(span) {
+                let mut extensions = span_ref.extensions_mut();
+                if extensions.get_mut::<FirstEntered>().is_none() {
+                    extensions.insert(FirstEntered(Instant::now()));
+                }
+            }
+        }
+
+        let timeline =
+            self.handle_span_message(msg, identifier_span_timeline(self.options(), span, &ctx));
+        if self.options().span_lifecycle_callback.is_some() {
+            let metadata = ctx
+                .span(span)
+                .expect("get span tracing just told us about")
+                .metadata();
+            self.span_lifecycle(SpanLifecycleKind::Enter, local_id, metadata, timeline);
+        }
     }
 
     fn on_exit(&self, span: &Id, ctx: Context<'_, S>) {
@@ -225,33 +814,77 @@ where
 
         let msg = ingest::Message::Exit { span: local_id.0 };
 
-        self.handle_message(msg)
+        let timeline =
+            self.handle_span_message(msg, identifier_span_timeline(self.options(), span, &ctx));
+        if self.options().span_lifecycle_callback.is_some() {
+            let metadata = ctx
+                .span(span)
+                .expect("get span tracing just told us about")
+                .metadata();
+            self.span_lifecycle(SpanLifecycleKind::Exit, local_id, metadata, timeline);
+        }
     }
 
     fn on_id_change(&self, old: &Id, new: &Id, ctx: Context<'_, S>) {
         let old_local_id = get_local_span_id(old, &ctx);
         let new_local_id = self.get_next_span_id();
         ctx.span(new).unwrap().extensions_mut().insert(new_local_id);
+        // the new ID is for the same span, on the same timeline
+        let span_timeline = ctx
+            .span(old)
+            .and_then(|old| old.extensions().get::<SpanTimeline>().copied());
+        if let Some(span_timeline) = span_timeline {
+            ctx.span(new)
+                .unwrap()
+                .extensions_mut()
+                .insert(span_timeline);
+        }
 
         let msg = ingest::Message::IdChange {
             old: old_local_id.0,
             new: new_local_id.0,
         };
 
-        self.handle_message(msg)
+        self.handle_span_message(msg, identifier_span_timeline(self.options(), new, &ctx));
     }
 
     fn on_close(&self, span: Id, ctx: Context<'_, S>) {
         let local_id = get_local_span_id(&span, &ctx);
+        let span_ref = ctx
+            .span(&span)
+            .expect("get span tracing just told us about");
+        let fields = span_ref.extensions_mut().remove::<SpanFields>();
+        // fields may also be stored for inheritance, only send them if a snapshot was asked for
+        let mut records = fields
+            .filter(|_| self.options().span_close_snapshot)
+            .map(|fields| fields.0);
 
-        let msg = ingest::Message::Close { span: local_id.0 };
+        let poll_count = if self.options().span_poll_count {
+            let count = span_ref.extensions_mut().remove::<PollCount>();
+            // the poll count needs a close event to go on, even without a snapshot
+            records.get_or_insert_with(RecordMap::new);
+            Some(count.unwrap_or_default().0)
+        } else {
+            None
+        };
+
+        let metadata = span_ref.metadata();
+        let msg = ingest::Message::Close {
+            span: local_id.0,
+            metadata,
+            records,
+            poll_count,
+        };
 
-        self.handle_message(msg)
+        let timeline =
+            self.handle_span_message(msg, identifier_span_timeline(self.options(), &span, &ctx));
+        self.span_lifecycle(SpanLifecycleKind::Close, local_id, metadata, timeline);
     }
 }
 
-#[derive(Debug)]
-pub(crate) enum TracingValue {
+/// A field value as captured from `tracing`, before conversion to a modality attribute value.
+#[derive(Clone, Debug)]
+pub enum TracingValue {
     String(String),
     F64(f64),
     I64(i64),
@@ -259,7 +892,22 @@ pub(crate) enum TracingValue {
     Bool(bool),
 }
 
-pub(crate) type RecordMap = HashMap<String, TracingValue>;
+impl TracingValue {
+    /// The name of the type the value was captured as, `string` for both strings and `Debug`
+    /// formatted values.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            TracingValue::String(_) => "string",
+            TracingValue::F64(_) => "f64",
+            TracingValue::I64(_) => "i64",
+            TracingValue::U64(_) => "u64",
+            TracingValue::Bool(_) => "bool",
+        }
+    }
+}
+
+/// The fields captured from a span or event, by field name.
+pub type RecordMap = HashMap<String, TracingValue>;
 
 struct RecordMapBuilder {
     record_map: RecordMap,
@@ -278,6 +926,13 @@ impl RecordMapBuilder {
             record_map: HashMap::new(),
         }
     }
+
+    /// Create a builder with space for `capacity` fields, which doesn't allocate when it's zero.
+    fn with_capacity(capacity: usize) -> RecordMapBuilder {
+        RecordMapBuilder {
+            record_map: HashMap::with_capacity(capacity),
+        }
+    }
 }
 
 impl Visit for RecordMapBuilder {
@@ -315,3 +970,643 @@ impl Visit for RecordMapBuilder {
         );
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Run `f` with a blocking modality layer sending to `sender` as the default subscriber.
+    #[cfg(feature = "blocking")]
+    pub(crate) fn trace_with(sender: IngestSender, options: Options, f: impl FnOnce()) {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let layer = crate::blocking::ModalityLayer::with_sender(sender, options);
+        let subscriber = tracing_subscriber::Registry::default().with(layer);
+        tracing::subscriber::with_default(subscriber, f);
+    }
+
+    /// Run `f` like [`trace_with`], and return every message the layer sent.
+    #[cfg(feature = "blocking")]
+    pub(crate) fn capture(options: Options, f: impl FnOnce()) -> Vec<WrappedMessage> {
+        let (sender, mut recv) = ingest::ingest_channel(None);
+        trace_with(sender, options, f);
+
+        let mut messages = Vec::new();
+        while let Ok(wrapped) = recv.try_recv() {
+            messages.push(wrapped);
+        }
+        messages
+    }
+
+    /// The fields of each event [`capture`] saw, in order.
+    #[cfg(feature = "blocking")]
+    pub(crate) fn capture_events(options: Options, f: impl FnOnce()) -> Vec<RecordMap> {
+        capture(options, f)
+            .into_iter()
+            .filter_map(|wrapped| match wrapped.message {
+                ingest::Message::Event { records, .. } => Some(records),
+                _ => None,
+            })
+            .collect()
+    }
+
+    struct TestLayer {
+        sender: IngestSender,
+        options: Options,
+    }
+
+    impl TestLayer {
+        thread_local! {
+            static LOCAL_METADATA: Lazy<LocalMetadata> = Lazy::new(|| LocalMetadata {
+                thread_timeline: ingest::current_timeline(),
+            });
+            static THREAD_TIMELINE_INITIALIZED: Cell<bool> = const { Cell::new(false) };
+        }
+    }
+
+    impl LayerHandler for TestLayer {
+        fn send(&self, msg: WrappedMessage) -> Result<(), TrySendError<()>> {
+            self.sender.send(msg)
+        }
+
+        fn sender(&self) -> &IngestSender {
+            &self.sender
+        }
+
+        fn options(&self) -> &Options {
+            &self.options
+        }
+
+        fn shutting_down(&self) -> bool {
+            false
+        }
+
+        fn local_metadata(&self) -> &'static LocalKey<Lazy<LocalMetadata>> {
+            &Self::LOCAL_METADATA
+        }
+
+        fn thread_timeline_initialized(&self) -> &'static LocalKey<Cell<bool>> {
+            &Self::THREAD_TIMELINE_INITIALIZED
+        }
+    }
+
+    impl LayerCommon for TestLayer {}
+
+    #[test]
+    fn timeline_initialization_retried_after_failed_send() {
+        // ingest has gone away, the first message on the thread can't name the timeline
+        let (sender, recv) = ingest::ingest_channel(None);
+        drop(recv);
+        let layer = TestLayer {
+            sender,
+            options: Options::new(),
+        };
+        layer.ensure_timeline_has_been_initialized();
+        assert!(!TestLayer::THREAD_TIMELINE_INITIALIZED.with(|i| i.get()));
+
+        let (sender, mut recv) = ingest::ingest_channel(None);
+        let layer = TestLayer {
+            sender,
+            options: Options::new(),
+        };
+        layer.ensure_timeline_has_been_initialized();
+        assert!(TestLayer::THREAD_TIMELINE_INITIALIZED.with(|i| i.get()));
+        assert!(matches!(
+            recv.try_recv().map(|m| m.message),
+            Ok(ingest::Message::NewTimeline { run_id: None, .. })
+        ));
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn dropped_messages_leave_timeline_seq_gaps() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let (sender, mut recv) = ingest::ingest_channel(Some(3));
+        let layer = crate::blocking::ModalityLayer::with_sender(sender, Options::new());
+        let subscriber = tracing_subscriber::Registry::default().with(layer);
+
+        let mut seqs = Vec::new();
+        tracing::subscriber::with_default(subscriber, || {
+            // the timeline's NewTimeline and two events fit, the last three are dropped
+            for i in 0..5 {
+                tracing::info!(i, "event");
+            }
+            while let Ok(wrapped) = recv.try_recv() {
+                seqs.push(wrapped.seq);
+            }
+            tracing::info!("after the drop");
+        });
+        while let Ok(wrapped) = recv.try_recv() {
+            seqs.push(wrapped.seq);
+        }
+
+        assert_eq!(seqs, [None, Some(1), Some(2), Some(6)]);
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn span_context_uses_the_span_timeline() {
+        use crate::{current_span_context, timeline_scope, UserTimelineInfo};
+
+        let options = Options::new();
+        let run_id = options.run_id.get();
+
+        let info = UserTimelineInfo::new("scoped", 7);
+        let mut context = None;
+        capture(options, || {
+            let span = {
+                let _scope = timeline_scope(info.clone());
+                tracing::info_span!("s")
+            };
+            // the scope has ended, but the span stays on the timeline it was created on
+            let _entered = span.enter();
+            context = current_span_context();
+        });
+
+        assert_eq!(context.unwrap().timeline_id, info.timeline_id(&run_id));
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn identifier_called_once_per_span() {
+        use crate::UserTimelineInfo;
+        use std::sync::atomic::AtomicUsize;
+
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        let options = Options::new().with_timeline_identifier(|| {
+            // a different timeline each call, as if the span moved to another task
+            let call = CALLS.fetch_add(1, Ordering::Relaxed) as u64;
+            UserTimelineInfo::new(format!("task-{call}"), call)
+        });
+
+        let messages = capture(options, || {
+            let span = tracing::info_span!("s", x = tracing::field::Empty);
+            span.record("x", 1u64);
+            span.in_scope(|| {});
+        });
+
+        assert_eq!(CALLS.load(Ordering::Relaxed), 1);
+        let timelines: HashSet<_> = messages.iter().map(|wrapped| wrapped.timeline).collect();
+        assert_eq!(timelines.len(), 1);
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn tick_fn_assigns_ticks() {
+        let counter = Arc::new(AtomicU64::new(0));
+        let ticks = counter.clone();
+        let options = Options::new()
+            .with_tick_fn(move || Duration::from_nanos(ticks.fetch_add(1, Ordering::Relaxed) + 1));
+
+        let messages = capture(options, || {
+            tracing::info_span!("s").in_scope(|| tracing::info!("event"));
+        });
+
+        let assigned: Vec<u64> = messages
+            .iter()
+            .map(|wrapped| wrapped.tick.as_nanos() as u64)
+            .collect();
+        // one tick per message, in the order they were sent
+        let expected: Vec<u64> = (1..=counter.load(Ordering::Relaxed)).collect();
+        assert_eq!(assigned, expected);
+        assert!(assigned.len() >= 5, "{assigned:?}");
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn deterministic_runs_send_identical_messages() {
+        use std::collections::BTreeMap;
+
+        let run_id = uuid::Uuid::new_v4();
+        let thread_timeline = TimelineId::from(uuid::Uuid::new_v4());
+        let run = move || {
+            // a fresh thread per run, so the thread timeline can be pinned, named so the timeline
+            // isn't named by thread ID
+            let sent = std::thread::Builder::new()
+                .name("worker".to_string())
+                .spawn(move || {
+                    crate::set_timeline_id(thread_timeline).unwrap();
+                    capture(Options::deterministic(run_id), || {
+                        tracing::info_span!("s", x = 1u64)
+                            .in_scope(|| tracing::info!(y = 2u64, "event"));
+                        let _scope =
+                            crate::timeline_scope(crate::UserTimelineInfo::new("scoped", 1));
+                        tracing::warn!("scoped event");
+                    })
+                })
+                .unwrap()
+                .join()
+                .unwrap();
+
+            let mut messages = Vec::new();
+            for mut wrapped in sent {
+                // sampled regardless, the ingest drops it with wall clock timestamps off
+                wrapped.nanos_since_unix_epoch = None;
+                // fields are unordered, compare them sorted
+                let records = match &mut wrapped.message {
+                    ingest::Message::NewSpan { records, .. }
+                    | ingest::Message::Event { records, .. } => std::mem::take(records),
+                    _ => RecordMap::new(),
+                };
+                let records: BTreeMap<_, _> = records
+                    .into_iter()
+                    .map(|(k, v)| (k, format!("{v:?}")))
+                    .collect();
+                messages.push(format!("{wrapped:?} {records:?}"));
+            }
+            messages
+        };
+
+        let first = run();
+        assert!(first.len() >= 6, "{first:?}");
+        assert_eq!(first, run());
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn interaction_macros_record_interaction_fields() {
+        let remote_timeline = TimelineId::from(uuid::Uuid::new_v4());
+        let events = capture_events(Options::new(), || {
+            crate::interaction_send!(7u64, "sending");
+            crate::interaction_receive!(remote_timeline, 7u64, job = 3u64);
+        });
+        let [sent, received] = &events[..] else {
+            panic!("expected two events, got {events:?}");
+        };
+
+        assert!(matches!(
+            sent.get("interaction.nonce"),
+            Some(TracingValue::U64(7))
+        ));
+        assert!(matches!(sent.get("message"), Some(TracingValue::String(m)) if m == "sending"));
+        assert!(matches!(
+            received.get("interaction.remote_timeline_id"),
+            Some(TracingValue::String(id)) if *id == remote_timeline.to_string()
+        ));
+        assert!(matches!(
+            received.get("interaction.remote_nonce"),
+            Some(TracingValue::U64(7))
+        ));
+        assert!(matches!(received.get("job"), Some(TracingValue::U64(3))));
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn lifecycle_callback_doesnt_rerun_identifier() {
+        use std::sync::{atomic::AtomicUsize, Mutex};
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let options = Options::new()
+            .with_timeline_identifier({
+                let calls = calls.clone();
+                move || {
+                    let n = calls.fetch_add(1, Ordering::Relaxed) as u64;
+                    crate::UserTimelineInfo::new("identified", n)
+                }
+            })
+            .with_span_lifecycle_callback({
+                let events = events.clone();
+                move |event| events.lock().unwrap().push((event.kind, event.timeline))
+            });
+        let run_id = options.run_id.get();
+
+        capture(options, || tracing::info_span!("request").in_scope(|| {}));
+
+        // the identifier chose the span's timeline once, every lifecycle change is reported on it
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+        let timeline = derive_timeline_id(&run_id, 0);
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                (SpanLifecycleKind::New, timeline),
+                (SpanLifecycleKind::Enter, timeline),
+                (SpanLifecycleKind::Exit, timeline),
+                (SpanLifecycleKind::Close, timeline),
+            ]
+        );
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn timeline_scope_overrides_identifier() {
+        let options = Options::new()
+            .with_timeline_identifier(|| crate::UserTimelineInfo::new("identified", 1));
+        let run_id = options.run_id.get();
+
+        let messages = capture(options, || {
+            {
+                let _scope = crate::timeline_scope(crate::UserTimelineInfo::new("scoped", 2));
+                tracing::info!("in scope");
+            }
+            tracing::info!("after scope");
+        });
+
+        let timelines: Vec<_> = messages
+            .iter()
+            .filter(|wrapped| matches!(wrapped.message, ingest::Message::Event { .. }))
+            .map(|wrapped| wrapped.timeline)
+            .collect();
+        assert_eq!(
+            timelines,
+            vec![
+                derive_timeline_id(&run_id, 2),
+                derive_timeline_id(&run_id, 1)
+            ]
+        );
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn empty_fields_only_recorded_once_filled() {
+        let options = Options::new().with_span_close_snapshot(true);
+        let messages = capture(options, || {
+            let span = tracing::info_span!("s", x = tracing::field::Empty);
+            span.record("x", 5u64);
+        });
+
+        let mut defined = None;
+        let mut closed = None;
+        for wrapped in messages {
+            match wrapped.message {
+                ingest::Message::NewSpan { records, .. } => defined = Some(records),
+                ingest::Message::Close { records, .. } => closed = records,
+                _ => {}
+            }
+        }
+
+        assert!(!defined.expect("span defined").contains_key("x"));
+        let closed = closed.expect("span closed with a snapshot");
+        assert!(matches!(closed.get("x"), Some(TracingValue::U64(5))));
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn events_record_span_elapsed() {
+        let options = Options::new().with_span_elapsed_on_events(true);
+        let events = capture_events(options, || {
+            tracing::info!("outside");
+            let _entered = tracing::info_span!("s").entered();
+            std::thread::sleep(Duration::from_millis(20));
+            tracing::info!("inside");
+        });
+        let elapsed: Vec<_> = events
+            .iter()
+            .map(|records| records.get("modality.span_elapsed_ns").cloned())
+            .collect();
+
+        assert!(elapsed[0].is_none());
+        let Some(TracingValue::U64(ns)) = elapsed[1] else {
+            panic!("no elapsed time on the event inside the span");
+        };
+        assert!((20_000_000..10_000_000_000).contains(&ns), "{ns}");
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn events_linked_by_cause_event_id() {
+        let options = Options::new().with_event_ids(true);
+        let events = capture_events(options, || {
+            tracing::info!("command");
+            let command = last_event_id();
+            tracing::info!(modality.cause.event_id = command, "result");
+        });
+
+        let Some(TracingValue::U64(command)) = events[0].get("modality.event_id").cloned() else {
+            panic!("no event ID on the command");
+        };
+        assert!(matches!(
+            events[1].get("interaction.cause_event_id"),
+            Some(TracingValue::U64(cause)) if *cause == command
+        ));
+        assert!(!events[1].contains_key("modality.cause.event_id"));
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn level_filter_changes_at_runtime() {
+        let (sender, mut recv) = ingest::ingest_channel(None);
+        let options = Options::new().with_max_level(LevelFilter::INFO);
+        let max_level = options.max_level.clone();
+        let layer = crate::blocking::ModalityLayer::with_sender(sender, options);
+        let subscriber = layer.into_subscriber();
+
+        let mut event_count = || {
+            let mut count = 0;
+            while let Ok(wrapped) = recv.try_recv() {
+                if let ingest::Message::Event { .. } = wrapped.message {
+                    count += 1;
+                }
+            }
+            count
+        };
+
+        tracing::subscriber::with_default(subscriber, || {
+            let debug = || tracing::debug!("debug");
+
+            debug();
+            assert_eq!(event_count(), 0);
+
+            max_level.set(LevelFilter::DEBUG);
+            debug();
+            assert_eq!(event_count(), 1);
+
+            max_level.set(LevelFilter::INFO);
+            debug();
+            assert_eq!(event_count(), 0);
+        });
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn max_level_leaves_other_layers_alone() {
+        use std::sync::atomic::AtomicUsize;
+        use tracing_subscriber::layer::SubscriberExt;
+
+        struct CountingLayer(Arc<AtomicUsize>);
+        impl<S: Subscriber> Layer<S> for CountingLayer {
+            fn on_event(&self, _event: &tracing_core::Event<'_>, _ctx: Context<'_, S>) {
+                self.0.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        let (sender, mut recv) = ingest::ingest_channel(None);
+        let options = Options::new().with_max_level(LevelFilter::INFO);
+        let layer = crate::blocking::ModalityLayer::with_sender(sender, options);
+        let filter = layer.max_level_filter();
+        let other_events = Arc::new(AtomicUsize::new(0));
+        let subscriber = tracing_subscriber::Registry::default()
+            .with(layer.with_filter(filter))
+            .with(CountingLayer(other_events.clone()));
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::debug!("debug");
+            tracing::info!("info");
+        });
+
+        let mut events = 0;
+        while let Ok(wrapped) = recv.try_recv() {
+            if let ingest::Message::Event { .. } = wrapped.message {
+                events += 1;
+            }
+        }
+        assert_eq!(events, 1);
+        assert_eq!(other_events.load(Ordering::Relaxed), 2);
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn kind_fn_applies_without_explicit_kind() {
+        let options = Options::new().with_kind_fn(|metadata| {
+            (*metadata.level() == tracing::Level::ERROR).then(|| "error".to_string())
+        });
+        let events = capture_events(options, || {
+            tracing::error!("failed");
+            tracing::info!("fine");
+            tracing::error!(modality.kind = crate::kind::AUDIT, "denied");
+        });
+        let kinds: Vec<_> = events
+            .iter()
+            .map(|records| records.get("modality.kind").cloned())
+            .collect();
+
+        assert!(matches!(&kinds[0], Some(TracingValue::String(k)) if k == "error"));
+        // no kind here means the default `event` kind
+        assert!(kinds[1].is_none());
+        assert!(matches!(&kinds[2], Some(TracingValue::String(k)) if k == crate::kind::AUDIT));
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn fields_on_enter_sends_latest_fields() {
+        let options = Options::new().with_fields_on_enter(true);
+        let messages = capture(options, || {
+            let span = tracing::info_span!("work", step = 1u64);
+            span.in_scope(|| {});
+            span.record("step", 2u64);
+            span.in_scope(|| {});
+        });
+
+        let steps: Vec<_> = messages
+            .into_iter()
+            .filter_map(|wrapped| match wrapped.message {
+                ingest::Message::Enter {
+                    records: Some(records),
+                    ..
+                } => Some(records.get("step").cloned()),
+                _ => None,
+            })
+            .collect();
+
+        assert!(matches!(
+            steps[..],
+            [Some(TracingValue::U64(1)), Some(TracingValue::U64(2))]
+        ));
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn events_inherit_span_fields() {
+        let trace = || {
+            let _outer = tracing::info_span!("outer", request_id = 7u64, step = 1u64).entered();
+            let _inner = tracing::info_span!("inner", step = 2u64).entered();
+            tracing::info!(own = true, "event");
+        };
+
+        let events = capture_events(Options::new().with_span_field_inheritance(true), trace);
+        let event = &events[0];
+        assert!(matches!(
+            event.get("span.request_id"),
+            Some(TracingValue::U64(7))
+        ));
+        // the innermost span wins
+        assert!(matches!(event.get("span.step"), Some(TracingValue::U64(2))));
+        assert!(matches!(event.get("own"), Some(TracingValue::Bool(true))));
+
+        let events = capture_events(Options::new(), trace);
+        assert!(!events[0].keys().any(|name| name.starts_with("span.")));
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn events_get_named_ancestor_fields() {
+        let events = capture_events(Options::new().with_ancestor_fields(["txn_id"]), || {
+            let _txn = tracing::info_span!("transaction", txn_id = 3u64, other = 1u64).entered();
+            let _step = tracing::info_span!("step").entered();
+            tracing::info!("event");
+        });
+
+        let event = &events[0];
+        assert!(matches!(
+            event.get("transaction.txn_id"),
+            Some(TracingValue::U64(3))
+        ));
+        assert!(event.get("transaction.other").is_none());
+        assert!(!event.keys().any(|name| name.starts_with("span.")));
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn event_observer_sees_recorded_fields() {
+        use std::sync::Mutex;
+
+        let observed = Arc::new(Mutex::new(Vec::new()));
+        let options = Options::new().with_event_observer({
+            let observed = observed.clone();
+            move |metadata, records| {
+                let mut fields: Vec<_> = records.keys().cloned().collect();
+                fields.sort();
+                observed
+                    .lock()
+                    .unwrap()
+                    .push((metadata.level().to_owned(), fields));
+            }
+        });
+
+        let events = capture_events(options, || {
+            let _span = tracing::info_span!("span").entered();
+            tracing::warn!(answer = 42u64, "observed");
+        });
+
+        let observed = observed.lock().unwrap();
+        assert_eq!(
+            *observed,
+            [(
+                tracing::Level::WARN,
+                vec!["answer".to_string(), "message".to_string()]
+            )]
+        );
+        assert_eq!(events.len(), 1);
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn span_poll_count_on_close() {
+        let poll_counts = |options| {
+            capture(options, || {
+                let span = tracing::info_span!("polled");
+                for _ in 0..3 {
+                    let _entered = span.enter();
+                }
+            })
+            .into_iter()
+            .filter_map(|wrapped| match wrapped.message {
+                ingest::Message::Close {
+                    records,
+                    poll_count,
+                    ..
+                } => Some((records.is_some(), poll_count)),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+        };
+
+        assert_eq!(poll_counts(Options::new()), [(false, None)]);
+        // a close event is sent for the count, even without a snapshot
+        assert_eq!(
+            poll_counts(Options::new().with_span_poll_count(true)),
+            [(true, Some(3))]
+        );
+    }
+}
@@ -1,12 +1,68 @@
+pub mod attr_handlers;
+#[cfg(feature = "config")]
+pub(crate) mod config;
+pub(crate) mod fallback;
 pub(crate) mod ingest;
+pub mod kind;
 pub(crate) mod layer;
 pub(crate) mod options;
+pub(crate) mod panic;
+pub(crate) mod signal;
+pub(crate) mod span_context;
+pub(crate) mod timed;
+pub(crate) mod timeline;
+
+#[cfg(feature = "config")]
+pub use config::ConfigError;
+pub use panic::record_panic;
+pub use span_context::{current_span_context, import_span, ParseSpanContextError, SpanContext};
+pub use timed::{timed, timed_async};
+pub use timeline::{timeline_scope, TimelineScope, UserTimelineInfo};
 
-#[cfg(doc)]
 use crate::Options;
 use ingest::{ConnectError, TimelineId};
-use std::fmt::Debug;
+use std::{
+    fmt::Debug,
+    sync::atomic::{AtomicBool, Ordering},
+};
 use thiserror::Error;
+use tracing::dispatcher::SetGlobalDefaultError;
+use tracing_core::Dispatch;
+
+/// The version of the attribute conventions this crate records with, e.g. the `internal.rs.*`
+/// keys and kind strings. Sent as `event.internal.rs.schema_version` on the first event of each
+/// timeline, so data from different versions of this crate can be told apart.
+///
+/// This is bumped whenever the recorded attributes change:
+///
+/// - `1`: the first versioned schema.
+/// - `2`: adds the `span:record` and `span:follows_from` kinds, kinds from
+///   [`Options::set_kind_fn`], and the `event.internal.rs.event_id`,
+///   `event.internal.rs.span_elapsed_ns`, `event.internal.rs.clock_source` and
+///   `event.internal.rs.clock_id` attributes.
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// Set once a `TracingModality` has been installed as the global default tracer.
+pub(crate) static INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+/// Install a `TracingModality`'s `dispatch` as the global default tracer, then the panic hook and
+/// signal handling `options` ask for. `install_signal_handler` is only called if graceful signal
+/// handling is on.
+pub(crate) fn install_global_default(
+    dispatch: Dispatch,
+    options: &Options,
+    install_signal_handler: impl FnOnce(),
+) -> Result<(), SetGlobalDefaultError> {
+    tracing::dispatcher::set_global_default(dispatch)?;
+    INITIALIZED.store(true, Ordering::SeqCst);
+    if options.panic_capture {
+        panic::install_panic_hook();
+    }
+    if options.graceful_signal_handling {
+        install_signal_handler();
+    }
+    Ok(())
+}
 
 #[derive(Debug, Error)]
 pub enum InitError {
@@ -31,3 +87,13 @@ pub enum InitError {
 pub fn timeline_id() -> TimelineId {
     ingest::current_timeline()
 }
+
+/// Use the provided timeline ID for the current thread's timeline rather than allocating a new
+/// one, e.g. to match timeline IDs already assigned by another system.
+///
+/// This must be called before the thread emits any trace events or calls [`timeline_id`]. Calling
+/// it on the thread that initializes tracing, before initializing, sets the ID of the root
+/// timeline. If the thread already has a timeline ID that ID is returned as the error.
+pub fn set_timeline_id(timeline: TimelineId) -> Result<(), TimelineId> {
+    ingest::set_current_timeline(timeline)
+}
@@ -1,5 +1,41 @@
-use auxon_sdk::api::AttrVal;
-use std::net::SocketAddr;
+use crate::attr_handlers::{self, AttributeHandler};
+use crate::ingest::IngestExitReason;
+use crate::layer::{RecordMap, SpanLifecycleEvent, TickFn};
+use crate::timeline::{RunId, UserTimelineInfo};
+use auxon_sdk::api::{AttrVal, BigInt};
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+    num::NonZeroU64,
+    sync::{
+        atomic::{AtomicU64, AtomicU8, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tracing_core::{Level, LevelFilter, Metadata};
+use uuid::Uuid;
+
+pub(crate) type SeverityFn = dyn Fn(&Level) -> AttrVal + Send + Sync;
+pub(crate) type KindFn = dyn Fn(&Metadata<'static>) -> Option<String> + Send + Sync;
+pub(crate) type ComponentFn = dyn Fn(&Metadata<'static>) -> Option<String> + Send + Sync;
+pub(crate) type EventObserverFn = dyn Fn(&'static Metadata<'static>, &RecordMap) + Send + Sync;
+pub(crate) type ContextProviderFn = dyn Fn() -> Vec<(String, AttrVal)> + Send + Sync;
+pub(crate) type IngestExitFn = dyn Fn(IngestExitReason) + Send + Sync;
+
+/// Field name suffixes and the units they imply, used by [`Options::set_unit_suffixes`].
+pub(crate) const UNIT_SUFFIXES: &[(&str, &str)] = &[
+    ("_ns", "ns"),
+    ("_us", "us"),
+    ("_ms", "ms"),
+    ("_secs", "s"),
+    ("_bytes", "bytes"),
+    ("_count", "count"),
+    ("_pct", "%"),
+];
+
+/// Field name patterns used by [`Options::add_default_redactions`].
+const DEFAULT_REDACTIONS: &[&str] = &["password", "token", "secret", "authorization"];
 
 /// Initialization options.
 #[derive(Clone)]
@@ -7,6 +43,59 @@ pub struct Options {
     pub(crate) auth: Option<Vec<u8>>,
     pub(crate) metadata: Vec<(String, AttrVal)>,
     pub(crate) server_addr: SocketAddr,
+    pub(crate) additional_server_addrs: Vec<SocketAddr>,
+    pub(crate) max_level: MaxLevel,
+    pub(crate) run_id: RunId,
+    pub(crate) run_id_metadata: bool,
+    pub(crate) environment: Option<String>,
+    pub(crate) service_id: Option<String>,
+    pub(crate) clock_id: Option<u64>,
+    pub(crate) wall_clock_timestamps: bool,
+    pub(crate) timeline_churn_threshold: Option<f64>,
+    pub(crate) channel_capacity: Option<usize>,
+    pub(crate) timeline_identifier:
+        Option<Arc<dyn Fn(Option<&'static Metadata<'static>>) -> UserTimelineInfo + Send + Sync>>,
+    pub(crate) timeline_name_separator: Option<String>,
+    pub(crate) unnamed_event_name: Option<String>,
+    pub(crate) module_path_strip_prefix: Option<String>,
+    pub(crate) span_close_snapshot: bool,
+    pub(crate) fields_on_enter: bool,
+    pub(crate) span_field_inheritance: bool,
+    pub(crate) span_poll_count: bool,
+    pub(crate) poll_coalescing: Option<Duration>,
+    pub(crate) span_elapsed_on_events: bool,
+    pub(crate) ancestor_fields: Vec<String>,
+    pub(crate) field_allowlist: Option<HashSet<String>>,
+    pub(crate) approved_keys: Option<HashSet<String>>,
+    pub(crate) strict_approved_keys: bool,
+    pub(crate) redactions: Vec<String>,
+    pub(crate) field_units: HashMap<String, String>,
+    pub(crate) unit_suffixes: bool,
+    pub(crate) attr_handlers: Vec<AttributeHandler>,
+    pub(crate) timeline_end_marker: bool,
+    pub(crate) heartbeat_interval: Option<Duration>,
+    pub(crate) flush_diagnostics: bool,
+    pub(crate) layer_overhead_diagnostics: bool,
+    pub(crate) callsite_schema: bool,
+    pub(crate) field_type_tags: bool,
+    pub(crate) attr_vec_capacity: usize,
+    pub(crate) max_attrs_per_event: Option<usize>,
+    pub(crate) occurrence_counts: Option<usize>,
+    pub(crate) flight_recorder: Option<usize>,
+    pub(crate) panic_capture: bool,
+    pub(crate) graceful_signal_handling: bool,
+    pub(crate) console_fallback: bool,
+    pub(crate) event_ids: bool,
+    pub(crate) span_id_source: Option<Arc<dyn Fn() -> NonZeroU64 + Send + Sync>>,
+    pub(crate) tick_fn: Option<Arc<TickFn>>,
+    pub(crate) clock_source: Option<String>,
+    pub(crate) severity_fn: Option<Arc<SeverityFn>>,
+    pub(crate) kind_fn: Option<Arc<KindFn>>,
+    pub(crate) component_fn: Option<Arc<ComponentFn>>,
+    pub(crate) span_lifecycle_callback: Option<Arc<dyn Fn(SpanLifecycleEvent) + Send + Sync>>,
+    pub(crate) event_observer: Option<Arc<EventObserverFn>>,
+    pub(crate) context_provider: Option<Arc<ContextProviderFn>>,
+    pub(crate) on_ingest_exit: Option<Arc<IngestExitFn>>,
 }
 
 impl Options {
@@ -17,9 +106,101 @@ impl Options {
             auth,
             metadata: Vec::new(),
             server_addr,
+            additional_server_addrs: Vec::new(),
+            max_level: MaxLevel::new(LevelFilter::TRACE),
+            run_id: RunId::new(),
+            run_id_metadata: true,
+            environment: Self::resolve_environment(),
+            service_id: None,
+            clock_id: None,
+            wall_clock_timestamps: true,
+            timeline_churn_threshold: Some(0.9),
+            channel_capacity: Some(8192),
+            timeline_identifier: None,
+            timeline_name_separator: None,
+            unnamed_event_name: None,
+            module_path_strip_prefix: None,
+            span_close_snapshot: false,
+            fields_on_enter: false,
+            span_field_inheritance: false,
+            span_poll_count: false,
+            poll_coalescing: None,
+            span_elapsed_on_events: false,
+            ancestor_fields: Vec::new(),
+            field_allowlist: None,
+            approved_keys: None,
+            strict_approved_keys: false,
+            redactions: Vec::new(),
+            field_units: HashMap::new(),
+            unit_suffixes: false,
+            attr_handlers: attr_handlers::default_handlers(),
+            timeline_end_marker: false,
+            heartbeat_interval: None,
+            flush_diagnostics: false,
+            layer_overhead_diagnostics: false,
+            callsite_schema: false,
+            field_type_tags: false,
+            attr_vec_capacity: 8,
+            max_attrs_per_event: None,
+            occurrence_counts: None,
+            flight_recorder: None,
+            panic_capture: false,
+            graceful_signal_handling: false,
+            console_fallback: false,
+            event_ids: false,
+            span_id_source: None,
+            tick_fn: None,
+            clock_source: None,
+            severity_fn: None,
+            kind_fn: None,
+            component_fn: None,
+            span_lifecycle_callback: None,
+            event_observer: None,
+            context_provider: None,
+            on_ingest_exit: None,
         }
     }
 
+    /// Options for reproducible output, e.g. for golden tests: `run_id` is fixed, wall clock
+    /// timestamps are turned off, ticks count up by one nanosecond per message rather than
+    /// following real time, and span IDs count up from one for this layer rather than for the
+    /// process.
+    ///
+    /// Timelines from [`timeline_scope`](crate::timeline_scope) and the
+    /// [timeline identifier](Self::set_timeline_identifier) are derived from `run_id`, but thread
+    /// timeline IDs are still random. Pin those with [`set_timeline_id`](crate::set_timeline_id),
+    /// before initializing for the root timeline, and name the threads, to get the same output
+    /// from every run.
+    ///
+    /// The individual settings can still be changed afterwards.
+    pub fn deterministic(run_id: Uuid) -> Options {
+        let ticks = AtomicU64::new(0);
+        let span_ids = AtomicU64::new(0);
+        Options::new()
+            .with_run_id(run_id)
+            .with_wall_clock_timestamps(false)
+            .with_tick_fn(move || Duration::from_nanos(ticks.fetch_add(1, Ordering::Relaxed) + 1))
+            .with_span_id_source(move || {
+                NonZeroU64::new(span_ids.fetch_add(1, Ordering::Relaxed) + 1).unwrap()
+            })
+            .with_clock_source("deterministic")
+    }
+
+    /// The environment variables the environment is read from, in order of precedence.
+    const ENVIRONMENT_VARS: [&'static str; 3] = ["MODALITY_ENV", "ENVIRONMENT", "RUST_ENV"];
+
+    fn resolve_environment() -> Option<String> {
+        Self::environment_from(|var| std::env::var(var).ok())
+    }
+
+    /// The environment named by the first of [ENVIRONMENT_VARS](Self::ENVIRONMENT_VARS) that
+    /// `lookup` finds a non-empty value for.
+    fn environment_from(lookup: impl Fn(&str) -> Option<String>) -> Option<String> {
+        Self::ENVIRONMENT_VARS
+            .iter()
+            .find_map(|var| lookup(var).filter(|env| !env.is_empty()))
+    }
+
     fn resolve_auth_token() -> Option<Vec<u8>> {
         if let Some(from_env) = std::env::var("MODALITY_AUTH_TOKEN")
             .ok()
@@ -67,25 +248,13 @@ impl Options {
     ///
     /// This can be called multiple times.
     pub fn add_metadata<K: AsRef<str>, V: Into<AttrVal>>(&mut self, key: K, value: V) {
-        let key = key.as_ref();
-        let key = if key.starts_with("timeline.") {
-            key.to_string()
-        } else {
-            format!("timeline.{}", key)
-        };
-
-        self.metadata.push((key, value.into()));
+        self.metadata
+            .push((timeline_metadata_key(key.as_ref()), value.into()));
     }
     /// A chainable version of [add_metadata](Self::add_metadata).
     pub fn with_metadata<K: AsRef<str>, V: Into<AttrVal>>(mut self, key: K, value: V) -> Self {
-        let key = key.as_ref();
-        let key = if key.starts_with("timeline.") {
-            key.to_string()
-        } else {
-            format!("timeline.{}", key)
-        };
-
-        self.metadata.push((key, value.into()));
+        self.metadata
+            .push((timeline_metadata_key(key.as_ref()), value.into()));
         self
     }
 
@@ -100,6 +269,1241 @@ impl Options {
         self.server_addr = addr;
         self
     }
+
+    /// Also send trace data to `addr`, e.g. to both a local reflector and a central server.
+    ///
+    /// Each address gets its own connection and ingest thread, fed every message. All addresses
+    /// must be reachable at init, after that a failure sending to one doesn't affect the others.
+    pub fn add_server_address(&mut self, addr: SocketAddr) {
+        self.additional_server_addrs.push(addr);
+    }
+    /// A chainable version of [add_server_address](Self::add_server_address).
+    pub fn with_server_address_added(mut self, addr: SocketAddr) -> Self {
+        self.additional_server_addrs.push(addr);
+        self
+    }
+
+    /// Set how many messages can be queued for the ingest thread before new ones are dropped, so
+    /// a slow or unreachable modality can't grow the queue until the program runs out of memory.
+    ///
+    /// Tracing never blocks the program when the queue is full, the message is dropped instead.
+    /// Dropped messages are counted, see `TracingModality::dropped_messages` and
+    /// [`ShutdownReport::dropped`](crate::ShutdownReport::dropped). Each additional server address
+    /// gets a queue of its own. `None` makes the queue unbounded. Defaults to `Some(8192)`.
+    pub fn set_channel_capacity(&mut self, capacity: Option<usize>) {
+        self.channel_capacity = capacity;
+    }
+    /// A chainable version of [set_channel_capacity](Self::set_channel_capacity).
+    pub fn with_channel_capacity(mut self, capacity: Option<usize>) -> Self {
+        self.channel_capacity = capacity;
+        self
+    }
+
+    /// Set the deployment environment, e.g. `prod` or `staging`, recorded on every timeline as
+    /// `timeline.environment` so that data from all services can be filtered by it.
+    ///
+    /// When not set, it's read from the first of the `MODALITY_ENV`, `ENVIRONMENT`, and
+    /// `RUST_ENV` environment variables that is set, and left out if none are.
+    pub fn set_environment<S: AsRef<str>>(&mut self, environment: S) {
+        self.environment = Some(environment.as_ref().to_string());
+    }
+    /// A chainable version of [set_environment](Self::set_environment).
+    pub fn with_environment<S: AsRef<str>>(mut self, environment: S) -> Self {
+        self.environment = Some(environment.as_ref().to_string());
+        self
+    }
+
+    /// Set a stable identity for the service, e.g. `billing-api`, recorded on every timeline as
+    /// `timeline.service_id`.
+    ///
+    /// Unlike `timeline.run_id`, which is new every time the process starts (or the run is
+    /// [rotated](crate::TracingModality::rotate_run_id)), the service ID stays the same across
+    /// restarts. Filter by it for every run of the service, and by the run ID for one run of it.
+    /// Defaults to no service ID.
+    pub fn set_service_id<S: AsRef<str>>(&mut self, service_id: S) {
+        self.service_id = Some(service_id.as_ref().to_string());
+    }
+    /// A chainable version of [set_service_id](Self::set_service_id).
+    pub fn with_service_id<S: AsRef<str>>(mut self, service_id: S) -> Self {
+        self.service_id = Some(service_id.as_ref().to_string());
+        self
+    }
+
+    /// Put this process's timelines in logical clock domain `clock_id`, for processes taking part
+    /// in a distributed logical clock scheme. It's recorded on every timeline as
+    /// `timeline.internal.rs.clock_id`, the same key `tracing-serde` device info uses.
+    ///
+    /// Each event's `event.internal.rs.tick` is a unary logical time in the timeline's clock
+    /// domain; the ticks themselves don't change, this only says which domain they're counted in.
+    /// An event that refers to a time in another domain can name it with a `modality.clock_id`
+    /// field, recorded as `event.internal.rs.clock_id`. Defaults to no clock domain.
+    pub fn set_clock_id(&mut self, clock_id: u64) {
+        self.clock_id = Some(clock_id);
+    }
+    /// A chainable version of [set_clock_id](Self::set_clock_id).
+    pub fn with_clock_id(mut self, clock_id: u64) -> Self {
+        self.clock_id = Some(clock_id);
+        self
+    }
+
+    /// Use `run_id` as this run's ID rather than a randomly generated one, e.g. to correlate with
+    /// an ID assigned by a test harness.
+    pub fn set_run_id(&mut self, run_id: Uuid) {
+        self.run_id = RunId::from_uuid(run_id);
+    }
+    /// A chainable version of [set_run_id](Self::set_run_id).
+    pub fn with_run_id(mut self, run_id: Uuid) -> Self {
+        self.run_id = RunId::from_uuid(run_id);
+        self
+    }
+
+    /// Set whether events get an `event.timestamp` from the system clock when they don't have a
+    /// `timestamp` field of their own.
+    ///
+    /// Turn this off if your workspace is ordered purely by logical time and wall clock times are
+    /// just noise. Defaults to `true`.
+    pub fn set_wall_clock_timestamps(&mut self, enabled: bool) {
+        self.wall_clock_timestamps = enabled;
+    }
+    /// A chainable version of [set_wall_clock_timestamps](Self::set_wall_clock_timestamps).
+    pub fn with_wall_clock_timestamps(mut self, enabled: bool) -> Self {
+        self.wall_clock_timestamps = enabled;
+        self
+    }
+
+    /// Set whether a randomly generated `timeline.run_id` is added to every timeline's metadata to
+    /// correlate the timelines of one run of the program.
+    ///
+    /// Defaults to `true`. Turn this off if you manage your own run correlation metadata.
+    pub fn set_run_id_metadata(&mut self, enabled: bool) {
+        self.run_id_metadata = enabled;
+    }
+    /// A chainable version of [set_run_id_metadata](Self::set_run_id_metadata).
+    pub fn with_run_id_metadata(mut self, enabled: bool) -> Self {
+        self.run_id_metadata = enabled;
+        self
+    }
+
+    /// Choose the timeline each trace event is recorded on with `identifier`, rather than using one
+    /// timeline per thread.
+    ///
+    /// The identifier is called on the producing thread for every event, and when each span is
+    /// created. The span's later records, enters, exits, and close go on the timeline it was
+    /// created on. Events for the same [`UserTimelineInfo::user_id`] go to the same timeline,
+    /// whichever thread they come from. Use
+    /// [set_timeline_identifier_with_metadata](Self::set_timeline_identifier_with_metadata) to
+    /// choose based on the event or span being recorded.
+    pub fn set_timeline_identifier<F>(&mut self, identifier: F)
+    where
+        F: Fn() -> UserTimelineInfo + Send + Sync + 'static,
+    {
+        self.timeline_identifier = Some(Arc::new(move |_metadata| identifier()));
+    }
+    /// A chainable version of [set_timeline_identifier](Self::set_timeline_identifier).
+    pub fn with_timeline_identifier<F>(mut self, identifier: F) -> Self
+    where
+        F: Fn() -> UserTimelineInfo + Send + Sync + 'static,
+    {
+        self.set_timeline_identifier(identifier);
+        self
+    }
+
+    /// Like [set_timeline_identifier](Self::set_timeline_identifier), but the identifier is also
+    /// given the metadata of the event or span being recorded, when there is one.
+    pub fn set_timeline_identifier_with_metadata<F>(&mut self, identifier: F)
+    where
+        F: Fn(Option<&'static Metadata<'static>>) -> UserTimelineInfo + Send + Sync + 'static,
+    {
+        self.timeline_identifier = Some(Arc::new(identifier));
+    }
+    /// A chainable version of
+    /// [set_timeline_identifier_with_metadata](Self::set_timeline_identifier_with_metadata).
+    pub fn with_timeline_identifier_with_metadata<F>(mut self, identifier: F) -> Self
+    where
+        F: Fn(Option<&'static Metadata<'static>>) -> UserTimelineInfo + Send + Sync + 'static,
+    {
+        self.timeline_identifier = Some(Arc::new(identifier));
+        self
+    }
+
+    /// Record each `tracing` target on its own timeline, named after the target, rather than using
+    /// one timeline per thread. See [`UserTimelineInfo::for_target`].
+    pub fn set_target_timelines(&mut self) {
+        self.set_timeline_identifier_with_metadata(|metadata| {
+            UserTimelineInfo::for_target(metadata)
+        });
+    }
+    /// A chainable version of [set_target_timelines](Self::set_target_timelines).
+    pub fn with_target_timelines(mut self) -> Self {
+        self.set_target_timelines();
+        self
+    }
+
+    /// Warn once, on stderr, if more than `threshold` (a fraction between 0 and 1) of recent trace
+    /// events went to a timeline from the timeline identifier that hadn't been seen before. That
+    /// usually means the identifier doesn't return the same `user_id` for the same timeline.
+    ///
+    /// `None` turns the check off. Defaults to `Some(0.9)`.
+    pub fn set_timeline_churn_threshold(&mut self, threshold: Option<f64>) {
+        self.timeline_churn_threshold = threshold;
+    }
+    /// A chainable version of [set_timeline_churn_threshold](Self::set_timeline_churn_threshold).
+    pub fn with_timeline_churn_threshold(mut self, threshold: Option<f64>) -> Self {
+        self.timeline_churn_threshold = threshold;
+        self
+    }
+
+    /// Set a separator used to treat timeline names as a hierarchical path, e.g. `/` for names like
+    /// `worker/db/pool-3`.
+    ///
+    /// Names containing the separator are recorded with the leaf component as `timeline.name`, the
+    /// whole name as `timeline.name.full`, and each component as `timeline.path.N`. By default
+    /// timeline names are not split.
+    pub fn set_timeline_name_separator<S: AsRef<str>>(&mut self, separator: S) {
+        self.timeline_name_separator = Some(separator.as_ref().to_string());
+    }
+    /// A chainable version of [set_timeline_name_separator](Self::set_timeline_name_separator).
+    pub fn with_timeline_name_separator<S: AsRef<str>>(mut self, separator: S) -> Self {
+        self.timeline_name_separator = Some(separator.as_ref().to_string());
+        self
+    }
+
+    /// Set the `event.name` used for events that have neither a `name` nor a `message` field.
+    ///
+    /// By default (`None`) these events are named by `tracing`'s generated callsite name, e.g.
+    /// `event src/main.rs:42`.
+    pub fn set_unnamed_event_name(&mut self, name: Option<String>) {
+        self.unnamed_event_name = name;
+    }
+    /// A chainable version of [set_unnamed_event_name](Self::set_unnamed_event_name).
+    pub fn with_unnamed_event_name(mut self, name: Option<String>) -> Self {
+        self.unnamed_event_name = name;
+        self
+    }
+
+    /// Trim `prefix` from the start of module paths recorded as `event.source.module`, e.g.
+    /// `my_app::` to record `my_app::services::billing` as `services::billing`.
+    ///
+    /// The prefix only matches whole path segments, `my_app` strips `my_app::` but leaves
+    /// `my_appx::foo` alone. Module paths that don't start with the prefix are recorded unchanged,
+    /// as is an explicit `source.module` field.
+    pub fn set_module_path_strip_prefix<S: AsRef<str>>(&mut self, prefix: S) {
+        self.module_path_strip_prefix = Some(prefix.as_ref().to_string());
+    }
+    /// A chainable version of [set_module_path_strip_prefix](Self::set_module_path_strip_prefix).
+    pub fn with_module_path_strip_prefix<S: AsRef<str>>(mut self, prefix: S) -> Self {
+        self.module_path_strip_prefix = Some(prefix.as_ref().to_string());
+        self
+    }
+
+    /// Emit a `span:close` event when each span closes, carrying every field the span was defined
+    /// or recorded with over its lifetime.
+    ///
+    /// Defaults to `false`.
+    pub fn set_span_close_snapshot(&mut self, enabled: bool) {
+        self.span_close_snapshot = enabled;
+    }
+    /// A chainable version of [set_span_close_snapshot](Self::set_span_close_snapshot).
+    pub fn with_span_close_snapshot(mut self, enabled: bool) -> Self {
+        self.span_close_snapshot = enabled;
+        self
+    }
+
+    /// Attach every field a span has been defined or recorded with so far to each of its
+    /// `span:enter` events, e.g. for long-lived async spans whose fields change between polls.
+    ///
+    /// This can add a lot of attributes, as async spans are entered on every poll. There are no
+    /// enter events to attach them to with [poll coalescing](Self::set_poll_coalescing).
+    /// Defaults to `false`.
+    pub fn set_fields_on_enter(&mut self, enabled: bool) {
+        self.fields_on_enter = enabled;
+    }
+    /// A chainable version of [set_fields_on_enter](Self::set_fields_on_enter).
+    pub fn with_fields_on_enter(mut self, enabled: bool) -> Self {
+        self.fields_on_enter = enabled;
+        self
+    }
+
+    /// Attach the fields of every span an event occurs within to the event, prefixed with `span.`,
+    /// e.g. a `request_id` field on an enclosing span is recorded as `event.span.request_id`.
+    ///
+    /// When spans are nested the innermost span's value wins, and the event's own fields are never
+    /// overwritten. Defaults to `false`.
+    pub fn set_span_field_inheritance(&mut self, enabled: bool) {
+        self.span_field_inheritance = enabled;
+    }
+    /// A chainable version of [set_span_field_inheritance](Self::set_span_field_inheritance).
+    pub fn with_span_field_inheritance(mut self, enabled: bool) -> Self {
+        self.span_field_inheritance = enabled;
+        self
+    }
+
+    /// Count how many times each span is entered and record it on the span's close event as
+    /// `event.internal.rs.poll_count`. For async spans that's how many times the future was
+    /// polled, a span polled thousands of times may be busy-waiting.
+    ///
+    /// This sends a `span:close` event for every span, which only carries the span's fields if
+    /// [span close snapshots](Self::set_span_close_snapshot) are also enabled. Defaults to
+    /// `false`.
+    pub fn set_span_poll_count(&mut self, enabled: bool) {
+        self.span_poll_count = enabled;
+    }
+    /// A chainable version of [set_span_poll_count](Self::set_span_poll_count).
+    pub fn with_span_poll_count(mut self, enabled: bool) -> Self {
+        self.span_poll_count = enabled;
+        self
+    }
+
+    /// Replace each span's `span:enter` and `span:exit` events with a
+    /// [`span:busy`](crate::kind::SPAN_BUSY) summary per `window`, for async spans that are polled
+    /// so often their enter and exit events would flood modality.
+    ///
+    /// The summary is sent with the first exit at least `window` after the first enter it covers,
+    /// and with whatever is left when the span closes. It records how many times the span was
+    /// entered in `event.internal.rs.poll_count` and how long it was entered for in total in
+    /// `event.internal.rs.busy_ns`. Defaults to `None`, sending every enter and exit.
+    pub fn set_poll_coalescing(&mut self, window: Option<Duration>) {
+        self.poll_coalescing = window;
+    }
+    /// A chainable version of [set_poll_coalescing](Self::set_poll_coalescing).
+    pub fn with_poll_coalescing(mut self, window: Option<Duration>) -> Self {
+        self.poll_coalescing = window;
+        self
+    }
+
+    /// Record how long an event's span has been open when the event happens, as
+    /// `event.internal.rs.span_elapsed_ns`, e.g. to see that a warning came 4.2s into a request.
+    ///
+    /// The time is measured from when the nearest span in the event's scope was first entered,
+    /// later entries of the same span don't reset it. Events outside any entered span don't get
+    /// the attribute. Defaults to `false`.
+    pub fn set_span_elapsed_on_events(&mut self, enabled: bool) {
+        self.span_elapsed_on_events = enabled;
+    }
+    /// A chainable version of [set_span_elapsed_on_events](Self::set_span_elapsed_on_events).
+    pub fn with_span_elapsed_on_events(mut self, enabled: bool) -> Self {
+        self.span_elapsed_on_events = enabled;
+        self
+    }
+
+    /// Attach the named fields of any span an event occurs within to the event, prefixed with the
+    /// name of the span they came from, e.g. `txn_id` from a `transaction` span is recorded as
+    /// `event.transaction.txn_id`.
+    ///
+    /// This is a more targeted alternative to
+    /// [set_span_field_inheritance](Self::set_span_field_inheritance). Defaults to no fields.
+    pub fn set_ancestor_fields<I, S>(&mut self, fields: I)
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.ancestor_fields = fields.into_iter().map(Into::into).collect();
+    }
+    /// A chainable version of [set_ancestor_fields](Self::set_ancestor_fields).
+    pub fn with_ancestor_fields<I, S>(mut self, fields: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.ancestor_fields = fields.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Only send the listed fields, any other field is dropped rather than being recorded as an
+    /// `event.<field>` attribute.
+    ///
+    /// Fields are matched by the name they're recorded with, including the `span.` or
+    /// `<span name>.` prefix of fields attached from enclosing spans. The fields this crate gives
+    /// special meaning, such as `name`, `severity`, `timestamp`, `source.*`, `modality.*`, and
+    /// `interaction.*`, are always used. Other fields with an
+    /// [attribute handler](Self::add_attr_handler) aren't exempt, the handler only runs for fields
+    /// on the list. By default every field is sent.
+    pub fn set_field_allowlist<I, S>(&mut self, fields: I)
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.field_allowlist = Some(fields.into_iter().map(Into::into).collect());
+    }
+    /// A chainable version of [set_field_allowlist](Self::set_field_allowlist).
+    pub fn with_field_allowlist<I, S>(mut self, fields: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.field_allowlist = Some(fields.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Check the attribute keys of fields against a set of approved keys, to keep a shared
+    /// attribute schema from drifting. Where the [field allowlist](Self::set_field_allowlist)
+    /// decides which fields may be sent at all, this flags keys nobody has agreed on.
+    ///
+    /// Keys may be given with or without the `event.` prefix. The first use of a key that isn't
+    /// approved prints a warning, and the attribute is still sent unless
+    /// [strict_approved_keys](Self::set_strict_approved_keys) is set. The keys of fields from the
+    /// [context provider](Self::set_context_provider) and those returned by
+    /// [attribute handlers](Self::add_attr_handler) are checked too; the attributes this crate
+    /// adds itself, such as `event.name` and `event.internal.rs.*`, are not. Defaults to no
+    /// checking.
+    ///
+    /// The approved keys can be embedded at build time:
+    ///
+    /// ```rust,ignore
+    /// let keys = include_str!("approved_keys.txt").lines().map(String::from).collect();
+    /// let options = Options::new().with_approved_keys(keys);
+    /// ```
+    pub fn set_approved_keys(&mut self, keys: HashSet<String>) {
+        self.approved_keys = Some(keys);
+    }
+    /// A chainable version of [set_approved_keys](Self::set_approved_keys).
+    pub fn with_approved_keys(mut self, keys: HashSet<String>) -> Self {
+        self.approved_keys = Some(keys);
+        self
+    }
+
+    /// Drop attributes whose key isn't one of the [approved keys](Self::set_approved_keys),
+    /// rather than only warning about them. Defaults to `false`.
+    pub fn set_strict_approved_keys(&mut self, strict: bool) {
+        self.strict_approved_keys = strict;
+    }
+    /// A chainable version of [set_strict_approved_keys](Self::set_strict_approved_keys).
+    pub fn with_strict_approved_keys(mut self, strict: bool) -> Self {
+        self.strict_approved_keys = strict;
+        self
+    }
+
+    /// Replace the value of any field whose name contains `pattern`, ignoring case, with
+    /// `"<redacted>"`. The field itself is still sent, so its presence remains visible.
+    ///
+    /// This can be called multiple times. The fields this crate gives special meaning, such as
+    /// `name` and `severity`, are never redacted.
+    ///
+    /// Redaction takes precedence over [attribute handlers](Self::add_attr_handler): a redacted
+    /// field is sent as `event.<field>` = `"<redacted>"` and its handler isn't run, so the value
+    /// can't leak out under the keys the handler would have returned.
+    pub fn add_redaction<S: AsRef<str>>(&mut self, pattern: S) {
+        self.redactions.push(pattern.as_ref().to_lowercase());
+    }
+    /// A chainable version of [add_redaction](Self::add_redaction).
+    pub fn with_redaction<S: AsRef<str>>(mut self, pattern: S) -> Self {
+        self.redactions.push(pattern.as_ref().to_lowercase());
+        self
+    }
+
+    /// Redact a common set of sensitive field names: `password`, `token`, `secret`, and
+    /// `authorization`. See [add_redaction](Self::add_redaction).
+    pub fn add_default_redactions(&mut self) {
+        for pattern in DEFAULT_REDACTIONS {
+            self.add_redaction(pattern);
+        }
+    }
+    /// A chainable version of [add_default_redactions](Self::add_default_redactions).
+    pub fn with_default_redactions(mut self) -> Self {
+        self.add_default_redactions();
+        self
+    }
+
+    /// Record the unit of a numeric field alongside it as `event.<field>.unit`, e.g. `"ms"` for a
+    /// `latency` field, so dashboards can format its values.
+    ///
+    /// This can be called multiple times, a later unit for the same field replaces an earlier
+    /// one. Fields with an [attribute handler](Self::add_attr_handler) don't get a unit, the
+    /// handler decides what's recorded for them, and neither do non-numeric or
+    /// [redacted](Self::add_redaction) values. The `<field>.unit` key is checked against the
+    /// [approved keys](Self::set_approved_keys) like any other.
+    pub fn add_field_unit<K: Into<String>, U: Into<String>>(&mut self, field: K, unit: U) {
+        self.field_units.insert(field.into(), unit.into());
+    }
+    /// A chainable version of [add_field_unit](Self::add_field_unit).
+    pub fn with_field_unit<K: Into<String>, U: Into<String>>(mut self, field: K, unit: U) -> Self {
+        self.field_units.insert(field.into(), unit.into());
+        self
+    }
+
+    /// Also record units for fields named with a unit suffix, e.g. `"ms"` for `duration_ms`. The
+    /// recognized suffixes are `_ns`, `_us`, `_ms`, `_secs`, `_bytes`, `_count`, and `_pct` (as
+    /// `%`). A unit given with [add_field_unit](Self::add_field_unit) takes precedence. Defaults to
+    /// `false`.
+    pub fn set_unit_suffixes(&mut self, enabled: bool) {
+        self.unit_suffixes = enabled;
+    }
+    /// A chainable version of [set_unit_suffixes](Self::set_unit_suffixes).
+    pub fn with_unit_suffixes(mut self, enabled: bool) -> Self {
+        self.unit_suffixes = enabled;
+        self
+    }
+
+    /// Replace all attribute handlers, including the
+    /// [default handlers](crate::attr_handlers::default_handlers), with `handlers`.
+    ///
+    /// If more than one handler is given for the same field the last one is used, after any
+    /// [timeline-scoped](AttributeHandler::for_timelines) handler that matches.
+    pub fn set_attr_handlers<I>(&mut self, handlers: I)
+    where
+        I: IntoIterator<Item = AttributeHandler>,
+    {
+        self.attr_handlers = handlers.into_iter().collect();
+    }
+    /// A chainable version of [set_attr_handlers](Self::set_attr_handlers).
+    pub fn with_only_attr_handlers<I>(mut self, handlers: I) -> Self
+    where
+        I: IntoIterator<Item = AttributeHandler>,
+    {
+        self.attr_handlers = handlers.into_iter().collect();
+        self
+    }
+
+    /// Add an attribute handler, keeping the existing ones.
+    ///
+    /// An added handler takes precedence over any handler already registered for the same field,
+    /// including the [default handlers](crate::attr_handlers::default_handlers). How handlers
+    /// combine with the field allowlist, redactions, and approved keys is described in the
+    /// [`attr_handlers`](crate::attr_handlers) module docs.
+    pub fn add_attr_handler(&mut self, handler: AttributeHandler) {
+        self.attr_handlers.push(handler);
+    }
+    /// A chainable version of [add_attr_handler](Self::add_attr_handler).
+    pub fn with_attr_handler(mut self, handler: AttributeHandler) -> Self {
+        self.attr_handlers.push(handler);
+        self
+    }
+
+    /// Add several attribute handlers at once, see [add_attr_handler](Self::add_attr_handler).
+    pub fn add_attr_handlers<I>(&mut self, handlers: I)
+    where
+        I: IntoIterator<Item = AttributeHandler>,
+    {
+        self.attr_handlers.extend(handlers);
+    }
+    /// A chainable version of [add_attr_handlers](Self::add_attr_handlers).
+    pub fn with_attr_handlers<I>(mut self, handlers: I) -> Self
+    where
+        I: IntoIterator<Item = AttributeHandler>,
+    {
+        self.attr_handlers.extend(handlers);
+        self
+    }
+
+    /// Emit a final `timeline:end` event on a thread's timeline when that thread exits, so a
+    /// timeline that finished cleanly can be told apart from one that stalled.
+    ///
+    /// Thread exit is detected with a thread local destructor. Those don't run for the main thread,
+    /// or for threads still running when the process exits.
+    ///
+    /// Timelines from a [timeline identifier](Self::set_timeline_identifier) or
+    /// [`timeline_scope`](crate::timeline_scope) aren't tied to a thread. They get their
+    /// `timeline:end` when [closed](crate::TracingModality::close_timeline), or otherwise when the
+    /// ingest shuts down in `finish`. Defaults to `false`.
+    pub fn set_timeline_end_marker(&mut self, enabled: bool) {
+        self.timeline_end_marker = enabled;
+    }
+    /// A chainable version of [set_timeline_end_marker](Self::set_timeline_end_marker).
+    pub fn with_timeline_end_marker(mut self, enabled: bool) -> Self {
+        self.timeline_end_marker = enabled;
+        self
+    }
+
+    /// Send a [`heartbeat`](crate::kind::HEARTBEAT) event on every timeline that has been quiet for
+    /// `interval`, and again each `interval` it stays quiet, so a quiet timeline can be told apart
+    /// from one whose producer died.
+    ///
+    /// Only timelines that have recorded an event get heartbeats, and they stop once the timeline
+    /// [ends](Self::set_timeline_end_marker) or is
+    /// [closed](crate::TracingModality::close_timeline). Heartbeats come from the ingest thread,
+    /// so they also stop if it does. By default no heartbeats are sent.
+    pub fn set_heartbeat_interval(&mut self, interval: Duration) {
+        self.heartbeat_interval = Some(interval);
+    }
+    /// A chainable version of [set_heartbeat_interval](Self::set_heartbeat_interval).
+    pub fn with_heartbeat_interval(mut self, interval: Duration) -> Self {
+        self.heartbeat_interval = Some(interval);
+        self
+    }
+
+    /// Emit `internal.rs.flush_requested` and `internal.rs.flush_completed` events on the root
+    /// timeline around the final flush in `finish`, the latter with the flush's duration in
+    /// `event.internal.rs.flush_elapsed` (nanoseconds).
+    ///
+    /// This is an internal diagnostic for looking into flush latency. Defaults to `false`.
+    pub fn set_flush_diagnostics(&mut self, enabled: bool) {
+        self.flush_diagnostics = enabled;
+    }
+    /// A chainable version of [set_flush_diagnostics](Self::set_flush_diagnostics).
+    pub fn with_flush_diagnostics(mut self, enabled: bool) -> Self {
+        self.flush_diagnostics = enabled;
+        self
+    }
+
+    /// Time how long the layer spends handling each new span and event, and emit an
+    /// `internal.rs.layer_overhead` event on the root timeline for every 1024 of them on a
+    /// thread, with the median and 99th percentile in `event.internal.rs.layer_overhead_ns.p50`
+    /// and `event.internal.rs.layer_overhead_ns.p99`.
+    ///
+    /// This covers capturing the fields and queueing the message, not the ingest thread's work.
+    /// Threads that record fewer than 1024 spans and events never report. This is an internal
+    /// diagnostic, the timing itself adds some overhead. Defaults to `false`.
+    pub fn set_layer_overhead_diagnostics(&mut self, enabled: bool) {
+        self.layer_overhead_diagnostics = enabled;
+    }
+    /// A chainable version of [set_layer_overhead_diagnostics](Self::set_layer_overhead_diagnostics).
+    pub fn with_layer_overhead_diagnostics(mut self, enabled: bool) -> Self {
+        self.layer_overhead_diagnostics = enabled;
+        self
+    }
+
+    /// The first time each span or event callsite is used, emit an
+    /// `internal.rs.callsite_schema` event on the root timeline listing every field the callsite
+    /// declares, whether or not this use set it, for building a catalog of the attributes the
+    /// instrumentation can produce.
+    ///
+    /// The schema event records the callsite's `event.internal.rs.callsite.name`, `.target`,
+    /// `.kind` (`span` or `event`), and `.fields`, a comma separated list of field names. Each
+    /// callsite is only described once per process. This is an internal diagnostic. Defaults to
+    /// `false`.
+    pub fn set_callsite_schema(&mut self, enabled: bool) {
+        self.callsite_schema = enabled;
+    }
+    /// A chainable version of [set_callsite_schema](Self::set_callsite_schema).
+    pub fn with_callsite_schema(mut self, enabled: bool) -> Self {
+        self.callsite_schema = enabled;
+        self
+    }
+
+    /// Record the type each field's value was captured as alongside it, as
+    /// `event.<field>.__type`: one of `string`, `f64`, `i64`, `u64`, or `bool`. Values logged with
+    /// `?` or `%` are captured as strings.
+    ///
+    /// This is a diagnostic for working out why a field shows up in modality with an unexpected
+    /// type, e.g. a `u64` recorded as a big integer. The `<field>.__type` keys are checked against
+    /// the [approved keys](Self::set_approved_keys) like any other. Defaults to `false`.
+    pub fn set_field_type_tags(&mut self, enabled: bool) {
+        self.field_type_tags = enabled;
+    }
+    /// A chainable version of [set_field_type_tags](Self::set_field_type_tags).
+    pub fn with_field_type_tags(mut self, enabled: bool) -> Self {
+        self.field_type_tags = enabled;
+        self
+    }
+
+    /// Set the initial capacity of the attribute list built for each event sent to modality.
+    ///
+    /// Sized to hold an event's usual attributes without reallocating, raise it if your events
+    /// typically carry many fields. Defaults to `8`.
+    pub fn set_attr_vec_capacity(&mut self, capacity: usize) {
+        self.attr_vec_capacity = capacity;
+    }
+    /// A chainable version of [set_attr_vec_capacity](Self::set_attr_vec_capacity).
+    pub fn with_attr_vec_capacity(mut self, capacity: usize) -> Self {
+        self.attr_vec_capacity = capacity;
+        self
+    }
+
+    /// Limit how many attributes an event can have, to protect the pipeline from an event logged
+    /// with hundreds of fields by mistake.
+    ///
+    /// Well-known attributes like `event.name`, `event.severity`, `event.source.*`,
+    /// `event.interaction.*` and `event.internal.rs.*` are always kept, even if that takes the
+    /// event past the limit. Fields past the limit are dropped and the number dropped is recorded
+    /// in `event.internal.rs.attrs_truncated`. By default there's no limit.
+    pub fn set_max_attrs_per_event(&mut self, max: usize) {
+        self.max_attrs_per_event = Some(max);
+    }
+    /// A chainable version of [set_max_attrs_per_event](Self::set_max_attrs_per_event).
+    pub fn with_max_attrs_per_event(mut self, max: usize) -> Self {
+        self.max_attrs_per_event = Some(max);
+        self
+    }
+
+    /// Count how many times each event name has been recorded, giving every event a running count
+    /// in `event.internal.rs.occurrence`, e.g. to query how many times something happened without
+    /// a metrics system.
+    ///
+    /// Counts are kept for at most `max_names` distinct names, about 50 bytes plus the name each.
+    /// When that's reached the least recently recorded name is forgotten, and starts counting from
+    /// 1 again if it's recorded later. Counts are per process and reset when the process restarts.
+    /// Off by default.
+    pub fn set_occurrence_counts(&mut self, max_names: usize) {
+        self.occurrence_counts = Some(max_names);
+    }
+    /// A chainable version of [set_occurrence_counts](Self::set_occurrence_counts).
+    pub fn with_occurrence_counts(mut self, max_names: usize) -> Self {
+        self.occurrence_counts = Some(max_names);
+        self
+    }
+
+    /// Only send events to modality when there's an error: events below `error` level are held
+    /// in a ring of the last `capacity` events instead, and an `error` event sends the held events
+    /// first, as the context leading up to it. Older events are dropped as newer ones arrive.
+    ///
+    /// This is for services that can't afford to send everything but need context around
+    /// failures. The held events can also be sent on demand with
+    /// `TracingModality::dump_flight_recorder`, and are dropped by `finish`.
+    ///
+    /// Only events are held. Spans, and their enter and exit events, are sent as they happen, so
+    /// held events keep their original `event.internal.rs.tick` and timestamp, but are recorded
+    /// on their timelines just before the error that sent them.
+    ///
+    /// Events are given their `event.internal.rs.timeline_seq` when they're traced, so events the
+    /// recorder discards leave gaps in it just like events lost on the way to modality. They're
+    /// counted in [`ShutdownReport::discarded`](crate::ShutdownReport::discarded) to tell the two
+    /// apart. Off by default.
+    pub fn set_flight_recorder(&mut self, capacity: usize) {
+        self.flight_recorder = Some(capacity);
+    }
+    /// A chainable version of [set_flight_recorder](Self::set_flight_recorder).
+    pub fn with_flight_recorder(mut self, capacity: usize) -> Self {
+        self.flight_recorder = Some(capacity);
+        self
+    }
+
+    /// Install a panic hook when initializing `TracingModality` that records panics with
+    /// [`record_panic`](crate::record_panic), then calls the previously installed hook.
+    ///
+    /// Has no effect when only the layer is initialized. Defaults to `false`.
+    pub fn set_panic_capture(&mut self, enabled: bool) {
+        self.panic_capture = enabled;
+    }
+    /// A chainable version of [set_panic_capture](Self::set_panic_capture).
+    pub fn with_panic_capture(mut self, enabled: bool) -> Self {
+        self.panic_capture = enabled;
+        self
+    }
+
+    /// Handle SIGINT and SIGTERM (Ctrl-C on other platforms) when initializing `TracingModality`
+    /// by flushing queued trace events, for up to 5 seconds, then exiting the process with the
+    /// status a shell reports for a process killed by that signal (130 or 143). This is for
+    /// programs that are stopped by a signal, e.g. in a container, and never get to call `finish`.
+    ///
+    /// This is intrusive, so it's off by default:
+    /// * Once the handlers are installed the signals no longer terminate the process by
+    ///   themselves, and the process exits after flushing without running destructors or the rest
+    ///   of `main`. Programs with their own graceful shutdown should call `finish` from it instead.
+    /// * Handlers installed through `tokio::signal` or `signal-hook` keep working alongside this,
+    ///   but only get until the flush finishes to act. Installing handlers with `sigaction`
+    ///   directly, e.g. with the `ctrlc` crate, replaces this one.
+    ///
+    /// `tracing_modality::TracingModality` watches for signals from a task on its runtime, which
+    /// needs IO enabled. The blocking version uses a thread of its own. Has no effect when only
+    /// the layer is initialized.
+    pub fn set_graceful_signal_handling(&mut self, enabled: bool) {
+        self.graceful_signal_handling = enabled;
+    }
+    /// A chainable version of [set_graceful_signal_handling](Self::set_graceful_signal_handling).
+    pub fn with_graceful_signal_handling(mut self, enabled: bool) -> Self {
+        self.graceful_signal_handling = enabled;
+        self
+    }
+
+    /// If initializing `TracingModality` fails, e.g. because modalityd isn't running, print trace
+    /// events to stderr instead of returning the error, so the same code runs with or without
+    /// modality during development. A warning with the error is printed once when this happens.
+    ///
+    /// Events are printed in the `tracing_subscriber::fmt` format, with their level, target and
+    /// fields, filtered by the [max level](Self::set_max_level). Everything modality specific,
+    /// like timelines and attribute handlers, is skipped, and `TracingModality::is_initialized`
+    /// stays `false`. Has no effect when only the layer is initialized. Defaults to `false`.
+    pub fn set_console_fallback(&mut self, enabled: bool) {
+        self.console_fallback = enabled;
+    }
+    /// A chainable version of [set_console_fallback](Self::set_console_fallback).
+    pub fn with_console_fallback(mut self, enabled: bool) -> Self {
+        self.console_fallback = enabled;
+        self
+    }
+
+    /// Give every event a process-wide unique ID, recorded as `event.internal.rs.event_id`, e.g. to
+    /// join modality data with an application's own records. Unlike the per-timeline sequence
+    /// number it's unique across all timelines.
+    ///
+    /// The ID of the last event recorded on the current thread is available from
+    /// [`last_event_id`](crate::last_event_id). Defaults to `false`.
+    ///
+    /// An event can name the event that caused it by recording that event's ID in a
+    /// `modality.cause.event_id` field, which becomes `event.interaction.cause_event_id`:
+    ///
+    /// ```rust,no_run
+    /// tracing::info!("command received");
+    /// let command = tracing_modality::last_event_id();
+    /// tracing::info!(modality.cause.event_id = command, "command completed");
+    /// ```
+    pub fn set_event_ids(&mut self, enabled: bool) {
+        self.event_ids = enabled;
+    }
+    /// A chainable version of [set_event_ids](Self::set_event_ids).
+    pub fn with_event_ids(mut self, enabled: bool) -> Self {
+        self.event_ids = enabled;
+        self
+    }
+
+    /// Provide the function used to generate span IDs, e.g. for reproducible span IDs in tests.
+    ///
+    /// The function must return a unique ID each time it's called for the life of the process,
+    /// IDs are used to associate span enter/exit/close with the span's definition and reusing an ID
+    /// while a span with the same ID is still open will mix up the two spans' events. By default a
+    /// global counter is used.
+    pub fn set_span_id_source<F>(&mut self, source: F)
+    where
+        F: Fn() -> NonZeroU64 + Send + Sync + 'static,
+    {
+        self.span_id_source = Some(Arc::new(source));
+    }
+    /// A chainable version of [set_span_id_source](Self::set_span_id_source).
+    pub fn with_span_id_source<F>(mut self, source: F) -> Self
+    where
+        F: Fn() -> NonZeroU64 + Send + Sync + 'static,
+    {
+        self.span_id_source = Some(Arc::new(source));
+        self
+    }
+
+    /// Only record events and spans at or above `max_level`, e.g. `LevelFilter::INFO` to drop
+    /// `debug` and `trace`. Defaults to `LevelFilter::TRACE`, recording everything.
+    ///
+    /// It's applied by a per-layer [`MaxLevelFilter`](crate::MaxLevelFilter), so other layers on
+    /// the same subscriber still see everything. `init` and `into_subscriber` apply the filter;
+    /// when adding the layer to a subscriber yourself, apply the one from the layer's
+    /// `max_level_filter`, otherwise every level is recorded. The filter also reports the level
+    /// as a max level hint, so when every layer filters `tracing` can skip disabled callsites
+    /// without evaluating their field values. To remove them from the binary entirely, also enable
+    /// one of `tracing`'s `max_level_*`/`release_max_level_*` features, see the crate docs.
+    ///
+    /// This is the only filter that runs before field values are computed. The field allowlist,
+    /// attribute handlers, and attribute cap all see events whose fields have already been
+    /// evaluated.
+    ///
+    /// It can be changed after initialization with
+    /// [`TracingModality::set_level_filter`](crate::TracingModality::set_level_filter).
+    pub fn set_max_level<L: Into<LevelFilter>>(&mut self, max_level: L) {
+        self.max_level = MaxLevel::new(max_level.into());
+    }
+    /// A chainable version of [set_max_level](Self::set_max_level).
+    pub fn with_max_level<L: Into<LevelFilter>>(mut self, max_level: L) -> Self {
+        self.max_level = MaxLevel::new(max_level.into());
+        self
+    }
+
+    /// Provide the function used to assign each message its tick, the logical time events are
+    /// ordered by, e.g. an incrementing counter to make ordering deterministic in tests.
+    ///
+    /// It's called on the producing thread as each message is sent. Ticks must not go backwards
+    /// for ordering to be meaningful. This only replaces the tick, wall clock timestamps are still
+    /// taken from the system clock. By default the time since the first message is used.
+    pub fn set_tick_fn<F>(&mut self, tick_fn: F)
+    where
+        F: Fn() -> Duration + Send + Sync + 'static,
+    {
+        self.tick_fn = Some(Arc::new(tick_fn));
+    }
+    /// A chainable version of [set_tick_fn](Self::set_tick_fn).
+    pub fn with_tick_fn<F>(mut self, tick_fn: F) -> Self
+    where
+        F: Fn() -> Duration + Send + Sync + 'static,
+    {
+        self.tick_fn = Some(Arc::new(tick_fn));
+        self
+    }
+
+    /// Name the clock the [tick function](Self::set_tick_fn) reads, e.g. `"ptp"`, for telling
+    /// apart timestamps from systems with different clock disciplines.
+    ///
+    /// Every event records its clock source as `event.internal.rs.clock_source`: this name, or
+    /// `"custom"` for an unnamed tick function. Without a tick function it's
+    /// `"system_monotonic"`, the tick and wall clock timestamp are both taken from the monotonic
+    /// clock, anchored to the system's realtime clock at the first message.
+    pub fn set_clock_source<S: Into<String>>(&mut self, name: S) {
+        self.clock_source = Some(name.into());
+    }
+    /// A chainable version of [set_clock_source](Self::set_clock_source).
+    pub fn with_clock_source<S: Into<String>>(mut self, name: S) -> Self {
+        self.clock_source = Some(name.into());
+        self
+    }
+
+    /// Provide the mapping from `tracing` levels to `event.severity` values.
+    ///
+    /// By default the lowercase `tracing` level names are used: `error`, `warn`, `info`, `debug`,
+    /// and `trace`. Use this to match a different severity vocabulary, e.g. `warning` for `WARN`.
+    /// An explicit `severity` field on an event always takes precedence.
+    pub fn set_severity_fn<F>(&mut self, severity_fn: F)
+    where
+        F: Fn(&Level) -> AttrVal + Send + Sync + 'static,
+    {
+        self.severity_fn = Some(Arc::new(severity_fn));
+    }
+    /// A chainable version of [set_severity_fn](Self::set_severity_fn).
+    pub fn with_severity_fn<F>(mut self, severity_fn: F) -> Self
+    where
+        F: Fn(&Level) -> AttrVal + Send + Sync + 'static,
+    {
+        self.severity_fn = Some(Arc::new(severity_fn));
+        self
+    }
+
+    /// Derive the [kind](crate::kind) of events and spans without a `modality.kind` field from
+    /// their metadata, e.g. the level or target. Returning `None` keeps the default kind, `event`
+    /// for events and `span:defined` for spans.
+    ///
+    /// ```rust
+    /// # use tracing_modality::Options;
+    /// let options = Options::new().with_kind_fn(|metadata| {
+    ///     (*metadata.level() == tracing::Level::ERROR).then(|| "error".to_string())
+    /// });
+    /// ```
+    pub fn set_kind_fn<F>(&mut self, kind_fn: F)
+    where
+        F: Fn(&Metadata<'static>) -> Option<String> + Send + Sync + 'static,
+    {
+        self.kind_fn = Some(Arc::new(kind_fn));
+    }
+    /// A chainable version of [set_kind_fn](Self::set_kind_fn).
+    pub fn with_kind_fn<F>(mut self, kind_fn: F) -> Self
+    where
+        F: Fn(&Metadata<'static>) -> Option<String> + Send + Sync + 'static,
+    {
+        self.kind_fn = Some(Arc::new(kind_fn));
+        self
+    }
+
+    /// Classify events and spans into logical components, recorded as `event.component`, e.g.
+    /// `"api"` for everything under `my_app::http`. Returning `None` records no component.
+    ///
+    /// It's given the callsite's metadata, so it can look at the target or module path. An
+    /// explicit `component` field always takes precedence.
+    ///
+    /// ```rust
+    /// # use tracing_modality::Options;
+    /// let options = Options::new().with_component_fn(|metadata| {
+    ///     match metadata.target() {
+    ///         t if t.starts_with("my_app::http") => Some("api".to_string()),
+    ///         t if t.starts_with("my_app::db") => Some("storage".to_string()),
+    ///         _ => None,
+    ///     }
+    /// });
+    /// ```
+    pub fn set_component_fn<F>(&mut self, component_fn: F)
+    where
+        F: Fn(&Metadata<'static>) -> Option<String> + Send + Sync + 'static,
+    {
+        self.component_fn = Some(Arc::new(component_fn));
+    }
+    /// A chainable version of [set_component_fn](Self::set_component_fn).
+    pub fn with_component_fn<F>(mut self, component_fn: F) -> Self
+    where
+        F: Fn(&Metadata<'static>) -> Option<String> + Send + Sync + 'static,
+    {
+        self.component_fn = Some(Arc::new(component_fn));
+        self
+    }
+
+    /// Provide a callback that is called on the producing thread whenever a span is created,
+    /// entered, exited, or closed, e.g. to keep a live count of open spans by name.
+    ///
+    /// The callback runs inline in the traced code, so it should be cheap and must not itself emit
+    /// trace events.
+    pub fn set_span_lifecycle_callback<F>(&mut self, callback: F)
+    where
+        F: Fn(SpanLifecycleEvent) + Send + Sync + 'static,
+    {
+        self.span_lifecycle_callback = Some(Arc::new(callback));
+    }
+    /// A chainable version of [set_span_lifecycle_callback](Self::set_span_lifecycle_callback).
+    pub fn with_span_lifecycle_callback<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(SpanLifecycleEvent) + Send + Sync + 'static,
+    {
+        self.span_lifecycle_callback = Some(Arc::new(callback));
+        self
+    }
+
+    /// Provide a callback that is called on the producing thread with every event's captured
+    /// fields, exactly as they were recorded and before they're converted to modality attributes.
+    ///
+    /// This is a debugging aid. Like the
+    /// [span lifecycle callback](Self::set_span_lifecycle_callback) it runs inline in the traced
+    /// code and must not itself emit trace events.
+    pub fn set_event_observer<F>(&mut self, observer: F)
+    where
+        F: Fn(&'static Metadata<'static>, &RecordMap) + Send + Sync + 'static,
+    {
+        self.event_observer = Some(Arc::new(observer));
+    }
+    /// A chainable version of [set_event_observer](Self::set_event_observer).
+    pub fn with_event_observer<F>(mut self, observer: F) -> Self
+    where
+        F: Fn(&'static Metadata<'static>, &RecordMap) + Send + Sync + 'static,
+    {
+        self.event_observer = Some(Arc::new(observer));
+        self
+    }
+
+    /// Provide ambient context to record on every event, e.g. a request ID or tenant kept in a
+    /// thread-local, instead of adding it to every log call.
+    ///
+    /// The provider is called on the producing thread for each event, so it can read
+    /// thread-locals, and its attributes are recorded like fields, as `event.<key>`, subject to the
+    /// field allowlist, redactions and approved keys but not attribute handlers. Fields on the
+    /// event itself take precedence. It runs inline for every recorded event, so keep it cheap;
+    /// it isn't called for events that are filtered out.
+    pub fn set_context_provider<F>(&mut self, provider: F)
+    where
+        F: Fn() -> Vec<(String, AttrVal)> + Send + Sync + 'static,
+    {
+        self.context_provider = Some(Arc::new(provider));
+    }
+    /// A chainable version of [set_context_provider](Self::set_context_provider).
+    pub fn with_context_provider<F>(mut self, provider: F) -> Self
+    where
+        F: Fn() -> Vec<(String, AttrVal)> + Send + Sync + 'static,
+    {
+        self.context_provider = Some(Arc::new(provider));
+        self
+    }
+
+    /// Provide a callback that is called when the ingest thread (or task) stops, with the
+    /// [reason](IngestExitReason) it stopped, e.g. to alert when trace data stops flowing.
+    ///
+    /// It's called from the ingest thread, also when that thread is panicking, so it must not
+    /// panic itself. With additional server addresses it's called once per address.
+    pub fn set_on_ingest_exit<F>(&mut self, callback: F)
+    where
+        F: Fn(IngestExitReason) + Send + Sync + 'static,
+    {
+        self.on_ingest_exit = Some(Arc::new(callback));
+    }
+    /// A chainable version of [set_on_ingest_exit](Self::set_on_ingest_exit).
+    pub fn with_on_ingest_exit<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(IngestExitReason) + Send + Sync + 'static,
+    {
+        self.on_ingest_exit = Some(Arc::new(callback));
+        self
+    }
+}
+
+impl Options {
+    /// Whether spans need their fields kept in their extensions for use after they're defined.
+    pub(crate) fn keep_span_fields(&self) -> bool {
+        self.span_close_snapshot
+            || self.fields_on_enter
+            || self.span_field_inheritance
+            || !self.ancestor_fields.is_empty()
+    }
+
+    /// Add the root timeline metadata that other options ask for, such as the run ID, environment,
+    /// and service ID. Called once when a layer is initialized.
+    pub(crate) fn add_init_metadata(&mut self) {
+        if self.run_id_metadata {
+            let run_id = self.run_id.get();
+            self.add_metadata("run_id", run_id.to_string());
+        }
+        if let Some(environment) = self.environment.clone() {
+            self.add_metadata("environment", environment);
+        }
+        if let Some(service_id) = self.service_id.clone() {
+            self.add_metadata("service_id", service_id);
+        }
+        if let Some(clock_id) = self.clock_id {
+            self.add_metadata(
+                "internal.rs.clock_id",
+                BigInt::new_attr_val(clock_id.into()),
+            );
+        }
+    }
+
+    /// The options for an additional server address, which doesn't have any further mirrors.
+    pub(crate) fn for_mirror(&self, addr: SocketAddr) -> Options {
+        let mut options = self.clone();
+        options.server_addr = addr;
+        options.additional_server_addrs.clear();
+        options
+    }
+
+    pub(crate) fn snapshot(&self) -> OptionsSnapshot {
+        let mut field_allowlist: Option<Vec<String>> = self
+            .field_allowlist
+            .as_ref()
+            .map(|a| a.iter().cloned().collect());
+        if let Some(allowlist) = &mut field_allowlist {
+            allowlist.sort();
+        }
+        let mut approved_keys: Option<Vec<String>> = self
+            .approved_keys
+            .as_ref()
+            .map(|k| k.iter().cloned().collect());
+        if let Some(keys) = &mut approved_keys {
+            keys.sort();
+        }
+
+        let mut field_units: Vec<(String, String)> = self
+            .field_units
+            .iter()
+            .map(|(field, unit)| (field.clone(), unit.clone()))
+            .collect();
+        field_units.sort();
+
+        OptionsSnapshot {
+            server_addr: self.server_addr,
+            additional_server_addrs: self.additional_server_addrs.clone(),
+            auth_configured: self.auth.is_some(),
+            run_id: self.run_id.get(),
+            environment: self.environment.clone(),
+            service_id: self.service_id.clone(),
+            clock_id: self.clock_id,
+            wall_clock_timestamps: self.wall_clock_timestamps,
+            timeline_churn_threshold: self.timeline_churn_threshold,
+            channel_capacity: self.channel_capacity,
+            metadata: self.metadata.clone(),
+            max_level: self.max_level.get(),
+            timeline_name_separator: self.timeline_name_separator.clone(),
+            unnamed_event_name: self.unnamed_event_name.clone(),
+            module_path_strip_prefix: self.module_path_strip_prefix.clone(),
+            span_close_snapshot: self.span_close_snapshot,
+            fields_on_enter: self.fields_on_enter,
+            span_field_inheritance: self.span_field_inheritance,
+            span_poll_count: self.span_poll_count,
+            poll_coalescing: self.poll_coalescing,
+            span_elapsed_on_events: self.span_elapsed_on_events,
+            ancestor_fields: self.ancestor_fields.clone(),
+            field_allowlist,
+            approved_keys,
+            strict_approved_keys: self.strict_approved_keys,
+            redactions: self.redactions.clone(),
+            field_units,
+            unit_suffixes: self.unit_suffixes,
+            attr_handlers: self.attr_handlers.iter().map(|h| h.key.clone()).collect(),
+            timeline_end_marker: self.timeline_end_marker,
+            heartbeat_interval: self.heartbeat_interval,
+            flush_diagnostics: self.flush_diagnostics,
+            layer_overhead_diagnostics: self.layer_overhead_diagnostics,
+            callsite_schema: self.callsite_schema,
+            field_type_tags: self.field_type_tags,
+            attr_vec_capacity: self.attr_vec_capacity,
+            max_attrs_per_event: self.max_attrs_per_event,
+            occurrence_counts: self.occurrence_counts,
+            flight_recorder: self.flight_recorder,
+            panic_capture: self.panic_capture,
+            graceful_signal_handling: self.graceful_signal_handling,
+            console_fallback: self.console_fallback,
+            event_ids: self.event_ids,
+            timeline_identifier: self.timeline_identifier.is_some(),
+            span_id_source: self.span_id_source.is_some(),
+            tick_fn: self.tick_fn.is_some(),
+            clock_source: self.clock_source.clone(),
+            severity_fn: self.severity_fn.is_some(),
+            kind_fn: self.kind_fn.is_some(),
+            component_fn: self.component_fn.is_some(),
+            span_lifecycle_callback: self.span_lifecycle_callback.is_some(),
+            event_observer: self.event_observer.is_some(),
+            context_provider: self.context_provider.is_some(),
+            on_ingest_exit: self.on_ingest_exit.is_some(),
+        }
+    }
+}
+
+/// The max level, shared between the layer and the `TracingModality` that owns it so it can be
+/// changed at runtime.
+#[derive(Clone)]
+pub(crate) struct MaxLevel(Arc<AtomicU8>);
+
+impl MaxLevel {
+    /// Every level filter, from most to least restrictive, indexed by the stored value.
+    const LEVELS: [LevelFilter; 6] = [
+        LevelFilter::OFF,
+        LevelFilter::ERROR,
+        LevelFilter::WARN,
+        LevelFilter::INFO,
+        LevelFilter::DEBUG,
+        LevelFilter::TRACE,
+    ];
+
+    fn index(level: LevelFilter) -> u8 {
+        Self::LEVELS
+            .iter()
+            .position(|l| *l == level)
+            .expect("every level filter is listed") as u8
+    }
+
+    pub(crate) fn new(level: LevelFilter) -> Self {
+        MaxLevel(Arc::new(AtomicU8::new(Self::index(level))))
+    }
+
+    pub(crate) fn get(&self) -> LevelFilter {
+        Self::LEVELS[usize::from(self.0.load(Ordering::Relaxed))]
+    }
+
+    /// Change the level, and have `tracing` ask the layer again about callsites it has already
+    /// cached an answer for.
+    pub(crate) fn set(&self, level: LevelFilter) {
+        self.0.store(Self::index(level), Ordering::Relaxed);
+        tracing_core::callsite::rebuild_interest_cache();
+    }
+}
+
+/// A read-only view of the options a tracer was initialized with, e.g. for reporting the telemetry
+/// configuration from a diagnostics endpoint.
+///
+/// The auth token itself is never included. Callbacks are only reported as being set or not.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct OptionsSnapshot {
+    pub server_addr: SocketAddr,
+    pub additional_server_addrs: Vec<SocketAddr>,
+    /// Whether an auth token was provided.
+    pub auth_configured: bool,
+    /// The current `run_id`, reflecting any rotations.
+    pub run_id: Uuid,
+    pub environment: Option<String>,
+    pub service_id: Option<String>,
+    pub clock_id: Option<u64>,
+    pub wall_clock_timestamps: bool,
+    pub timeline_churn_threshold: Option<f64>,
+    pub channel_capacity: Option<usize>,
+    pub metadata: Vec<(String, AttrVal)>,
+    pub max_level: LevelFilter,
+    pub timeline_name_separator: Option<String>,
+    pub unnamed_event_name: Option<String>,
+    pub module_path_strip_prefix: Option<String>,
+    pub span_close_snapshot: bool,
+    pub fields_on_enter: bool,
+    pub span_field_inheritance: bool,
+    pub span_poll_count: bool,
+    pub poll_coalescing: Option<Duration>,
+    pub span_elapsed_on_events: bool,
+    pub ancestor_fields: Vec<String>,
+    pub field_allowlist: Option<Vec<String>>,
+    pub approved_keys: Option<Vec<String>>,
+    pub strict_approved_keys: bool,
+    pub redactions: Vec<String>,
+    pub field_units: Vec<(String, String)>,
+    pub unit_suffixes: bool,
+    /// The field names that have an attribute handler.
+    pub attr_handlers: Vec<String>,
+    pub timeline_end_marker: bool,
+    pub heartbeat_interval: Option<Duration>,
+    pub flush_diagnostics: bool,
+    pub layer_overhead_diagnostics: bool,
+    pub callsite_schema: bool,
+    pub field_type_tags: bool,
+    pub attr_vec_capacity: usize,
+    pub max_attrs_per_event: Option<usize>,
+    pub occurrence_counts: Option<usize>,
+    pub flight_recorder: Option<usize>,
+    pub panic_capture: bool,
+    pub graceful_signal_handling: bool,
+    pub console_fallback: bool,
+    pub event_ids: bool,
+    pub timeline_identifier: bool,
+    pub span_id_source: bool,
+    pub tick_fn: bool,
+    pub clock_source: Option<String>,
+    pub severity_fn: bool,
+    pub kind_fn: bool,
+    pub component_fn: bool,
+    pub span_lifecycle_callback: bool,
+    pub event_observer: bool,
+    pub context_provider: bool,
+    pub on_ingest_exit: bool,
 }
 
 impl Default for Options {
@@ -107,3 +1511,127 @@ impl Default for Options {
         Options::new()
     }
 }
+
+/// Prefix a timeline metadata key with `timeline.` if it isn't already.
+pub(crate) fn timeline_metadata_key(key: &str) -> String {
+    if key.starts_with("timeline.") {
+        key.to_string()
+    } else {
+        format!("timeline.{}", key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata<'a>(options: &'a Options, key: &str) -> Option<&'a AttrVal> {
+        options
+            .metadata
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v)
+    }
+
+    #[test]
+    fn run_id_metadata_can_be_turned_off() {
+        let run_id = Uuid::new_v4();
+        let mut options = Options::new().with_run_id(run_id);
+        options.add_init_metadata();
+        assert_eq!(
+            metadata(&options, "timeline.run_id"),
+            Some(&AttrVal::from(run_id.to_string()))
+        );
+
+        let mut options = Options::new()
+            .with_run_id(run_id)
+            .with_run_id_metadata(false);
+        options.add_init_metadata();
+        assert!(metadata(&options, "timeline.run_id").is_none());
+    }
+
+    #[test]
+    fn service_id_recorded_on_timelines() {
+        let mut options = Options::new();
+        options.add_init_metadata();
+        assert!(metadata(&options, "timeline.service_id").is_none());
+
+        let mut options = Options::new().with_service_id("billing-api");
+        options.add_init_metadata();
+        assert_eq!(
+            metadata(&options, "timeline.service_id"),
+            Some(&AttrVal::from("billing-api"))
+        );
+    }
+
+    #[test]
+    fn environment_vars_in_precedence_order() {
+        let vars = |set: &'static [(&'static str, &'static str)]| {
+            move |var: &str| {
+                set.iter()
+                    .find(|(name, _)| *name == var)
+                    .map(|(_, value)| value.to_string())
+            }
+        };
+
+        assert_eq!(
+            Options::environment_from(vars(&[
+                ("RUST_ENV", "dev"),
+                ("ENVIRONMENT", "staging"),
+                ("MODALITY_ENV", "prod"),
+            ])),
+            Some("prod".to_string())
+        );
+        assert_eq!(
+            Options::environment_from(vars(&[("RUST_ENV", "dev"), ("ENVIRONMENT", "staging")])),
+            Some("staging".to_string())
+        );
+        // empty values are skipped
+        assert_eq!(
+            Options::environment_from(vars(&[("RUST_ENV", "dev"), ("MODALITY_ENV", "")])),
+            Some("dev".to_string())
+        );
+        assert_eq!(Options::environment_from(vars(&[])), None);
+    }
+
+    #[test]
+    fn snapshot_reflects_options() {
+        let options = Options::new()
+            .with_auth("abcd")
+            .with_field_allowlist(["b", "a"])
+            .with_severity_fn(|_| "info".into())
+            .with_max_level(Level::DEBUG);
+        let snapshot = options.snapshot();
+        assert!(snapshot.auth_configured);
+        assert_eq!(
+            snapshot.field_allowlist,
+            Some(vec!["a".to_string(), "b".to_string()])
+        );
+        assert!(snapshot.severity_fn);
+        assert!(!snapshot.kind_fn);
+        assert_eq!(snapshot.max_level, LevelFilter::DEBUG);
+
+        // the run ID is read when the snapshot is taken, so it reflects rotations
+        let rotated = options.run_id.rotate();
+        assert_eq!(options.snapshot().run_id, rotated);
+        assert_ne!(snapshot.run_id, rotated);
+    }
+
+    #[test]
+    fn attr_handlers_added_or_replaced() {
+        let handler_keys = |options: Options| options.snapshot().attr_handlers;
+        let defaults = handler_keys(Options::new());
+        assert!(!defaults.is_empty());
+
+        let added = handler_keys(Options::new().with_attr_handlers([
+            attr_handlers::bool_as_int("a"),
+            attr_handlers::bool_as_int("b"),
+        ]));
+        assert_eq!(added[..defaults.len()], defaults);
+        assert_eq!(added[defaults.len()..], ["a", "b"]);
+
+        let replaced =
+            handler_keys(Options::new().with_only_attr_handlers([attr_handlers::bool_as_int("a")]));
+        assert_eq!(replaced, ["a"]);
+    }
+}
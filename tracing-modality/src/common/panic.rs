@@ -0,0 +1,76 @@
+use std::panic::{self, PanicHookInfo};
+
+/// Record a panic as an `error` level `panic` event, with `event.panic.message` and
+/// `event.panic.location`.
+///
+/// This is meant to be called from a custom panic hook, or see
+/// [`Options::set_panic_capture`](crate::Options::set_panic_capture) to have a hook installed at
+/// init. The event goes through the current default tracer like any other.
+pub fn record_panic(info: &PanicHookInfo<'_>) {
+    let payload = info.payload();
+    let message = payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("Box<dyn Any>");
+    let location = info
+        .location()
+        .map(|l| format!("{}:{}:{}", l.file(), l.line(), l.column()));
+
+    tracing::error!(
+        name = "panic",
+        panic.message = message,
+        panic.location = location.as_deref(),
+    );
+}
+
+/// Install a panic hook that records panics with [`record_panic`] before calling the previously
+/// installed hook.
+pub(crate) fn install_panic_hook() {
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        record_panic(info);
+        previous(info);
+    }));
+}
+
+#[cfg(all(test, feature = "blocking"))]
+mod tests {
+    use super::*;
+    use crate::{
+        ingest,
+        layer::{tests::capture, TracingValue},
+        Options,
+    };
+
+    #[test]
+    fn panic_recorded_as_event() {
+        let messages = capture(Options::new(), || {
+            // the hook is process wide, put the default one back straight away
+            let previous = panic::take_hook();
+            panic::set_hook(Box::new(record_panic));
+            let _ = panic::catch_unwind(|| panic!("boom {}", 42));
+            panic::set_hook(previous);
+        });
+
+        let events: Vec<_> = messages
+            .into_iter()
+            .filter_map(|wrapped| match wrapped.message {
+                ingest::Message::Event {
+                    metadata, records, ..
+                } => Some((*metadata.level(), records)),
+                _ => None,
+            })
+            .collect();
+
+        let (level, records) = &events[0];
+        assert_eq!(*level, tracing::Level::ERROR);
+        assert!(matches!(records.get("name"), Some(TracingValue::String(n)) if n == "panic"));
+        assert!(
+            matches!(records.get("panic.message"), Some(TracingValue::String(m)) if m == "boom 42")
+        );
+        assert!(
+            matches!(records.get("panic.location"), Some(TracingValue::String(l)) if l.starts_with(file!()))
+        );
+    }
+}
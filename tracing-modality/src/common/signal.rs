@@ -0,0 +1,127 @@
+use crate::ingest::FlushRequests;
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::sync::oneshot;
+
+/// How long to wait for queued events to be flushed after a signal before exiting anyway.
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The signals that trigger a flush, registered with the tokio runtime they're created in.
+pub(crate) struct Signals {
+    #[cfg(unix)]
+    interrupt: tokio::signal::unix::Signal,
+    #[cfg(unix)]
+    terminate: tokio::signal::unix::Signal,
+}
+
+impl Signals {
+    /// Start listening for SIGINT and SIGTERM, or Ctrl-C on other platforms. Must be called from
+    /// within a tokio runtime with IO enabled.
+    pub(crate) fn register() -> io::Result<Self> {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+            Ok(Signals {
+                interrupt: signal(SignalKind::interrupt())?,
+                terminate: signal(SignalKind::terminate())?,
+            })
+        }
+        #[cfg(not(unix))]
+        Ok(Signals {})
+    }
+
+    /// Wait for a signal, returning the exit status a shell reports for a process it killed.
+    async fn recv(&mut self) -> i32 {
+        #[cfg(unix)]
+        {
+            tokio::select! {
+                _ = self.interrupt.recv() => 130,
+                _ = self.terminate.recv() => 143,
+            }
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = tokio::signal::ctrl_c().await;
+            130
+        }
+    }
+
+    /// Wait for a signal, flush each ingest in turn, then exit the process.
+    ///
+    /// Registering the handlers replaced the signals' default action, so exiting is up to us.
+    pub(crate) async fn flush_then_exit(
+        mut self,
+        flush_requests: Vec<FlushRequests>,
+        shutting_down: Arc<AtomicBool>,
+    ) {
+        let status = self.recv().await;
+        shutting_down.store(true, Ordering::Relaxed);
+
+        let flush = async {
+            // in order, so mirrors get everything the primary ingest forwards while flushing
+            for requests in flush_requests {
+                let (reply, flushed) = oneshot::channel();
+                if requests.send(reply).is_ok() {
+                    let _ = flushed.await;
+                }
+            }
+        };
+        let _ = tokio::time::timeout(FLUSH_TIMEOUT, flush).await;
+
+        std::process::exit(status);
+    }
+}
+
+/// Flush and exit on a signal, from a task on the current runtime.
+#[cfg(feature = "async")]
+pub(crate) fn install_signal_task(
+    flush_requests: Vec<FlushRequests>,
+    shutting_down: Arc<AtomicBool>,
+) {
+    match Signals::register() {
+        Ok(signals) => {
+            tokio::spawn(signals.flush_then_exit(flush_requests, shutting_down));
+        }
+        Err(e) => warn_not_installed(e),
+    }
+}
+
+/// Flush and exit on a signal, from a dedicated thread, since the blocking tracer can't assume
+/// there's a runtime to spawn a task on.
+#[cfg(feature = "blocking")]
+pub(crate) fn install_signal_thread(
+    flush_requests: Vec<FlushRequests>,
+    shutting_down: Arc<AtomicBool>,
+) {
+    let registered = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .and_then(|rt| {
+            let signals = {
+                let _guard = rt.enter();
+                Signals::register()?
+            };
+            Ok((rt, signals))
+        });
+    match registered {
+        Ok((rt, signals)) => {
+            std::thread::spawn(move || {
+                rt.block_on(signals.flush_then_exit(flush_requests, shutting_down))
+            });
+        }
+        Err(e) => warn_not_installed(e),
+    }
+}
+
+fn warn_not_installed(e: io::Error) {
+    eprintln!(
+        "warning: tracing modality couldn't install its signal handlers, trace events won't be \
+         flushed when the process is terminated: {e}"
+    );
+}
@@ -0,0 +1,134 @@
+use crate::ingest::{current_timeline, TimelineId};
+use crate::layer::{LocalSpanId, SpanTimeline};
+
+use auxon_sdk::api::Uuid;
+use std::{cell::Cell, fmt, num::NonZeroU64, str::FromStr};
+use thiserror::Error;
+use tracing_subscriber::registry::{LookupSpan, Registry};
+
+thread_local! {
+    static IMPORTED_SPAN: Cell<Option<SpanContext>> = const { Cell::new(None) };
+}
+
+/// Identifies a span in another process, so that a span in this process can be recorded as its
+/// continuation.
+///
+/// To pass a span context between processes use its `Display` format,
+/// `<timeline uuid>:<span id>`, e.g. `1b5d0d5c-6c1e-4a59-9b1e-3f2a8f3c7d10:42`, and parse it on the
+/// other side with `FromStr`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SpanContext {
+    /// The timeline the span was current on.
+    pub timeline_id: TimelineId,
+    /// The span's ID, as recorded in `event.internal.rs.span_id`.
+    pub span_id: NonZeroU64,
+}
+
+impl fmt::Display for SpanContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.timeline_id.get_raw(), self.span_id)
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("Invalid span context, expected `<timeline uuid>:<span id>`")]
+pub struct ParseSpanContextError;
+
+impl FromStr for SpanContext {
+    type Err = ParseSpanContextError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (timeline_id, span_id) = s.rsplit_once(':').ok_or(ParseSpanContextError)?;
+        let timeline_id = Uuid::from_str(timeline_id).map_err(|_| ParseSpanContextError)?;
+        let span_id = span_id.parse().map_err(|_| ParseSpanContextError)?;
+
+        Ok(SpanContext {
+            timeline_id: timeline_id.into(),
+            span_id,
+        })
+    }
+}
+
+/// Get the context of the current span, to be passed to another process and imported there with
+/// [`import_span`]. Its timeline is the one the span was created on, which with a
+/// [timeline identifier](crate::Options::set_timeline_identifier) or
+/// [`timeline_scope`](crate::timeline_scope) needn't be the current thread's.
+///
+/// Returns `None` if there is no current span or the current subscriber isn't built on a
+/// `tracing_subscriber` `Registry`.
+pub fn current_span_context() -> Option<SpanContext> {
+    tracing::Span::current()
+        .with_subscriber(|(id, dispatch)| {
+            let registry = dispatch.downcast_ref::<Registry>()?;
+            let span = registry.span(id)?;
+            let extensions = span.extensions();
+            let local_id = *extensions.get::<LocalSpanId>()?;
+            let timeline_id = extensions
+                .get::<SpanTimeline>()
+                .map_or_else(current_timeline, |timeline| timeline.0);
+
+            Some(SpanContext {
+                timeline_id,
+                span_id: local_id.0,
+            })
+        })
+        .flatten()
+}
+
+/// Run `f`, marking the first span it creates on this thread as the continuation of a span from
+/// another process.
+///
+/// That span's defined event is recorded with `event.interaction.remote_timeline_id` and
+/// `event.interaction.remote_span_id`, establishing the causal link between the two processes. If
+/// `f` creates no span, e.g. because it was filtered out, the import is discarded when `f` returns
+/// rather than being applied to some later span.
+///
+/// ```rust,no_run
+/// # let context: tracing_modality::SpanContext = unimplemented!();
+/// let span = tracing_modality::import_span(context, || tracing::info_span!("handle request"));
+/// ```
+pub fn import_span<T>(context: SpanContext, f: impl FnOnce() -> T) -> T {
+    struct Discard;
+    impl Drop for Discard {
+        fn drop(&mut self) {
+            IMPORTED_SPAN.with(|imported| imported.set(None));
+        }
+    }
+
+    IMPORTED_SPAN.with(|imported| imported.set(Some(context)));
+    let _discard = Discard;
+    f()
+}
+
+/// Take the span context imported on this thread, if any.
+pub(crate) fn take_imported_span() -> Option<SpanContext> {
+    IMPORTED_SPAN.with(|imported| imported.take())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_from_str_round_trip() {
+        let context = SpanContext {
+            timeline_id: Uuid::new_v4().into(),
+            span_id: NonZeroU64::new(42).unwrap(),
+        };
+        assert_eq!(context.to_string().parse::<SpanContext>().unwrap(), context);
+
+        for invalid in ["", "42", "not-a-uuid:42", &format!("{}:0", Uuid::new_v4())] {
+            assert!(invalid.parse::<SpanContext>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn unused_import_is_discarded() {
+        let context = SpanContext {
+            timeline_id: Uuid::new_v4().into(),
+            span_id: NonZeroU64::new(1).unwrap(),
+        };
+        import_span(context, || ());
+        assert_eq!(take_imported_span(), None);
+    }
+}
@@ -0,0 +1,111 @@
+use std::{future::Future, time::Instant};
+use tracing::{info_span, Instrument, Span};
+
+/// Time a closure: run it in an `info` span named `name`, then record an `info` event named
+/// `name` in that span with how long it took, in nanoseconds, as `event.duration_ns`.
+///
+/// This is a shorthand for the common "how long did this take" case, without `#[instrument]` or
+/// setting up a span by hand.
+///
+/// ```rust,no_run
+/// # fn parse_config() -> u32 { 0 }
+/// let config = tracing_modality::timed("parse_config", || parse_config());
+/// ```
+pub fn timed<T, F: FnOnce() -> T>(name: &str, f: F) -> T {
+    let span = info_span!("timed", name);
+    let start = Instant::now();
+    let output = span.in_scope(f);
+    record_duration(&span, name, start);
+    output
+}
+
+/// Time a future, like [`timed`]: the future runs in an `info` span named `name`, and once it
+/// completes an `info` event named `name` records how long that took as `event.duration_ns`.
+///
+/// The duration runs from the first poll to completion, so it includes time spent waiting on
+/// other tasks.
+///
+/// ```rust,no_run
+/// # async fn fetch() -> u32 { 0 }
+/// # async fn run() {
+/// let body = tracing_modality::timed_async("fetch", fetch()).await;
+/// # }
+/// ```
+pub async fn timed_async<F: Future>(name: &str, future: F) -> F::Output {
+    let span = info_span!("timed", name);
+    let start = Instant::now();
+    let output = future.instrument(span.clone()).await;
+    record_duration(&span, name, start);
+    output
+}
+
+fn record_duration(span: &Span, name: &str, start: Instant) {
+    let duration_ns = u64::try_from(start.elapsed().as_nanos()).unwrap_or(u64::MAX);
+    span.in_scope(|| tracing::info!(name, duration_ns));
+}
+
+#[cfg(all(test, feature = "blocking"))]
+mod tests {
+    use super::*;
+    use crate::{
+        ingest::{Message, WrappedMessage},
+        layer::{tests::capture, TracingValue},
+        Options,
+    };
+    use std::time::Duration;
+
+    /// The name of the span and the event `timed` sent, and the recorded duration.
+    fn timed_messages(messages: Vec<WrappedMessage>) -> (Vec<String>, u64) {
+        let (mut names, mut duration_ns) = (Vec::new(), 0);
+        for wrapped in messages {
+            let mut records = match wrapped.message {
+                Message::NewSpan { records, .. } | Message::Event { records, .. } => records,
+                _ => continue,
+            };
+            if let Some(TracingValue::String(name)) = records.remove("name") {
+                names.push(name);
+            }
+            if let Some(TracingValue::U64(ns)) = records.remove("duration_ns") {
+                duration_ns = ns;
+            }
+        }
+        (names, duration_ns)
+    }
+
+    #[test]
+    fn timed_records_duration() {
+        let mut output = 0;
+        let messages = capture(Options::new(), || {
+            output = timed("parse_config", || {
+                std::thread::sleep(Duration::from_millis(2));
+                7
+            });
+        });
+        assert_eq!(output, 7);
+
+        let (names, duration_ns) = timed_messages(messages);
+        assert_eq!(names, ["parse_config", "parse_config"]);
+        assert!(duration_ns >= 2_000_000, "{duration_ns}");
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn timed_async_records_duration() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        let mut output = 0;
+        let messages = capture(Options::new(), || {
+            output = rt.block_on(timed_async("fetch", async {
+                tokio::time::sleep(Duration::from_millis(2)).await;
+                7
+            }));
+        });
+        assert_eq!(output, 7);
+
+        let (names, duration_ns) = timed_messages(messages);
+        assert_eq!(names, ["fetch", "fetch"]);
+        assert!(duration_ns >= 2_000_000, "{duration_ns}");
+    }
+}
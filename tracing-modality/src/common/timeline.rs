@@ -0,0 +1,287 @@
+use crate::ingest::TimelineId;
+
+use std::{
+    cell::RefCell,
+    hash::{Hash, Hasher},
+    marker::PhantomData,
+    sync::{Arc, RwLock},
+    thread,
+};
+use tracing_core::Metadata;
+use uuid::Uuid;
+
+/// A logical timeline, as chosen by a
+/// [timeline identifier](crate::Options::set_timeline_identifier).
+///
+/// Timelines are identified by `user_id`. The modality timeline ID is derived from the run's
+/// `run_id` and the `user_id`, so the same `user_id` always maps to the same timeline within one
+/// run, no matter which thread it's used from.
+///
+/// A timeline can name its parent with [`with_parent`](Self::with_parent), e.g. a worker task's
+/// timeline naming the supervisor that spawned it. The parent is recorded in the `timeline.parent`
+/// metadata as the parent's modality timeline ID, derived from the same `run_id` and the parent's
+/// `user_id`, so modality can show the timeline spawn tree.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UserTimelineInfo {
+    pub(crate) name: String,
+    pub(crate) user_id: u64,
+    pub(crate) parent_user_id: Option<u64>,
+}
+
+impl UserTimelineInfo {
+    pub fn new<S: Into<String>>(name: S, user_id: u64) -> Self {
+        UserTimelineInfo {
+            name: name.into(),
+            user_id,
+            parent_user_id: None,
+        }
+    }
+
+    /// Record the timeline with `parent_user_id` as this timeline's parent.
+    pub fn with_parent(mut self, parent_user_id: u64) -> Self {
+        self.parent_user_id = Some(parent_user_id);
+        self
+    }
+
+    /// The timeline for the current thread, named like the default thread timelines.
+    pub fn current_thread() -> Self {
+        let cur = thread::current();
+        let name = cur
+            .name()
+            .map(Into::into)
+            .unwrap_or_else(|| format!("thread-{:?}", cur.id()));
+
+        UserTimelineInfo::new(name, hash_id(&cur.id()))
+    }
+
+    /// A timeline per `tracing` target, for use with
+    /// [`Options::set_target_timelines`](crate::Options::set_target_timelines). Falls back to the
+    /// current thread's timeline when there's no metadata.
+    ///
+    /// The `user_id` is the 64-bit FNV-1a hash of the target, which doesn't change between runs,
+    /// builds, or Rust releases.
+    pub fn for_target(metadata: Option<&Metadata<'_>>) -> Self {
+        match metadata {
+            Some(metadata) => UserTimelineInfo::new(metadata.target(), name_id(metadata.target())),
+            None => UserTimelineInfo::current_thread(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn user_id(&self) -> u64 {
+        self.user_id
+    }
+
+    pub fn parent_user_id(&self) -> Option<u64> {
+        self.parent_user_id
+    }
+
+    /// The modality timeline ID this timeline has in the run identified by `run_id`.
+    pub(crate) fn timeline_id(&self, run_id: &Uuid) -> TimelineId {
+        derive_timeline_id(run_id, self.user_id)
+    }
+}
+
+thread_local! {
+    /// The timelines of this thread's active [`timeline_scope`]s, innermost last.
+    static TIMELINE_SCOPES: RefCell<Vec<UserTimelineInfo>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Record this thread's events and spans on the timeline `info` until the returned guard is
+/// dropped, e.g. to attribute a block of work to a named logical timeline without a custom
+/// [timeline identifier](crate::Options::set_timeline_identifier).
+///
+/// While a scope is active it takes precedence over the timeline identifier, custom or default.
+/// Scopes nest: the innermost one wins, and dropping it returns to the one around it. Dropping an
+/// outer guard also ends any scopes nested inside it.
+///
+/// ```rust,no_run
+/// use tracing_modality::{timeline_scope, UserTimelineInfo};
+///
+/// let _scope = timeline_scope(UserTimelineInfo::new("housekeeping", 1));
+/// tracing::info!("recorded on the housekeeping timeline");
+/// ```
+pub fn timeline_scope(info: UserTimelineInfo) -> TimelineScope {
+    let depth = TIMELINE_SCOPES.with(|scopes| {
+        let mut scopes = scopes.borrow_mut();
+        scopes.push(info);
+        scopes.len() - 1
+    });
+    TimelineScope {
+        depth,
+        _not_send: PhantomData,
+    }
+}
+
+/// Guard returned by [`timeline_scope`], ends the scope when dropped. It must be dropped on the
+/// thread that created it.
+#[must_use = "the timeline scope ends when the guard is dropped"]
+pub struct TimelineScope {
+    depth: usize,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Drop for TimelineScope {
+    fn drop(&mut self) {
+        TIMELINE_SCOPES.with(|scopes| scopes.borrow_mut().truncate(self.depth));
+    }
+}
+
+/// The timeline of the innermost active [`timeline_scope`] on this thread.
+pub(crate) fn scoped_timeline() -> Option<UserTimelineInfo> {
+    TIMELINE_SCOPES.with(|scopes| scopes.borrow().last().cloned())
+}
+
+/// The current run's ID, shared by the layer and the ingest thread so that it can be rotated.
+#[derive(Clone, Debug)]
+pub(crate) struct RunId(Arc<RwLock<Uuid>>);
+
+impl RunId {
+    pub(crate) fn new() -> Self {
+        RunId(Arc::new(RwLock::new(Uuid::new_v4())))
+    }
+
+    pub(crate) fn from_uuid(run_id: Uuid) -> Self {
+        RunId(Arc::new(RwLock::new(run_id)))
+    }
+
+    pub(crate) fn get(&self) -> Uuid {
+        *self.0.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Replace the run ID with a fresh one, returning it.
+    pub(crate) fn rotate(&self) -> Uuid {
+        let run_id = Uuid::new_v4();
+        *self.0.write().unwrap_or_else(|e| e.into_inner()) = run_id;
+        run_id
+    }
+}
+
+/// Derive the modality timeline ID for `user_id` in the run identified by `run_id`.
+pub(crate) fn derive_timeline_id(run_id: &Uuid, user_id: u64) -> TimelineId {
+    Uuid::new_v5(run_id, &user_id.to_be_bytes()).into()
+}
+
+/// Turn anything hashable into a `user_id`, for values that only mean something within one run of
+/// the program, such as thread IDs.
+fn hash_id<T: Hash + ?Sized>(value: &T) -> u64 {
+    let mut hasher = Fnv1a::default();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Turn a name into a `user_id` that stays the same across runs, builds, and Rust releases, the
+/// 64-bit FNV-1a hash of its UTF-8 bytes. Unlike [`hash_id`] this doesn't go through `Hash`, whose
+/// output for `str` isn't guaranteed to stay the same.
+fn name_id(name: &str) -> u64 {
+    let mut hasher = Fnv1a::default();
+    hasher.write(name.as_bytes());
+    hasher.finish()
+}
+
+/// The 64-bit FNV-1a hash, a fixed algorithm unlike the std `DefaultHasher`.
+struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Fnv1a(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for Fnv1a {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn name_ids_are_fnv1a() {
+        assert_eq!(name_id(""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(name_id("a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(name_id("foobar"), 0x8594_4171_f739_67e8);
+    }
+
+    #[test]
+    fn derive_timeline_id_per_run() {
+        let (run, other_run) = (Uuid::new_v4(), Uuid::new_v4());
+
+        assert_eq!(derive_timeline_id(&run, 1), derive_timeline_id(&run, 1));
+        assert_ne!(derive_timeline_id(&run, 1), derive_timeline_id(&run, 2));
+        assert_ne!(
+            derive_timeline_id(&run, 1),
+            derive_timeline_id(&other_run, 1)
+        );
+        assert_eq!(
+            UserTimelineInfo::new("a", 1).timeline_id(&run),
+            derive_timeline_id(&run, 1)
+        );
+    }
+
+    #[test]
+    fn timeline_scopes_nest() {
+        let (a, b, c) = (
+            UserTimelineInfo::new("a", 1),
+            UserTimelineInfo::new("b", 2),
+            UserTimelineInfo::new("c", 3),
+        );
+        assert_eq!(scoped_timeline(), None);
+
+        let outer = timeline_scope(a.clone());
+        {
+            let _inner = timeline_scope(b.clone());
+            assert_eq!(scoped_timeline(), Some(b));
+        }
+        assert_eq!(scoped_timeline(), Some(a));
+
+        // dropping the outer guard first also ends the scope nested inside it
+        let inner = timeline_scope(c);
+        drop(outer);
+        assert_eq!(scoped_timeline(), None);
+        drop(inner);
+        assert_eq!(scoped_timeline(), None);
+    }
+
+    #[test]
+    fn for_target_names_timelines_by_target() {
+        struct Callsite;
+        impl tracing_core::Callsite for Callsite {
+            fn set_interest(&self, _interest: tracing_core::Interest) {}
+            fn metadata(&self) -> &Metadata<'_> {
+                &METADATA
+            }
+        }
+        static CALLSITE: Callsite = Callsite;
+        static METADATA: Metadata<'static> = tracing_core::metadata!(
+            name: "query",
+            target: "app::db",
+            level: tracing_core::Level::INFO,
+            fields: &[],
+            callsite: &CALLSITE,
+            kind: tracing_core::metadata::Kind::SPAN,
+        );
+
+        let info = UserTimelineInfo::for_target(Some(&METADATA));
+        assert_eq!(info.name(), "app::db");
+        assert_eq!(info, UserTimelineInfo::for_target(Some(&METADATA)));
+        assert_ne!(info.user_id(), UserTimelineInfo::current_thread().user_id());
+
+        assert_eq!(
+            UserTimelineInfo::for_target(None),
+            UserTimelineInfo::current_thread()
+        );
+    }
+}
@@ -4,6 +4,8 @@
 #![allow(clippy::needless_doctest_main)]
 #![warn(clippy::all)]
 
+mod macros;
+
 #[cfg(feature = "async")]
 mod r#async;
 #[cfg(feature = "blocking")]
@@ -14,9 +16,17 @@ mod common;
 #[cfg(feature = "async")]
 pub use common::ingest::ModalityIngestTaskHandle;
 
-pub use common::ingest::TimelineId;
-pub use common::options::Options;
+pub use common::ingest::{ConnectError, IngestExitReason, ShutdownReport, TimelineId};
+pub use common::layer::{
+    last_event_id, MaxLevelFilter, RecordMap, SpanLifecycleEvent, SpanLifecycleKind, TracingValue,
+};
+pub use common::options::{Options, OptionsSnapshot};
 pub use common::*;
 
+#[doc(hidden)]
+pub use common::ingest::coerce_timestamp;
+#[doc(hidden)]
+pub use tracing as __tracing;
+
 #[cfg(feature = "async")]
 pub use r#async::{ModalityLayer, TracingModality};
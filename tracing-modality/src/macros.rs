@@ -0,0 +1,89 @@
+/// Record a metric, an `info` event with `event.name` set to `name`, `event.value` set to
+/// `value` and the kind [`kind::METRIC`](crate::kind::METRIC).
+///
+/// Any further fields are recorded as usual.
+///
+/// ```rust,no_run
+/// tracing_modality::metric_event!("queue_depth", 42u64);
+/// tracing_modality::metric_event!("latency_ms", 3.5, endpoint = "/status");
+/// ```
+#[macro_export]
+macro_rules! metric_event {
+    ($name:expr, $value:expr $(,)?) => {
+        $crate::__tracing::info!(
+            modality.kind = $crate::kind::METRIC,
+            name = $name,
+            value = $value,
+        )
+    };
+    ($name:expr, $value:expr, $($fields:tt)+) => {
+        $crate::__tracing::info!(
+            modality.kind = $crate::kind::METRIC,
+            name = $name,
+            value = $value,
+            $($fields)+
+        )
+    };
+}
+
+/// Record the sending side of an interaction, an `info` event with `event.interaction.nonce` set
+/// to `nonce`.
+///
+/// Send the nonce and this thread's [`timeline_id`](crate::timeline_id) along with the data, for
+/// the receiver to record with
+/// [`interaction_receive!`](crate::interaction_receive). Any further fields are recorded as usual.
+///
+/// ```rust,no_run
+/// let nonce: i64 = 7;
+/// tracing_modality::interaction_send!(nonce, "sending job");
+/// ```
+#[macro_export]
+macro_rules! interaction_send {
+    ($nonce:expr $(,)?) => {
+        $crate::__tracing::info!(interaction.nonce = $nonce)
+    };
+    ($nonce:expr, $($fields:tt)+) => {
+        $crate::__tracing::info!(interaction.nonce = $nonce, $($fields)+)
+    };
+}
+
+/// Record the receiving side of an interaction, an `info` event with
+/// `event.interaction.remote_timeline_id` and `event.interaction.remote_nonce` set to the sender's
+/// timeline ID and the nonce it recorded with
+/// [`interaction_send!`](crate::interaction_send).
+///
+/// Any further fields are recorded as usual.
+///
+/// ```rust,no_run
+/// # let (remote_timeline_id, nonce) = (tracing_modality::timeline_id(), 7i64);
+/// tracing_modality::interaction_receive!(remote_timeline_id, nonce, "received job");
+/// ```
+#[macro_export]
+macro_rules! interaction_receive {
+    ($remote_timeline_id:expr, $remote_nonce:expr $(,)?) => {
+        $crate::__tracing::info!(
+            interaction.remote_timeline_id = %$remote_timeline_id,
+            interaction.remote_nonce = $remote_nonce,
+        )
+    };
+    ($remote_timeline_id:expr, $remote_nonce:expr, $($fields:tt)+) => {
+        $crate::__tracing::info!(
+            interaction.remote_timeline_id = %$remote_timeline_id,
+            interaction.remote_nonce = $remote_nonce,
+            $($fields)+
+        )
+    };
+}
+
+/// Record an audit event, an `info` event with the kind [`kind::AUDIT`](crate::kind::AUDIT),
+/// taking the same arguments as `tracing::info!`.
+///
+/// ```rust,no_run
+/// tracing_modality::audit_event!(user = "alice", "password changed");
+/// ```
+#[macro_export]
+macro_rules! audit_event {
+    ($($args:tt)+) => {
+        $crate::__tracing::info!(modality.kind = $crate::kind::AUDIT, $($args)+)
+    };
+}
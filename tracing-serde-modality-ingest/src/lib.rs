@@ -3,7 +3,7 @@ pub mod options;
 use anyhow::Context;
 use auxon_sdk::{
     api::{AttrVal, BigInt, LogicalTime, Nanoseconds, Uuid},
-    ingest_client::{BoundTimelineState, IngestClient, IngestError as SdkIngestError},
+    ingest_client::{BoundTimelineState, IngestClient},
     ingest_protocol::InternedAttrKey,
 };
 use once_cell::sync::Lazy;
@@ -21,26 +21,14 @@ use tracing_serde_structured::{
 use tracing_serde_wire::{Packet, TWOther, TracingWire};
 
 pub use auxon_sdk::api::TimelineId;
+pub use tracing_modality::ConnectError;
+use tracing_modality::coerce_timestamp;
 
 pub use options::Options;
 
 // spans can be defined on any thread and then sent to another and entered/etc, track globally
 static SPAN_NAMES: Lazy<RwLock<HashMap<u64, String>>> = Lazy::new(|| RwLock::new(HashMap::new()));
 
-#[derive(Debug, Error)]
-pub enum ConnectError {
-    /// No auth was provided
-    #[error("Authentication required")]
-    AuthRequired,
-    /// Auth was provided, but was not accepted by modality
-    #[error("Authenticating with the provided auth failed")]
-    AuthFailed(SdkIngestError),
-    /// Errors that it is assumed there is no way to handle without human intervention, meant for
-    /// consumers to just print and carry on or panic.
-    #[error(transparent)]
-    UnexpectedFailure(#[from] anyhow::Error),
-}
-
 #[derive(Debug, Error)]
 pub enum IngestError {
     /// Errors that it is assumed there is no way to handle without human intervention, meant for
@@ -54,6 +42,7 @@ pub struct TracingModality {
     event_keys: HashMap<String, InternedAttrKey>,
     timeline_keys: HashMap<String, InternedAttrKey>,
     timeline_id: TimelineId,
+    device_id_bytes: bool,
 }
 
 impl TracingModality {
@@ -73,7 +62,7 @@ impl TracingModality {
         let client = unauth_client
             .authenticate(auth_key)
             .await
-            .map_err(ConnectError::AuthFailed)?;
+            .map_err(ConnectError::from_auth_error)?;
 
         let timeline_id = TimelineId::allocate();
 
@@ -87,6 +76,7 @@ impl TracingModality {
             event_keys: HashMap::new(),
             timeline_keys: HashMap::new(),
             timeline_id,
+            device_id_bytes: options.device_id_bytes,
         };
 
         for (key, value) in options.metadata {
@@ -313,56 +303,41 @@ impl TracingModality {
                     span_names.deref_mut().insert(new.id.get(), name);
                 }
             }
-            TracingWire::Other(two) => {
-                match two {
-                    TWOther::MessageDiscarded => {
-                        let mut packed_attrs = Vec::new();
+            TracingWire::Other(two) => match two {
+                TWOther::MessageDiscarded => {
+                    let mut packed_attrs = Vec::new();
 
-                        packed_attrs.push((
-                            self.get_or_create_event_attr_key("event.internal.rs.kind".to_string())
-                                .await?,
-                            AttrVal::String("message_discarded".to_string().into()),
-                        ));
-                        self.client
-                            .event(pkt.tick.into(), packed_attrs)
-                            .await
-                            .context("send packed event")?;
-                    }
-                    TWOther::DeviceInfo {
+                    packed_attrs.push((
+                        self.get_or_create_event_attr_key("event.internal.rs.kind".to_string())
+                            .await?,
+                        AttrVal::String("message_discarded".to_string().into()),
+                    ));
+                    self.client
+                        .event(pkt.tick.into(), packed_attrs)
+                        .await
+                        .context("send packed event")?;
+                }
+                TWOther::DeviceInfo {
+                    clock_id,
+                    ticks_per_sec,
+                    device_id,
+                } => {
+                    let mut packed_attrs = Vec::new();
+                    for (key, value) in device_info_metadata(
                         clock_id,
                         ticks_per_sec,
-                        device_id,
-                    } => {
-                        let mut packed_attrs = Vec::new();
-                        packed_attrs.push((
-                            self.get_or_create_timeline_attr_key(
-                                "timeline.internal.rs.clock_id".to_string(),
-                            )
-                            .await?,
-                            AttrVal::Integer(clock_id.into()),
-                        ));
-                        packed_attrs.push((
-                            self.get_or_create_timeline_attr_key(
-                                "timeline.ticks_per_sec".to_string(),
-                            )
-                            .await?,
-                            AttrVal::Integer(ticks_per_sec.into()),
-                        ));
-                        packed_attrs.push((
-                            self.get_or_create_timeline_attr_key(
-                                "timeline.internal.rs.device_id".to_string(),
-                            )
-                            .await?,
-                            // TODO: this includes array syntax in the ID
-                            AttrVal::String(format!("{:x?}", device_id).into()),
-                        ));
-                        self.client
-                            .timeline_metadata(packed_attrs)
-                            .await
-                            .context("send packed timeline metadata")?;
+                        &device_id,
+                        self.device_id_bytes,
+                    ) {
+                        packed_attrs
+                            .push((self.get_or_create_timeline_attr_key(key).await?, value));
                     }
+                    self.client
+                        .timeline_metadata(packed_attrs)
+                        .await
+                        .context("send packed timeline metadata")?;
                 }
-            }
+            },
             _ => (),
         }
 
@@ -511,14 +486,7 @@ impl TracingModality {
             .remove(&"interaction.remote_timestamp".into())
             .and_then(tracing_value_to_attr_val);
         if let Some(attrval) = remote_timestamp {
-            let remote_timestamp = match attrval {
-                AttrVal::Integer(i) if i >= 0 => AttrVal::Timestamp(Nanoseconds::from(i as u64)),
-                AttrVal::BigInt(i) if *i >= 0 && *i <= u64::MAX as i128 => {
-                    AttrVal::Timestamp(Nanoseconds::from(*i as u64))
-                }
-                AttrVal::Timestamp(t) => AttrVal::Timestamp(t),
-                x => x,
-            };
+            let remote_timestamp = coerce_timestamp(attrval);
 
             packed_attrs.push((
                 self.get_or_create_event_attr_key("event.interaction.remote_timestamp".into())
@@ -532,14 +500,7 @@ impl TracingModality {
             .remove(&"timestamp".into())
             .and_then(tracing_value_to_attr_val);
         if let Some(attrval) = local_timestamp {
-            let remote_timestamp = match attrval {
-                AttrVal::Integer(i) if i >= 0 => AttrVal::Timestamp(Nanoseconds::from(i as u64)),
-                AttrVal::BigInt(i) if *i >= 0 && *i <= u64::MAX as i128 => {
-                    AttrVal::Timestamp(Nanoseconds::from(*i as u64))
-                }
-                AttrVal::Timestamp(t) => AttrVal::Timestamp(t),
-                x => x,
-            };
+            let remote_timestamp = coerce_timestamp(attrval);
 
             packed_attrs.push((
                 self.get_or_create_event_attr_key("event.timestamp".into())
@@ -581,6 +542,49 @@ impl TracingModality {
     }
 }
 
+/// Timeline metadata for a `DeviceInfo` message.
+///
+/// The device ID is recorded as a plain lowercase hex string, and optionally as one integer
+/// attribute per byte.
+fn device_info_metadata(
+    clock_id: u32,
+    ticks_per_sec: u32,
+    device_id: &[u8],
+    device_id_bytes: bool,
+) -> Vec<(String, AttrVal)> {
+    let device_id_hex = hex::encode(device_id);
+
+    let mut metadata = vec![
+        (
+            "timeline.internal.rs.clock_id".to_string(),
+            AttrVal::Integer(i64::from(clock_id)),
+        ),
+        (
+            "timeline.ticks_per_sec".to_string(),
+            AttrVal::Integer(i64::from(ticks_per_sec)),
+        ),
+        (
+            "timeline.internal.rs.device_id".to_string(),
+            AttrVal::String(device_id_hex.clone().into()),
+        ),
+        (
+            "timeline.device_id".to_string(),
+            AttrVal::String(device_id_hex.into()),
+        ),
+    ];
+
+    if device_id_bytes {
+        metadata.extend(device_id.iter().enumerate().map(|(i, byte)| {
+            (
+                format!("timeline.device_id.{}", i),
+                AttrVal::Integer(i64::from(*byte)),
+            )
+        }));
+    }
+
+    metadata
+}
+
 // `SerializeValue` is `#[nonexhaustive]`, returns `None` if they add a type we don't handle and
 // fail to serialize it as a stringified json value
 fn tracing_value_to_attr_val<'a, V: Borrow<SerializeValue<'a>>>(value: V) -> Option<AttrVal> {
@@ -605,3 +609,52 @@ fn tracing_value_to_attr_val<'a, V: Borrow<SerializeValue<'a>>>(value: V) -> Opt
         }
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn device_info_hex_and_integers() {
+        let device_id = [
+            0x00, 0x01, 0x0a, 0xff, 0x10, 0x20, 0x30, 0x40, 0x50, 0x60, 0x70, 0x80, 0x90, 0xa0,
+            0xb0, 0xc0,
+        ];
+        let metadata = device_info_metadata(u32::MAX, 1_000_000, &device_id, false);
+
+        assert_eq!(
+            metadata,
+            vec![
+                (
+                    "timeline.internal.rs.clock_id".to_string(),
+                    AttrVal::Integer(4_294_967_295)
+                ),
+                (
+                    "timeline.ticks_per_sec".to_string(),
+                    AttrVal::Integer(1_000_000)
+                ),
+                (
+                    "timeline.internal.rs.device_id".to_string(),
+                    AttrVal::String("00010aff102030405060708090a0b0c0".into())
+                ),
+                (
+                    "timeline.device_id".to_string(),
+                    AttrVal::String("00010aff102030405060708090a0b0c0".into())
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn device_info_bytes() {
+        let metadata = device_info_metadata(0, 0, &[0x00, 0xff], true);
+
+        assert_eq!(
+            &metadata[4..],
+            &[
+                ("timeline.device_id.0".to_string(), AttrVal::Integer(0)),
+                ("timeline.device_id.1".to_string(), AttrVal::Integer(255)),
+            ]
+        );
+    }
+}
@@ -7,6 +7,7 @@ pub struct Options {
     pub(crate) auth: Option<Vec<u8>>,
     pub(crate) metadata: Vec<(String, AttrVal)>,
     pub(crate) server_addr: SocketAddr,
+    pub(crate) device_id_bytes: bool,
 }
 
 impl Options {
@@ -17,6 +18,7 @@ impl Options {
             auth,
             metadata: Vec::new(),
             server_addr,
+            device_id_bytes: false,
         }
     }
 
@@ -100,6 +102,19 @@ impl Options {
         self.server_addr = addr;
         self
     }
+
+    /// Record a device's ID as one `timeline.device_id.N` integer attribute per byte, in addition to
+    /// the `timeline.device_id` hex string.
+    ///
+    /// Defaults to `false`.
+    pub fn set_device_id_bytes(&mut self, enabled: bool) {
+        self.device_id_bytes = enabled;
+    }
+    /// A chainable version of [set_device_id_bytes](Self::set_device_id_bytes).
+    pub fn with_device_id_bytes(mut self, enabled: bool) -> Self {
+        self.device_id_bytes = enabled;
+        self
+    }
 }
 
 impl Default for Options {